@@ -0,0 +1,401 @@
+//! `objects/info/alternates` による共有 (`alternates` サブコマンド)
+//!
+//! 複数のリポジトリに共通するlooseオブジェクトを共有オブジェクトストア
+//! (Gitのobjectsディレクトリと同じ `xx/yyyy...` 配置) に移し、各リポジトリの
+//! `objects/info/alternates` にストアを追加したうえでローカルのコピーを削除する。
+//! ハードリンクと違い、ファイルシステムをまたいでも `git gc` 後も共有が保たれる。
+
+use std::collections::HashSet;
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::fs::PermissionsExt;
+
+use crate::hardlink::is_same_filesystem;
+use crate::scanner::GitObjectInfo;
+
+/// Gitが辿るalternatesの入れ子の上限
+pub const MAX_ALTERNATE_DEPTH: usize = 5;
+
+/// alternatesの連鎖の問題
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AlternatesError {
+    /// 連鎖が自分自身に戻る
+    Cycle(PathBuf),
+    /// 入れ子がGitの上限を超える
+    TooDeep(PathBuf),
+    /// alternatesファイルが読めない
+    Io(PathBuf, String),
+}
+
+impl fmt::Display for AlternatesError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AlternatesError::Cycle(path) => write!(f, "alternates cycle at {}", path.display()),
+            AlternatesError::TooDeep(path) => write!(
+                f,
+                "alternates nested deeper than {} at {}",
+                MAX_ALTERNATE_DEPTH,
+                path.display()
+            ),
+            AlternatesError::Io(path, e) => write!(f, "{}: {}", path.display(), e),
+        }
+    }
+}
+
+/// objectsディレクトリの `info/alternates` のパス
+pub fn alternates_file(objects_dir: &Path) -> PathBuf {
+    objects_dir.join("info").join("alternates")
+}
+
+/// `info/alternates` に書かれたobjectsディレクトリを読む
+///
+/// 空行と `#` で始まる行は無視し、相対パスはobjectsディレクトリからの相対として解決する。
+///
+/// Returns:
+///     記載順のパス (ファイルが無ければ空)
+pub fn read_alternates(objects_dir: &Path) -> io::Result<Vec<PathBuf>> {
+    let content = match fs::read_to_string(alternates_file(objects_dir)) {
+        Ok(content) => content,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e),
+    };
+    Ok(content
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| objects_dir.join(line))
+        .collect())
+}
+
+/// objectsディレクトリから辿れるalternatesを全て返す (自分自身は含まない)
+///
+/// 存在しないディレクトリは辿らない (Gitも無視する)。
+///
+/// Returns:
+///     辿れたobjectsディレクトリ (正規化済み)。循環や深すぎる入れ子はErr
+pub fn alternate_closure(objects_dir: &Path) -> Result<Vec<PathBuf>, AlternatesError> {
+    let mut found = Vec::new();
+    let mut stack = Vec::new();
+    let start = canonical(objects_dir);
+    visit(&start, 0, &mut stack, &mut found)?;
+    Ok(found)
+}
+
+fn visit(
+    dir: &Path,
+    depth: usize,
+    stack: &mut Vec<PathBuf>,
+    found: &mut Vec<PathBuf>,
+) -> Result<(), AlternatesError> {
+    if stack.iter().any(|d| d == dir) {
+        return Err(AlternatesError::Cycle(dir.to_path_buf()));
+    }
+    if depth > MAX_ALTERNATE_DEPTH {
+        return Err(AlternatesError::TooDeep(dir.to_path_buf()));
+    }
+    if depth > 0 && !found.iter().any(|d| d == dir) {
+        found.push(dir.to_path_buf());
+    }
+
+    let alternates = read_alternates(dir)
+        .map_err(|e| AlternatesError::Io(alternates_file(dir), e.to_string()))?;
+    stack.push(dir.to_path_buf());
+    for alternate in alternates.iter().filter(|a| a.is_dir()) {
+        visit(&canonical(alternate), depth + 1, stack, found)?;
+    }
+    stack.pop();
+    Ok(())
+}
+
+/// 比較用に正規化したパス (存在しなければそのまま)
+fn canonical(path: &Path) -> PathBuf {
+    fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf())
+}
+
+/// 共有オブジェクトストア
+#[derive(Debug, Clone)]
+pub struct ObjectStore {
+    objects_dir: PathBuf,
+}
+
+impl ObjectStore {
+    /// 指定ディレクトリをストアとして使う (無ければ作成し、絶対パスに正規化する)
+    pub fn open(objects_dir: &Path) -> io::Result<Self> {
+        fs::create_dir_all(objects_dir)?;
+        Ok(ObjectStore {
+            objects_dir: fs::canonicalize(objects_dir)?,
+        })
+    }
+
+    /// ストアのobjectsディレクトリ
+    pub fn objects_dir(&self) -> &Path {
+        &self.objects_dir
+    }
+
+    /// オブジェクトに対応するストア内のパス
+    pub fn entry_path(&self, hash: &str) -> PathBuf {
+        self.objects_dir.join(&hash[..2]).join(&hash[2..])
+    }
+
+    /// ストアにオブジェクトがあるか
+    pub fn contains(&self, hash: &str) -> bool {
+        self.entry_path(hash).is_file()
+    }
+
+    /// リポジトリのalternatesにストアを追加しても安全か調べる
+    ///
+    /// ストア自身がリポジトリのobjectsディレクトリ配下にある場合、ストアの
+    /// alternatesが循環している・リポジトリに戻る場合、入れ子がGitの上限を
+    /// 超える場合はErr。
+    ///
+    /// Args:
+    ///     objects_dir: リポジトリのobjectsディレクトリ
+    pub fn check_link(&self, objects_dir: &Path) -> Result<(), AlternatesError> {
+        let repo = canonical(objects_dir);
+        if self.objects_dir.starts_with(&repo) {
+            return Err(AlternatesError::Cycle(self.objects_dir.clone()));
+        }
+        let closure = alternate_closure(&self.objects_dir)?;
+        if closure.contains(&repo) {
+            return Err(AlternatesError::Cycle(repo));
+        }
+        // リポジトリから見たストアとその先のalternatesの段数
+        let mut depth = 0;
+        let mut level = vec![self.objects_dir.clone()];
+        while !level.is_empty() {
+            depth += 1;
+            level = level
+                .iter()
+                .flat_map(|d| read_alternates(d).unwrap_or_default())
+                .filter(|d| d.is_dir())
+                .map(|d| canonical(&d))
+                .collect();
+        }
+        if depth > MAX_ALTERNATE_DEPTH {
+            return Err(AlternatesError::TooDeep(self.objects_dir.clone()));
+        }
+        Ok(())
+    }
+
+    /// リポジトリのalternatesにストアが記載済みか
+    pub fn is_linked(&self, objects_dir: &Path) -> bool {
+        read_alternates(objects_dir)
+            .unwrap_or_default()
+            .iter()
+            .any(|d| canonical(d) == self.objects_dir)
+    }
+
+    /// リポジトリのalternatesにストアを追記する
+    ///
+    /// 既存の内容は保ったまま、一時ファイルに書いてからrenameで置き換える。
+    ///
+    /// Returns:
+    ///     追記したらtrue、記載済みならfalse
+    pub fn link(&self, objects_dir: &Path) -> io::Result<bool> {
+        if self.is_linked(objects_dir) {
+            return Ok(false);
+        }
+        let path = alternates_file(objects_dir);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let mut content = match fs::read_to_string(&path) {
+            Ok(content) => content,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        if !content.is_empty() && !content.ends_with('\n') {
+            content.push('\n');
+        }
+        content.push_str(&self.objects_dir.to_string_lossy());
+        content.push('\n');
+
+        let temp = path.with_extension("git-share-obj.tmp");
+        let result = (|| {
+            let mut file = File::create(&temp)?;
+            file.write_all(content.as_bytes())?;
+            file.sync_all()?;
+            fs::rename(&temp, &path)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result.map(|()| true)
+    }
+
+    /// オブジェクトをストアに入れる
+    ///
+    /// 同じファイルシステムならハードリンク、異なればコピー (一時ファイル + rename) する。
+    /// 既にストアにある場合は何もしない。
+    ///
+    /// Returns:
+    ///     新しく入れたらtrue
+    pub fn insert(&self, object: &GitObjectInfo) -> io::Result<bool> {
+        let entry = self.entry_path(&object.hash);
+        if entry.is_file() {
+            return Ok(false);
+        }
+        let Some(parent) = entry.parent() else {
+            return Ok(false);
+        };
+        fs::create_dir_all(parent)?;
+
+        if is_same_filesystem(&object.path, parent).unwrap_or(false) {
+            return match fs::hard_link(&object.path, &entry) {
+                Ok(()) => Ok(true),
+                Err(e) if e.kind() == io::ErrorKind::AlreadyExists => Ok(false),
+                Err(e) => Err(e),
+            };
+        }
+
+        let temp = entry.with_extension("git-share-obj.tmp");
+        let result = (|| {
+            fs::copy(&object.path, &temp)?;
+            #[cfg(unix)]
+            fs::set_permissions(&temp, fs::Permissions::from_mode(0o444))?;
+            File::open(&temp)?.sync_all()?;
+            fs::rename(&temp, &entry)
+        })();
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result.map(|()| true)
+    }
+}
+
+/// オブジェクトが属するobjectsディレクトリ (`objects/xx/yyyy` の `objects`)
+pub fn objects_dir_of(object: &GitObjectInfo) -> Option<&Path> {
+    object.path.parent()?.parent()
+}
+
+/// 2つ以上のリポジトリにあるか
+pub fn is_shared(files: &[GitObjectInfo]) -> bool {
+    let dirs: HashSet<_> = files.iter().filter_map(objects_dir_of).collect();
+    dirs.len() > 1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::tests::write_loose_object;
+    use crate::scanner::ObjectFormat;
+    use tempfile::TempDir;
+
+    fn write_alternates(objects_dir: &Path, lines: &[&Path]) {
+        fs::create_dir_all(objects_dir.join("info")).unwrap();
+        let content: String = lines.iter().map(|l| format!("{}\n", l.display())).collect();
+        fs::write(alternates_file(objects_dir), content).unwrap();
+    }
+
+    #[test]
+    fn test_read_alternates_resolves_relative_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        let objects = temp_dir.path().join("repo/objects");
+        fs::create_dir_all(objects.join("info")).unwrap();
+        fs::write(
+            alternates_file(&objects),
+            "# comment\n\n../../shared\n/abs/objects\n",
+        )
+        .unwrap();
+
+        let alternates = read_alternates(&objects).unwrap();
+        assert_eq!(
+            alternates,
+            vec![objects.join("../../shared"), PathBuf::from("/abs/objects")]
+        );
+        assert!(read_alternates(&temp_dir.path().join("none"))
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn test_check_link_rejects_cycle() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo/objects");
+        fs::create_dir_all(&repo).unwrap();
+        let store = ObjectStore::open(&temp_dir.path().join("store")).unwrap();
+        assert!(store.check_link(&repo).is_ok());
+
+        // ストアがリポジトリを参照していると repo -> store -> repo になる
+        write_alternates(store.objects_dir(), &[&repo]);
+        assert!(matches!(
+            store.check_link(&repo),
+            Err(AlternatesError::Cycle(_))
+        ));
+
+        // ストア自身の循環
+        write_alternates(store.objects_dir(), &[store.objects_dir()]);
+        assert!(matches!(
+            alternate_closure(store.objects_dir()),
+            Err(AlternatesError::Cycle(_))
+        ));
+
+        // リポジトリ配下のストア
+        let inner = ObjectStore::open(&repo.join("shared")).unwrap();
+        assert!(matches!(
+            inner.check_link(&repo),
+            Err(AlternatesError::Cycle(_))
+        ));
+    }
+
+    #[test]
+    fn test_check_link_rejects_deep_chain() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo/objects");
+        fs::create_dir_all(&repo).unwrap();
+        let dirs: Vec<_> = (0..=MAX_ALTERNATE_DEPTH)
+            .map(|i| {
+                let dir = temp_dir.path().join(format!("alt{}", i));
+                fs::create_dir_all(&dir).unwrap();
+                dir
+            })
+            .collect();
+        for pair in dirs.windows(2) {
+            write_alternates(&pair[0], &[&pair[1]]);
+        }
+        let store = ObjectStore::open(&dirs[0]).unwrap();
+        assert!(matches!(
+            store.check_link(&repo),
+            Err(AlternatesError::TooDeep(_))
+        ));
+
+        let store = ObjectStore::open(&dirs[1]).unwrap();
+        assert!(store.check_link(&repo).is_ok());
+    }
+
+    #[test]
+    fn test_link_and_insert() {
+        let temp_dir = TempDir::new().unwrap();
+        let (path, hash) = write_loose_object(
+            &temp_dir.path().join("repo"),
+            ObjectFormat::Sha1,
+            b"hello\n",
+        );
+        let object = GitObjectInfo::from_path(&path).unwrap();
+        let objects_dir = objects_dir_of(&object).unwrap().to_path_buf();
+        write_alternates(&objects_dir, &[Path::new("/existing/objects")]);
+
+        let store = ObjectStore::open(&temp_dir.path().join("store")).unwrap();
+        assert!(store.link(&objects_dir).unwrap());
+        assert!(!store.link(&objects_dir).unwrap());
+        assert_eq!(
+            read_alternates(&objects_dir).unwrap(),
+            vec![
+                PathBuf::from("/existing/objects"),
+                store.objects_dir().to_path_buf()
+            ]
+        );
+
+        assert!(!store.contains(&hash));
+        assert!(store.insert(&object).unwrap());
+        assert!(!store.insert(&object).unwrap());
+        assert_eq!(
+            fs::read(store.entry_path(&hash)).unwrap(),
+            fs::read(&path).unwrap()
+        );
+    }
+}
@@ -1,15 +1,45 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
 use std::path::{Path, PathBuf};
+use std::time::Instant;
 
-use crate::cli::Args;
-use crate::fsck::run_git_fsck;
-use crate::hardlink::{replace_with_hardlink, ReplaceResult};
+use serde_json::{json, Value};
+
+use crate::alternates::{is_shared, objects_dir_of, ObjectStore};
+use crate::cache::ScanCache;
+use crate::cli::{Args, Command, PoolCommand};
+use crate::fsck::{run_fsck, run_object_check, run_pack_check, FsckMode, FsckResult};
+use crate::hardlink::{
+    break_hardlink, is_same_content, is_same_inode, leftover_backups, link_count, link_max,
+    replace_set_with_links, replace_with_link_verified, LinkMode, LinkStrategy, ReplaceResult,
+    UnshareResult,
+};
 use crate::i18n::{format_size, msg, Msg};
-use crate::lock::{try_lock_repo, RepoLock};
+use crate::journal::{latest_journal, read_journal, restore_entry, Journal, JournalEntry};
+use crate::lock::{
+    inspect_lock, lock_all_or_nothing, lock_file_path, lock_repo_with_timeout,
+    lock_tree_with_timeout, remove_lock_file, LockHolder, LockState, RepoLock,
+};
+use crate::owner::{apply_owner_policy, normalize_mode, OwnerPolicy, Ownership};
+use crate::pack::{
+    find_duplicate_packs, scan_repository_packs, verify_pack_before_replace, PackGroup, PackInfo,
+};
+use crate::pool::ObjectPool;
+use crate::recover::{find_leftovers, recover_leftover, Leftover, LeftoverKind, RecoverResult};
+use crate::report::{fsck_event, lock_event, path_value, replace_event, unshare_event, Reporter};
+use crate::repository::{
+    dedup_repositories, discover_repositories_parallel, resolve_repository, GitRepository,
+};
+use crate::savings::{FreedSpace, InodeUsage};
+use crate::say;
 use crate::scanner::{
-    find_duplicates, find_git_repositories_with_progress, group_by_device, scan_git_objects_with_progress,
-    GitObjectInfo,
+    find_duplicates, group_by_device, group_by_hash, scan_repositories_parallel,
+    scan_repository_objects, split_by_link_limit, DuplicateGroup, GitObjectInfo,
 };
+use crate::signal::{self, interrupted, EXIT_INTERRUPTED};
+
+/// `--fail-on-busy` でロックできないリポジトリがあったときの終了コード
+const EXIT_LOCK_FAILED: i32 = 5;
 
 /// 処理統計
 struct Stats {
@@ -17,8 +47,22 @@ struct Stats {
     replaced: usize,
     already_linked: usize,
     cross_filesystem: usize,
+    content_mismatch: usize,
+    /// reflink非対応でスキップした数 (`--link-mode reflink`)
+    reflink_unsupported: usize,
+    /// リンク数の上限のため置換せず新しいsourceにした数
+    new_sources: usize,
+    /// 所有者・モードが異なるため共有しなかった数 (`--owner-policy`)
+    owner_mismatch: usize,
+    /// モードをそろえたsourceの数 (`--owner-policy normalize`)
+    normalized: usize,
+    /// プールに登録したオブジェクト数 (`--pool`)
+    pool_added: usize,
     errors: usize,
-    total_savings: u64,
+    /// 置換前に見積もった削減量 (論理サイズ)
+    estimated_savings: u64,
+    /// 実際に解放された容量 (ブロック単位)
+    freed: FreedSpace,
 }
 
 impl Stats {
@@ -28,102 +72,292 @@ impl Stats {
             replaced: 0,
             already_linked: 0,
             cross_filesystem: 0,
+            content_mismatch: 0,
+            reflink_unsupported: 0,
+            new_sources: 0,
+            owner_mismatch: 0,
+            normalized: 0,
+            pool_added: 0,
             errors: 0,
-            total_savings: 0,
+            estimated_savings: 0,
+            freed: FreedSpace::new(),
         }
     }
 }
 
 pub fn run(args: Args) -> i32 {
+    // 設定できなければシグナルの既定の動作 (即時終了) のまま
+    if let Err(e) = signal::install() {
+        eprintln!("{}: {}", msg(Msg::SignalHandlerFailed), e);
+    }
+    let mut reporter = Reporter::new(args.format);
+    let code = run_command(&args, &mut reporter);
+    reporter.finish(code);
+    code
+}
+
+/// サブコマンドを実行する
+///
+/// Returns:
+///     終了コード
+fn run_command(args: &Args, reporter: &mut Reporter) -> i32 {
+    match &args.command {
+        Some(Command::Restore { journal }) => run_restore(args, journal.as_deref(), reporter),
+        Some(Command::Unshare { repo }) => run_unshare(args, repo, reporter),
+        Some(Command::Alternates { store, paths }) => run_alternates(args, store, paths, reporter),
+        Some(Command::Recover { paths }) => run_recover(args, paths, reporter),
+        Some(Command::Locks { clean, paths }) => run_locks(args, paths, *clean, reporter),
+        Some(Command::Pool {
+            command: PoolCommand::Gc,
+        }) => run_pool_gc(args, reporter),
+        None => run_share(args, reporter),
+    }
+}
+
+/// 重複オブジェクトをハードリンクで共有する (サブコマンド無しの既定動作)
+fn run_share(args: &Args, reporter: &mut Reporter) -> i32 {
     if !validate_paths(&args.paths) {
         return 1;
     }
-
-    let repos = collect_repositories(&args.paths, args.verbose);
-    let (processing_repos, _locks) = if args.no_lock {
-        if args.verbose {
-            println!("{}", msg(Msg::LockSkipped));
+    let pool = if args.pool {
+        match open_pool(args) {
+            Some(pool) => Some(pool),
+            None => return 1,
         }
-        (repos.clone(), Vec::new())
     } else {
-        acquire_repo_locks(&repos, args.verbose)
+        None
+    };
+
+    let Some(_tree_locks) = lock_trees(&args.paths, args, reporter) else {
+        return EXIT_LOCK_FAILED;
     };
+    let repos = collect_repositories(&args.paths, args.jobs(), args.verbose);
+    let Some(locked) = lock_repositories(&repos, args, reporter) else {
+        return EXIT_LOCK_FAILED;
+    };
+    let processing_repos = locked.repos;
 
     if args.fsck_only {
-        let ok = run_fsck_checks(&processing_repos, args.verbose);
-        println!();
-        println!("{}", msg(Msg::FsckOnlyComplete));
+        let ok = run_fsck_checks(
+            &processing_repos,
+            args.fsck_mode,
+            args.verbose,
+            "only",
+            reporter,
+        );
+        say!();
+        say!("{}", msg(Msg::FsckOnlyComplete));
         return if ok { 0 } else { 2 };
     }
 
+    // 中断された実行の残りファイルを片付けてからfsckする (ロックしたリポジトリのみ)
+    if !args.no_lock {
+        recover_repos(args, &processing_repos, reporter);
+    }
+
     if args.no_fsck {
         if args.verbose {
-            println!("{}", msg(Msg::FsckSkipped));
+            say!("{}", msg(Msg::FsckSkipped));
+        }
+    } else if !run_fsck_checks(
+        &processing_repos,
+        args.fsck_mode,
+        args.verbose,
+        "pre",
+        reporter,
+    ) {
+        if interrupted() {
+            return interrupted_exit();
         }
-    } else if !run_fsck_checks(&processing_repos, args.verbose) {
         eprintln!("{}", msg(Msg::AbortOnFsckFailure));
         return 2;
     }
 
     if args.verbose {
-        println!("{}", msg(Msg::Scanning));
+        say!("{}", msg(Msg::Scanning));
     }
 
-    let all_objects = collect_all_objects(&args.paths, args.verbose);
+    let all_objects = collect_all_objects(args, &processing_repos);
+    if interrupted() {
+        return interrupted_exit();
+    }
     if args.verbose {
-        println!("{}: {}", msg(Msg::FoundObjects), all_objects.len());
+        say!("{}: {}", msg(Msg::FoundObjects), all_objects.len());
     }
 
-    let device_groups = group_by_device(all_objects);
+    // デバイスIDの順に処理し、出力を実行ごとに揃える
+    let mut device_groups: Vec<_> = group_by_device(all_objects).into_iter().collect();
+    device_groups.sort_by_key(|(device_id, _)| *device_id);
     let device_count = device_groups.len();
     if args.verbose && device_count > 1 {
-        println!("{}: {}", msg(Msg::DeviceGroups), device_count);
+        say!("{}: {}", msg(Msg::DeviceGroups), device_count);
     }
 
+    // 置換したオブジェクトをリポジトリごとに記録し、post-fsckの対象を絞る
+    let repo_by_objects_dir: HashMap<&Path, &Path> = processing_repos
+        .iter()
+        .map(|r| (r.objects_dir.as_path(), r.path.as_path()))
+        .collect();
+    let mut touched: BTreeMap<PathBuf, Touched> = BTreeMap::new();
+    // 置換した組を記録し、restoreで元に戻せるようにする
+    let mut journal = if args.dry_run {
+        None
+    } else {
+        args.state_dirs.journal.as_deref().map(Journal::for_run)
+    };
+
     let mut stats = Stats::new();
-    for (device_id, objects) in device_groups {
+    // 中断されたら処理中の置換を終えたところで止め、途中までの集計を表示する
+    'devices: for (device_id, objects) in device_groups {
         if args.verbose && device_count > 1 {
-            println!("\n{}: {}", msg(Msg::ProcessingDevice), device_id);
+            say!("\n{}: {}", msg(Msg::ProcessingDevice), device_id);
         }
 
-        let duplicates = find_duplicates(objects);
+        // autoのreflinkの確認は試しのファイルを書くため、dry-runでは行わず最初の置換の前に行う
+        let device_dir = objects[0].path.parent().map(Path::to_path_buf);
+        let mut strategy = None;
+
+        let duplicates = match &pool {
+            Some((pool, pool_device)) if *pool_device != device_id => {
+                // プールと別のファイルシステムにあるオブジェクトはリンクできない
+                // (プールが無い場合と同じく、共有するはずだったduplicateだけを数える)
+                stats.cross_filesystem += find_duplicates(objects)
+                    .iter()
+                    .map(|group| group.duplicates.len())
+                    .sum::<usize>();
+                say!(
+                    "{}: {} ({})",
+                    msg(Msg::PoolCrossFilesystem),
+                    device_id,
+                    pool.root().display()
+                );
+                continue;
+            }
+            Some((pool, _)) => pool_duplicates(pool, objects, args, &mut stats),
+            None => find_duplicates(objects),
+        };
+        // 所有者・モードの異なるファイルは--owner-policyに従って分ける
+        let plan = apply_owner_policy(duplicates, args.owner_policy);
+        stats.owner_mismatch += plan.mismatched.len();
+        if args.verbose {
+            for path in &plan.mismatched {
+                say!("{}: {}", msg(Msg::OwnerMismatch), path.display());
+            }
+        }
+        let duplicates = plan.groups;
+        // リンク数の上限を超える分は、duplicateの1つを新しいsourceにして分ける
+        // (reflinkを指定した場合以外はハードリンクになりうる)
+        let may_hardlink = pool.is_some() || args.link_mode != LinkMode::Reflink;
+        let duplicates = match (may_hardlink, device_dir.as_deref().and_then(link_max)) {
+            (true, Some(max_links)) => {
+                let mut split = Vec::new();
+                for group in duplicates {
+                    let parts = split_by_link_limit(group, max_links);
+                    stats.new_sources += parts.len().saturating_sub(1);
+                    split.extend(parts);
+                }
+                split
+            }
+            _ => duplicates,
+        };
         if args.verbose {
-            println!("{}: {}", msg(Msg::FoundDuplicateGroups), duplicates.len());
+            say!("{}: {}", msg(Msg::FoundDuplicateGroups), duplicates.len());
         }
 
         if duplicates.is_empty() {
             if args.verbose {
-                println!("{}: 0", msg(Msg::DuplicateFiles));
+                say!("{}: 0", msg(Msg::DuplicateFiles));
             }
             continue;
         }
 
         for group in &duplicates {
+            if interrupted() {
+                break 'devices;
+            }
+            reporter.emit(json!({
+                "type": "group",
+                "kind": "loose",
+                "device": device_id,
+                "format": group.source.format.as_str(),
+                "hash": group.source.hash,
+                "size": group.source.size,
+                "source": path_value(&group.source.path),
+                "duplicates": group.duplicates.iter().map(|d| path_value(&d.path)).collect::<Vec<_>>(),
+            }));
             let dup_count = group.duplicates.len();
             stats.total_duplicates += dup_count;
             let group_savings = group.source.size * dup_count as u64;
-            stats.total_savings += group_savings;
+            stats.estimated_savings += group_savings;
 
             if args.dry_run {
                 if args.verbose {
-                    println!(
+                    say!(
                         "\n{}: {} ({}: {})",
                         msg(Msg::DuplicateFiles),
                         dup_count + 1,
                         msg(Msg::GroupSavings),
                         format_size(group_savings)
                     );
-                    println!("  [source] {} ({})", group.source.path.display(), format_size(group.source.size));
+                    say!(
+                        "  [source] {} ({})",
+                        group.source.path.display(),
+                        format_size(group.source.size)
+                    );
                     for dup in &group.duplicates {
-                        println!("  [dup]    {}", dup.path.display());
+                        say!("  [dup]    {}", dup.path.display());
                     }
                 }
                 continue;
             }
 
+            let strategy = *strategy.get_or_insert_with(|| {
+                let strategy = device_strategy(args, pool.is_some(), device_dir.as_deref());
+                if args.verbose && args.link_mode != LinkMode::Hardlink {
+                    say!(
+                        "{}: {} ({})",
+                        msg(Msg::LinkStrategyForDevice),
+                        strategy.as_str(),
+                        device_id
+                    );
+                }
+                strategy
+            });
+            // 実行中にリンク数が上限に達したら、そのduplicateを以降のsourceにする
+            let mut source = &group.source;
+            normalize_source(args, &source.path, &mut stats);
             for dup in &group.duplicates {
+                if interrupted() {
+                    break 'devices;
+                }
+                let entry = JournalEntry::capture(&source.path, &dup.path).ok();
+                let usage = InodeUsage::of(&dup.path).ok();
+                let result = replace_object(args, source, &dup.path, strategy);
+                if result == ReplaceResult::Replaced {
+                    record_journal(&mut journal, entry);
+                    if let Some(usage) = usage {
+                        stats.freed.record_unlinked(usage);
+                    }
+                    let repo = dup
+                        .path
+                        .parent()
+                        .and_then(Path::parent)
+                        .and_then(|dir| repo_by_objects_dir.get(dir));
+                    if let Some(repo) = repo {
+                        touched
+                            .entry(repo.to_path_buf())
+                            .or_default()
+                            .objects
+                            .push(dup.hash.clone());
+                    }
+                }
+                reporter.emit(replace_event("loose", &source.path, &dup.path, &result));
+                if matches!(result, ReplaceResult::TooManyLinks(_)) {
+                    source = dup;
+                    normalize_source(args, &source.path, &mut stats);
+                }
                 handle_replace_result(
-                    replace_with_hardlink(&group.source.path, &dup.path),
+                    result,
                     dup.path.display().to_string(),
                     args.verbose,
                     &mut stats,
@@ -132,179 +366,2621 @@ pub fn run(args: Args) -> i32 {
         }
     }
 
-    print_summary(&args, &stats);
+    let pack_stats = if args.packs && !interrupted() {
+        let mut pack_stats = Stats::new();
+        process_packs(
+            &processing_repos,
+            args,
+            &mut pack_stats,
+            &mut journal,
+            &mut touched,
+            reporter,
+        );
+        Some(pack_stats)
+    } else {
+        None
+    };
+
+    print_summary(args, &stats, pack_stats.as_ref());
+    print_lock_failures(&locked.statuses);
+    let journal = journal.filter(|j| !j.is_empty());
+    if let Some(journal) = &journal {
+        say!(
+            "{}: {} ({})",
+            msg(Msg::JournalWritten),
+            journal.path().display(),
+            journal.len()
+        );
+    }
+    reporter.emit(json!({
+        "type": "summary",
+        "dry_run": args.dry_run,
+        "loose": stats_value(&stats),
+        "packs": pack_stats.as_ref().map(stats_value),
+        "journal": journal.as_ref().map(|j| path_value(j.path())),
+        "locks": lock_statuses_value(&locked.statuses),
+        "interrupted": interrupted(),
+    }));
 
-    if !args.no_fsck && !args.dry_run && !run_fsck_checks(&processing_repos, args.verbose) {
+    // 置換済みのものはjournalに記録済みなので、post-fsckは行わずに終了する
+    if interrupted() {
+        return interrupted_exit();
+    }
+    if args.no_fsck || args.dry_run {
+        return 0;
+    }
+    let post_ok = if args.full_post_fsck {
+        run_fsck_checks(
+            &processing_repos,
+            args.fsck_mode,
+            args.verbose,
+            "post",
+            reporter,
+        )
+    } else {
+        run_touched_checks(&touched, args.fsck_mode, args.verbose, reporter)
+    };
+    if !post_ok {
         return 3;
     }
     0
 }
 
-fn validate_paths(paths: &[String]) -> bool {
-    for path_str in paths {
-        let path = Path::new(path_str);
-        if !path.exists() {
-            eprintln!("Error: {} does not exist", path_str);
-            return false;
-        }
+/// デバイスで使うリンク方式を決める
+///
+/// プールはリンク数で参照を数えるため常にハードリンクにする。
+///
+/// Args:
+///     pooled: `--pool` で共有するか
+///     dir: そのデバイス上のobjectsのディレクトリ
+fn device_strategy(args: &Args, pooled: bool, dir: Option<&Path>) -> LinkStrategy {
+    match dir {
+        Some(dir) if !pooled => args.link_mode.resolve(dir),
+        _ => LinkStrategy::Hardlink,
     }
-    true
 }
 
-fn collect_repositories(paths: &[String], verbose: bool) -> Vec<PathBuf> {
-    let mut repos = HashSet::new();
-    for path_str in paths {
-        let path = Path::new(path_str);
-        if verbose {
-            println!("{}: {}", msg(Msg::ScanningPath), path.display());
-        }
-        for repo in find_git_repositories_with_progress(path, |current| {
-            if verbose {
-                println!("{}: {}", msg(Msg::CheckingDirectory), current.display());
+/// `--pool` のプールディレクトリを開き、デバイスIDを調べる
+fn open_pool(args: &Args) -> Option<(ObjectPool, u64)> {
+    let Some(root) = args.pool_root() else {
+        eprintln!("{}", msg(Msg::PoolUnavailable));
+        return None;
+    };
+    let pool = ObjectPool::new(root);
+    match pool.device() {
+        Ok(device) => {
+            if args.verbose {
+                say!("{}: {}", msg(Msg::PoolDir), pool.root().display());
             }
-        }) {
-            repos.insert(repo);
+            Some((pool, device))
+        }
+        Err(e) => {
+            eprintln!(
+                "{}: {} - {}",
+                msg(Msg::PoolUnavailable),
+                pool.root().display(),
+                e
+            );
+            None
         }
     }
-    let mut repo_list: Vec<_> = repos.into_iter().collect();
-    repo_list.sort();
-    repo_list
 }
 
-fn collect_all_objects(paths: &[String], verbose: bool) -> Vec<GitObjectInfo> {
-    let mut all_objects = Vec::new();
-    for path_str in paths {
-        let path = Path::new(path_str);
-        if verbose {
-            println!("{}: {}", msg(Msg::ScanningPath), path.display());
-        }
-        let objects = scan_git_objects_with_progress(path, |current| {
-            if verbose {
-                println!("{}: {}", msg(Msg::CheckingDirectory), current.display());
+/// プールのエントリを共有元とした重複グループを作る (`--pool`)
+///
+/// プールにまだ無いオブジェクトは、この時点でプールに登録する (dry-runでは数えるだけ)。
+fn pool_duplicates(
+    pool: &ObjectPool,
+    objects: Vec<GitObjectInfo>,
+    args: &Args,
+    stats: &mut Stats,
+) -> Vec<DuplicateGroup> {
+    let mut groups = Vec::new();
+    for files in group_by_hash(objects) {
+        let Some(plan) = pool.plan(files) else {
+            continue;
+        };
+        let source = match (plan.entry, plan.adopt) {
+            (Some(entry), _) => entry,
+            (None, Some(adopt)) if args.dry_run => {
+                stats.pool_added += 1;
+                adopt
             }
-        });
-        all_objects.extend(objects);
+            (None, Some(adopt)) => match pool.adopt(&adopt) {
+                Ok(entry) => {
+                    stats.pool_added += 1;
+                    if args.verbose {
+                        say!("{}: {}", msg(Msg::PoolAdded), adopt.path.display());
+                    }
+                    entry
+                }
+                Err(e) => {
+                    stats.errors += 1;
+                    eprintln!(
+                        "{}: {} - {}",
+                        msg(Msg::ErrorOccurred),
+                        adopt.path.display(),
+                        e
+                    );
+                    continue;
+                }
+            },
+            (None, None) => continue,
+        };
+        if !plan.duplicates.is_empty() {
+            groups.push(DuplicateGroup {
+                source,
+                duplicates: plan.duplicates,
+            });
+        }
     }
-    all_objects
+    groups
 }
 
-fn run_fsck_checks(repos: &[PathBuf], verbose: bool) -> bool {
-    let mut failed = 0usize;
-    for repo in repos {
-        if verbose {
-            println!("{}: {}", msg(Msg::FsckRunning), repo.display());
-        }
+/// リンクされなくなったプールのエントリを削除する (`pool gc` サブコマンド)
+///
+/// Returns:
+///     終了コード (0: 成功, 1: プールが無い, 4: 削除に失敗したエントリあり)
+fn run_pool_gc(args: &Args, reporter: &mut Reporter) -> i32 {
+    let Some(root) = args.pool_root().filter(|r| r.is_dir()) else {
+        eprintln!("{}", msg(Msg::PoolUnavailable));
+        return 1;
+    };
+    let pool = ObjectPool::new(root);
+    say!("{}: {}", msg(Msg::PoolDir), pool.root().display());
 
-        let result = run_git_fsck(repo);
-        if result.success {
-            if verbose {
-                println!("{}: {}", msg(Msg::FsckOk), repo.display());
-            }
-        } else {
-            failed += 1;
-            let detail = if result.stderr.is_empty() {
-                format!("exit code: {:?}", result.code)
-            } else {
-                result.stderr
-            };
-            eprintln!("{}: {} - {}", msg(Msg::FsckFailed), repo.display(), detail);
+    let stats = pool.gc(args.dry_run, |entry| {
+        reporter.emit(json!({
+            "type": "pool_gc",
+            "entry": path_value(entry),
+            "result": if args.dry_run { "planned" } else { "removed" },
+        }));
+        if args.verbose {
+            say!("{}: {}", msg(Msg::PoolRemoved), entry.display());
         }
-    }
+    });
+    reporter.emit(json!({
+        "type": "summary",
+        "command": "pool_gc",
+        "dry_run": args.dry_run,
+        "kept": stats.kept,
+        "removed": stats.removed,
+        "removed_bytes": stats.removed_bytes,
+        "errors": stats.errors,
+    }));
 
-    println!(
-        "{}: {}/{} (failed: {})",
-        msg(Msg::FsckSummary),
-        repos.len().saturating_sub(failed),
-        repos.len(),
-        failed
+    say!();
+    if args.dry_run {
+        say!("{}", msg(Msg::SummaryDryRun));
+    } else {
+        say!("{}", msg(Msg::PoolGcSummary));
+    }
+    say!("  {}: {}", msg(Msg::TotalPoolKept), stats.kept);
+    say!(
+        "  {}: {} ({})",
+        msg(Msg::TotalPoolRemoved),
+        stats.removed,
+        format_size(stats.removed_bytes)
     );
-    failed == 0
+    if stats.errors > 0 {
+        say!("  {}: {}", msg(Msg::TotalErrors), stats.errors);
+        return 4;
+    }
+    0
 }
 
-fn acquire_repo_locks(repos: &[PathBuf], verbose: bool) -> (Vec<PathBuf>, Vec<RepoLock>) {
-    let mut locked_repos = Vec::new();
-    let mut locks = Vec::new();
-    let mut failed = 0usize;
+/// 共通するlooseオブジェクトを共有ストアに移し、alternatesで参照させる (`alternates` サブコマンド)
+///
+/// 2つ以上のリポジトリにあるオブジェクトと、既にストアにあるオブジェクトが対象。
+/// ストアへの格納とalternatesへの追記が済んだリポジトリからだけローカルのコピーを削除する。
+///
+/// Returns:
+///     終了コード (0: 成功, 1: 入力パス・ストアが不正, 2: pre-fsck失敗, 3: post-fsck失敗,
+///     4: 追加できないリポジトリや失敗したオブジェクトあり)
+fn run_alternates(args: &Args, store: &Path, paths: &[String], reporter: &mut Reporter) -> i32 {
+    if !validate_paths(paths) {
+        return 1;
+    }
+    let store = match ObjectStore::open(store) {
+        Ok(store) => store,
+        Err(e) => {
+            eprintln!(
+                "{}: {} - {}",
+                msg(Msg::AlternatesStoreUnavailable),
+                store.display(),
+                e
+            );
+            return 1;
+        }
+    };
+    say!(
+        "{}: {}",
+        msg(Msg::AlternatesStore),
+        store.objects_dir().display()
+    );
 
-    for repo in repos {
-        if verbose {
-            println!("{}: {}", msg(Msg::LockingRepo), repo.display());
+    let Some(_tree_locks) = lock_trees(paths, args, reporter) else {
+        return EXIT_LOCK_FAILED;
+    };
+    let mut stats = AlternatesStats::default();
+    // 循環や深すぎる入れ子になるリポジトリは対象外
+    let repos: Vec<GitRepository> = collect_repositories(paths, args.jobs(), args.verbose)
+        .into_iter()
+        .filter(|repo| match store.check_link(&repo.objects_dir) {
+            Ok(()) => true,
+            Err(e) => {
+                stats.errors += 1;
+                reporter.emit(alternates_event(
+                    &repo.path,
+                    "rejected",
+                    0,
+                    Some(e.to_string()),
+                ));
+                eprintln!(
+                    "{}: {} - {}",
+                    msg(Msg::AlternatesRejected),
+                    repo.path.display(),
+                    e
+                );
+                false
+            }
+        })
+        .collect();
+
+    let Some(locked) = lock_repositories(&repos, args, reporter) else {
+        return EXIT_LOCK_FAILED;
+    };
+    let processing_repos = locked.repos;
+    if !args.no_fsck
+        && !run_fsck_checks(
+            &processing_repos,
+            args.fsck_mode,
+            args.verbose,
+            "pre",
+            reporter,
+        )
+    {
+        eprintln!("{}", msg(Msg::AbortOnFsckFailure));
+        return 2;
+    }
+
+    let objects =
+        scan_repositories_parallel(&processing_repos, args.jobs(), &mut ScanCache::disabled());
+    let groups: Vec<Vec<GitObjectInfo>> = group_by_hash(objects)
+        .into_iter()
+        .filter(|files| store.contains(&files[0].hash) || is_shared(files))
+        .collect();
+
+    // 1. ストアに無いオブジェクトを格納する
+    let mut stored_groups = Vec::new();
+    for files in groups {
+        if interrupted() {
+            break;
+        }
+        if store.contains(&files[0].hash) {
+            stored_groups.push(files);
+            continue;
+        }
+        if !args.dry_run {
+            if let Err(e) = store.insert(&files[0]) {
+                stats.errors += 1;
+                eprintln!(
+                    "{}: {} - {}",
+                    msg(Msg::ErrorOccurred),
+                    files[0].path.display(),
+                    e
+                );
+                continue;
+            }
         }
+        stats.stored += 1;
+        stored_groups.push(files);
+    }
 
-        match try_lock_repo(repo) {
-            Ok(lock) => {
-                if verbose {
-                    println!("{}: {}", msg(Msg::LockAcquired), repo.display());
+    // 2. 削除対象のあるリポジトリのalternatesにストアを追記する
+    let mut removable: BTreeMap<&Path, Vec<&GitObjectInfo>> = BTreeMap::new();
+    for file in stored_groups.iter().flatten() {
+        if let Some(dir) = objects_dir_of(file) {
+            removable.entry(dir).or_default().push(file);
+        }
+    }
+    for repo in &processing_repos {
+        if interrupted() {
+            break;
+        }
+        let Some(files) = removable.get(repo.objects_dir.as_path()) else {
+            continue;
+        };
+        let linked = if args.dry_run {
+            Ok(!store.is_linked(&repo.objects_dir))
+        } else {
+            store.link(&repo.objects_dir)
+        };
+        match linked {
+            Ok(added) => {
+                if added {
+                    stats.linked += 1;
+                    if args.verbose {
+                        say!("{}: {}", msg(Msg::AlternatesLinked), repo.path.display());
+                    }
                 }
-                locked_repos.push(repo.clone());
-                locks.push(lock);
             }
             Err(e) => {
-                failed += 1;
-                eprintln!("{}: {} - {}", msg(Msg::LockFailed), repo.display(), e);
+                stats.errors += 1;
+                reporter.emit(alternates_event(
+                    &repo.path,
+                    "error",
+                    0,
+                    Some(e.to_string()),
+                ));
+                eprintln!(
+                    "{}: {} - {}",
+                    msg(Msg::ErrorOccurred),
+                    repo.path.display(),
+                    e
+                );
+                continue;
+            }
+        }
+
+        // 3. ストアと同じ内容のローカルのコピーを削除する
+        let mut removed = 0;
+        for file in files {
+            if args.dry_run {
+                removed += 1;
+                stats.removed_bytes += file.size;
+                if args.verbose {
+                    say!("  [remove] {}", file.path.display());
+                }
+                continue;
+            }
+            match is_same_content(&store.entry_path(&file.hash), &file.path) {
+                Ok(true) => {}
+                Ok(false) => {
+                    stats.errors += 1;
+                    eprintln!("{}: {}", msg(Msg::ContentMismatch), file.path.display());
+                    continue;
+                }
+                Err(e) => {
+                    stats.errors += 1;
+                    eprintln!(
+                        "{}: {} - {}",
+                        msg(Msg::ErrorOccurred),
+                        file.path.display(),
+                        e
+                    );
+                    continue;
+                }
+            }
+            match std::fs::remove_file(&file.path) {
+                Ok(()) => {
+                    removed += 1;
+                    stats.removed_bytes += file.size;
+                    if args.verbose {
+                        say!("{}: {}", msg(Msg::AlternatesRemoved), file.path.display());
+                    }
+                }
+                Err(e) => {
+                    stats.errors += 1;
+                    eprintln!(
+                        "{}: {} - {}",
+                        msg(Msg::ErrorOccurred),
+                        file.path.display(),
+                        e
+                    );
+                }
             }
         }
+        stats.removed += removed;
+        stats.changed.push(repo.clone());
+        let result = if args.dry_run { "planned" } else { "linked" };
+        reporter.emit(alternates_event(&repo.path, result, removed, None));
     }
 
-    println!(
-        "{}: {}/{} (failed: {})",
-        msg(Msg::LockSummary),
-        locked_repos.len(),
-        repos.len(),
-        failed
+    reporter.emit(json!({
+        "type": "summary",
+        "command": "alternates",
+        "dry_run": args.dry_run,
+        "store": path_value(store.objects_dir()),
+        "linked": stats.linked,
+        "stored": stats.stored,
+        "removed": stats.removed,
+        "removed_bytes": stats.removed_bytes,
+        "errors": stats.errors,
+        "interrupted": interrupted(),
+    }));
+    say!();
+    if args.dry_run {
+        say!("{}", msg(Msg::SummaryDryRun));
+    } else {
+        say!("{}", msg(Msg::AlternatesSummary));
+    }
+    say!("  {}: {}", msg(Msg::TotalAlternatesLinked), stats.linked);
+    say!("  {}: {}", msg(Msg::TotalStored), stats.stored);
+    say!(
+        "  {}: {} ({})",
+        msg(Msg::TotalLocalRemoved),
+        stats.removed,
+        format_size(stats.removed_bytes)
     );
-    (locked_repos, locks)
+    if stats.errors > 0 {
+        say!("  {}: {}", msg(Msg::TotalErrors), stats.errors);
+    }
+
+    if interrupted() {
+        return interrupted_exit();
+    }
+    if !args.no_fsck
+        && !args.dry_run
+        && !run_fsck_checks(
+            &stats.changed,
+            args.fsck_mode,
+            args.verbose,
+            "post",
+            reporter,
+        )
+    {
+        return 3;
+    }
+    if stats.errors > 0 {
+        return 4;
+    }
+    0
 }
 
-fn handle_replace_result(result: ReplaceResult, path: String, verbose: bool, stats: &mut Stats) {
-    match result {
-        ReplaceResult::Replaced => {
-            stats.replaced += 1;
-            if verbose {
-                println!("{}: {}", msg(Msg::Replaced), path);
-            }
+/// alternatesの集計
+#[derive(Default)]
+struct AlternatesStats {
+    linked: usize,
+    stored: usize,
+    removed: usize,
+    removed_bytes: u64,
+    errors: usize,
+    /// alternatesを追記した・オブジェクトを削除したリポジトリ (post-fsckの対象)
+    changed: Vec<GitRepository>,
+}
+
+/// alternatesの1リポジトリ分のイベント
+fn alternates_event(repo: &Path, result: &str, removed: usize, detail: Option<String>) -> Value {
+    json!({
+        "type": "alternates",
+        "repo": path_value(repo),
+        "result": result,
+        "removed": removed,
+        "detail": detail,
+    })
+}
+
+/// 中断された実行が残したファイルを後始末する (`recover` サブコマンド)
+///
+/// ロックを取得できたリポジトリだけを対象にする。
+///
+/// Returns:
+///     終了コード (0: 成功, 1: 入力パスが不正, 4: 自動で処理できないファイルやエラーあり)
+fn run_recover(args: &Args, paths: &[String], reporter: &mut Reporter) -> i32 {
+    if !validate_paths(paths) {
+        return 1;
+    }
+    let Some(_tree_locks) = lock_trees(paths, args, reporter) else {
+        return EXIT_LOCK_FAILED;
+    };
+    let repos = collect_repositories(paths, args.jobs(), args.verbose);
+    let Some(locked) = lock_repositories(&repos, args, reporter) else {
+        return EXIT_LOCK_FAILED;
+    };
+    let processing_repos = locked.repos;
+
+    let stats = recover_repos(args, &processing_repos, reporter);
+    reporter.emit(json!({
+        "type": "summary",
+        "command": "recover",
+        "dry_run": args.dry_run,
+        "restored": stats.restored,
+        "removed": stats.removed,
+        "kept": stats.kept,
+        "errors": stats.errors,
+    }));
+    say!();
+    if args.dry_run {
+        say!("{}", msg(Msg::SummaryDryRun));
+    } else {
+        say!("{}", msg(Msg::RecoverSummary));
+    }
+    say!("  {}: {}", msg(Msg::TotalRecovered), stats.restored);
+    say!("  {}: {}", msg(Msg::TotalLeftoverRemoved), stats.removed);
+    say!("  {}: {}", msg(Msg::TotalLeftoverKept), stats.kept);
+    if stats.errors > 0 {
+        say!("  {}: {}", msg(Msg::TotalErrors), stats.errors);
+    }
+    if stats.kept > 0 || stats.errors > 0 {
+        return 4;
+    }
+    0
+}
+
+/// recoverの集計
+#[derive(Default)]
+struct RecoverStats {
+    restored: usize,
+    removed: usize,
+    kept: usize,
+    errors: usize,
+}
+
+/// リポジトリに残った退避用リンク・一時ファイルを探して後始末する
+///
+/// 起動時の回復処理と `recover` サブコマンドで共通。dry-runでは何も変更しない。
+fn recover_repos(args: &Args, repos: &[GitRepository], reporter: &mut Reporter) -> RecoverStats {
+    let mut stats = RecoverStats::default();
+    for leftover in repos.iter().flat_map(find_leftovers) {
+        if args.verbose {
+            say!("{}: {}", msg(Msg::RecoverFound), leftover.path.display());
         }
-        ReplaceResult::AlreadyLinked => {
-            stats.already_linked += 1;
-            if verbose {
-                println!("{}: {}", msg(Msg::AlreadyLinked), path);
+        let result = recover_leftover(&leftover, args.dry_run);
+        reporter.emit(recover_event(&leftover, &result, args.dry_run));
+        match result {
+            RecoverResult::Restored => {
+                stats.restored += 1;
+                say!(
+                    "{}: {}",
+                    msg(Msg::RecoverRestored),
+                    leftover.target.display()
+                );
+            }
+            RecoverResult::Removed => {
+                stats.removed += 1;
+                if args.verbose {
+                    say!("{}: {}", msg(Msg::RecoverRemoved), leftover.path.display());
+                }
+            }
+            RecoverResult::Kept(reason) => {
+                stats.kept += 1;
+                eprintln!(
+                    "{}: {} - {}",
+                    msg(Msg::RecoverKept),
+                    leftover.path.display(),
+                    reason
+                );
+            }
+            RecoverResult::Error(e) => {
+                stats.errors += 1;
+                eprintln!(
+                    "{}: {} - {}",
+                    msg(Msg::ErrorOccurred),
+                    leftover.path.display(),
+                    e
+                );
             }
-        }
-        ReplaceResult::CrossFilesystem => {
-            stats.cross_filesystem += 1;
-            println!("{}: {}", msg(Msg::CrossFilesystem), path);
-        }
-        ReplaceResult::RolledBack(e) => {
-            stats.errors += 1;
-            eprintln!("{}: {} - {}", msg(Msg::RollbackOccurred), path, e);
-        }
-        ReplaceResult::RollbackFailed(e) => {
-            stats.errors += 1;
-            eprintln!("{}: {} - {}", msg(Msg::RollbackFailed), path, e);
-        }
-        ReplaceResult::Error(e) => {
-            stats.errors += 1;
-            eprintln!("{}: {} - {}", msg(Msg::ErrorOccurred), path, e);
         }
     }
+    stats
 }
 
-fn print_summary(args: &Args, stats: &Stats) {
-    println!();
-    if args.dry_run {
-        println!("{}", msg(Msg::SummaryDryRun));
-        println!("  {}: {}", msg(Msg::TotalDuplicates), stats.total_duplicates);
-        println!("  {}: {}", msg(Msg::EstimatedSavings), format_size(stats.total_savings));
-        return;
+/// 残りファイル1件分のイベント (dry-runでは復元・削除を "planned" にする)
+fn recover_event(leftover: &Leftover, result: &RecoverResult, dry_run: bool) -> Value {
+    let (result, detail) = match result {
+        RecoverResult::Restored if dry_run => ("planned", Some("restore".to_string())),
+        RecoverResult::Removed if dry_run => ("planned", Some("remove".to_string())),
+        RecoverResult::Restored => ("restored", None),
+        RecoverResult::Removed => ("removed", None),
+        RecoverResult::Kept(reason) => ("kept", Some(reason.clone())),
+        RecoverResult::Error(e) => ("error", Some(e.clone())),
+    };
+    json!({
+        "type": "recover",
+        "path": path_value(&leftover.path),
+        "target": path_value(&leftover.target),
+        "kind": match leftover.kind {
+            LeftoverKind::Backup => "backup",
+            LeftoverKind::Temp => "temp",
+            LeftoverKind::Probe => "probe",
+        },
+        "result": result,
+        "detail": detail,
+    })
+}
+
+/// リポジトリのロックファイルを一覧する (`locks` サブコマンド)
+///
+/// `--clean` では保持されていないロックファイル (stale・空) を削除する。
+///
+/// Returns:
+///     終了コード (0: 成功, 1: 入力パスが不正, 4: 状態の確認・削除に失敗したファイルあり)
+fn run_locks(args: &Args, paths: &[String], clean: bool, reporter: &mut Reporter) -> i32 {
+    if !validate_paths(paths) {
+        return 1;
     }
+    let repos = collect_repositories(paths, args.jobs(), args.verbose);
 
-    println!("{}", msg(Msg::SummaryComplete));
-    println!("  {}: {}", msg(Msg::TotalDuplicates), stats.total_duplicates);
-    println!("  {}: {}", msg(Msg::TotalReplaced), stats.replaced);
-    let skipped = stats.already_linked + stats.cross_filesystem;
-    println!("  {}: {}", msg(Msg::TotalSkipped), skipped);
-    if stats.errors > 0 {
-        println!("  {}: {}", msg(Msg::TotalErrors), stats.errors);
+    let mut stats = LocksStats::default();
+    for repo in &repos {
+        let lock_path = lock_file_path(&repo.path, args.lock_dir.as_deref());
+        if !lock_path.exists() {
+            continue;
+        }
+        let state = match inspect_lock(&lock_path) {
+            Ok(state) => state,
+            Err(e) => {
+                stats.errors += 1;
+                eprintln!(
+                    "{}: {} - {}",
+                    msg(Msg::ErrorOccurred),
+                    lock_path.display(),
+                    e
+                );
+                continue;
+            }
+        };
+        let holder = match &state {
+            LockState::Held(holder) => {
+                stats.held += 1;
+                holder.as_ref()
+            }
+            LockState::Stale(holder) => {
+                stats.stale += 1;
+                Some(holder)
+            }
+            LockState::Free => {
+                stats.free += 1;
+                None
+            }
+        };
+        match holder {
+            Some(holder) => say!(
+                "  [{}] {} ({})",
+                state.as_str(),
+                lock_path.display(),
+                holder
+            ),
+            None => say!("  [{}] {}", state.as_str(), lock_path.display()),
+        }
+
+        let result = if !clean || matches!(state, LockState::Held(_)) {
+            "kept"
+        } else if args.dry_run {
+            stats.removed += 1;
+            "planned"
+        } else {
+            match remove_lock_file(&lock_path) {
+                Ok(()) => {
+                    stats.removed += 1;
+                    if args.verbose {
+                        say!("{}: {}", msg(Msg::LockFileRemoved), lock_path.display());
+                    }
+                    "removed"
+                }
+                Err(e) => {
+                    stats.errors += 1;
+                    eprintln!(
+                        "{}: {} - {}",
+                        msg(Msg::ErrorOccurred),
+                        lock_path.display(),
+                        e
+                    );
+                    "error"
+                }
+            }
+        };
+        reporter.emit(json!({
+            "type": "lock_file",
+            "repo": path_value(&repo.path),
+            "path": path_value(&lock_path),
+            "state": state.as_str(),
+            "holder": holder.map(holder_value),
+            "result": result,
+        }));
+    }
+
+    reporter.emit(json!({
+        "type": "summary",
+        "command": "locks",
+        "dry_run": args.dry_run,
+        "held": stats.held,
+        "stale": stats.stale,
+        "free": stats.free,
+        "removed": stats.removed,
+        "errors": stats.errors,
+    }));
+    say!();
+    if args.dry_run {
+        say!("{}", msg(Msg::SummaryDryRun));
+    } else {
+        say!("{}", msg(Msg::LocksSummary));
+    }
+    say!("  {}: {}", msg(Msg::TotalLocksHeld), stats.held);
+    say!("  {}: {}", msg(Msg::TotalLocksStale), stats.stale);
+    say!("  {}: {}", msg(Msg::TotalLocksFree), stats.free);
+    if clean {
+        say!("  {}: {}", msg(Msg::TotalLockFilesRemoved), stats.removed);
+    }
+    if stats.errors > 0 {
+        say!("  {}: {}", msg(Msg::TotalErrors), stats.errors);
+        return 4;
+    }
+    0
+}
+
+/// locksの集計
+#[derive(Default)]
+struct LocksStats {
+    held: usize,
+    stale: usize,
+    free: usize,
+    removed: usize,
+    errors: usize,
+}
+
+/// ロックファイルの保持者のJSON
+fn holder_value(holder: &LockHolder) -> Value {
+    json!({
+        "pid": holder.pid,
+        "host": holder.host,
+        "started": holder.started,
+        "command": holder.command,
+    })
+}
+
+/// シグナルで中断したことを表示し、終了コードを返す
+fn interrupted_exit() -> i32 {
+    eprintln!("{}", msg(Msg::Interrupted));
+    EXIT_INTERRUPTED
+}
+
+fn validate_paths(paths: &[String]) -> bool {
+    for path_str in paths {
+        let path = Path::new(path_str);
+        if !path.exists() {
+            eprintln!("Error: {} does not exist", path_str);
+            return false;
+        }
+    }
+    true
+}
+
+fn collect_repositories(paths: &[String], jobs: usize, verbose: bool) -> Vec<GitRepository> {
+    let mut repos = Vec::new();
+    for path_str in paths {
+        let path = Path::new(path_str);
+        if verbose {
+            say!("{}: {}", msg(Msg::ScanningPath), path.display());
+        }
+        repos.extend(discover_repositories_parallel(path, jobs, |current| {
+            if verbose {
+                say!("{}: {}", msg(Msg::CheckingDirectory), current.display());
+            }
+        }));
+    }
+    dedup_repositories(repos)
+}
+
+/// 処理対象リポジトリのオブジェクトを探索ルートごとのキャッシュを使って集める
+///
+/// 各リポジトリは、そのパスを含む最初の探索ルートのキャッシュに記録する。
+fn collect_all_objects(args: &Args, repos: &[GitRepository]) -> Vec<GitObjectInfo> {
+    let mut remaining: Vec<&GitRepository> = repos.iter().collect();
+    let mut all_objects = Vec::new();
+
+    for path_str in &args.paths {
+        let root = Path::new(path_str);
+        let (root_repos, rest): (Vec<_>, Vec<_>) = remaining
+            .into_iter()
+            .partition(|r| r.path.starts_with(root));
+        remaining = rest;
+
+        let mut cache = if args.no_cache {
+            ScanCache::disabled()
+        } else if args.rebuild_cache {
+            ScanCache::rebuild(args.state_dirs.cache.as_deref(), root)
+        } else {
+            ScanCache::load(args.state_dirs.cache.as_deref(), root)
+        };
+        if args.verbose && !cache.is_empty() {
+            say!(
+                "{}: {} ({})",
+                msg(Msg::CacheLoaded),
+                root.display(),
+                cache.len()
+            );
+        }
+
+        if args.verbose {
+            for repo in &root_repos {
+                say!(
+                    "{}: {}",
+                    msg(Msg::ScanningObjects),
+                    repo.objects_dir.display()
+                );
+            }
+        }
+        let root_repos: Vec<GitRepository> = root_repos.into_iter().cloned().collect();
+        all_objects.extend(scan_repositories_parallel(
+            &root_repos,
+            args.jobs(),
+            &mut cache,
+        ));
+
+        if let Err(e) = cache.save() {
+            eprintln!("{}: {} - {}", msg(Msg::CacheSaveFailed), root.display(), e);
+        }
+    }
+
+    // どの探索ルートにも含まれないリポジトリ (通常は無い) はキャッシュ無しで探索する
+    let remaining: Vec<GitRepository> = remaining.into_iter().cloned().collect();
+    all_objects.extend(scan_repositories_parallel(
+        &remaining,
+        args.jobs(),
+        &mut ScanCache::disabled(),
+    ));
+    all_objects
+}
+
+fn run_fsck_checks(
+    repos: &[GitRepository],
+    mode: FsckMode,
+    verbose: bool,
+    phase: &str,
+    reporter: &mut Reporter,
+) -> bool {
+    report_fsck_results(
+        repos.iter().map(|r| r.path.as_path()),
+        verbose,
+        phase,
+        reporter,
+        |repo| run_fsck(repo, mode),
+    )
+}
+
+/// post-fsckで確認する、リポジトリごとに置換したもの
+#[derive(Debug, Default)]
+struct Touched {
+    /// 置換したlooseオブジェクトのID
+    objects: Vec<String>,
+    /// 置換した組の `.pack`
+    packs: Vec<PathBuf>,
+}
+
+/// 置換したオブジェクトとpackだけを対象にpost-fsckを行う
+///
+/// packを置換したリポジトリは、`git` 方式では `git verify-pack` で、`native` 方式では
+/// `.pack` と `.idx` のチェックサムの照合でも確認する。
+fn run_touched_checks(
+    touched: &BTreeMap<PathBuf, Touched>,
+    mode: FsckMode,
+    verbose: bool,
+    reporter: &mut Reporter,
+) -> bool {
+    if verbose {
+        let objects: usize = touched.values().map(|t| t.objects.len()).sum();
+        let packs: usize = touched.values().map(|t| t.packs.len()).sum();
+        say!(
+            "{}: {} + {} packs ({} repos)",
+            msg(Msg::FsckTouchedOnly),
+            objects,
+            packs,
+            touched.len()
+        );
+    }
+    report_fsck_results(
+        touched.keys().map(PathBuf::as_path),
+        verbose,
+        "post",
+        reporter,
+        |repo| {
+            let touched = &touched[repo];
+            let result = run_object_check(repo, &touched.objects, mode);
+            if !result.success || touched.packs.is_empty() {
+                return result;
+            }
+            run_pack_check(repo, &touched.packs, mode)
+        },
+    )
+}
+
+fn report_fsck_results<'a, I, F>(
+    repos: I,
+    verbose: bool,
+    phase: &str,
+    reporter: &mut Reporter,
+    mut check: F,
+) -> bool
+where
+    I: ExactSizeIterator<Item = &'a Path>,
+    F: FnMut(&Path) -> FsckResult,
+{
+    let total = repos.len();
+    let mut failed = 0usize;
+    for repo in repos {
+        if verbose {
+            say!("{}: {}", msg(Msg::FsckRunning), repo.display());
+        }
+
+        let result = check(repo);
+        reporter.emit(fsck_event(phase, &result));
+        if result.success {
+            if verbose {
+                say!("{}: {}", msg(Msg::FsckOk), repo.display());
+            }
+        } else {
+            failed += 1;
+            let detail = if result.stderr.is_empty() {
+                format!("exit code: {:?}", result.code)
+            } else {
+                result.stderr
+            };
+            eprintln!("{}: {} - {}", msg(Msg::FsckFailed), repo.display(), detail);
+        }
+    }
+
+    say!(
+        "{}: {}/{} (failed: {})",
+        msg(Msg::FsckSummary),
+        total.saturating_sub(failed),
+        total,
+        failed
+    );
+    failed == 0
+}
+
+/// ロック取得の結果
+struct RepoLocks {
+    /// 処理するリポジトリ (ロックできたもの、`--no-lock` では全て)
+    repos: Vec<GitRepository>,
+    /// 獲得したロック (dropで解放される)
+    _locks: Vec<RepoLock>,
+    /// リポジトリごとの取得状況 (summaryの `locks`)
+    statuses: Vec<LockStatus>,
+}
+
+/// リポジトリ1つ分のロック取得状況
+struct LockStatus {
+    repo: PathBuf,
+    /// ロックを待った時間 (ミリ秒)
+    waited_ms: u64,
+    /// 取得できなかった理由 (取得できればNone)
+    error: Option<String>,
+}
+
+/// 処理対象のリポジトリをロックする
+///
+/// 使用中のロックは `--lock-timeout` まで待ち、取得できなかったリポジトリは
+/// 処理対象から除く。`--no-lock` ではロックせずに全てを返す。
+/// 複数の実行が互いのロックを待ち合わないよう、正規化したobjectsディレクトリの
+/// パス順にロックする (返すリポジトリは元の順)。
+///
+/// Returns:
+///     `--fail-on-busy` または `--all-or-nothing` で取得できないリポジトリがあった場合None
+///     (獲得したロックは解放する)
+fn lock_repositories(
+    repos: &[GitRepository],
+    args: &Args,
+    reporter: &mut Reporter,
+) -> Option<RepoLocks> {
+    if args.no_lock {
+        if args.verbose {
+            say!("{}", msg(Msg::LockSkipped));
+        }
+        return Some(RepoLocks {
+            repos: repos.to_vec(),
+            _locks: Vec::new(),
+            statuses: Vec::new(),
+        });
+    }
+
+    let mut order: Vec<usize> = (0..repos.len()).collect();
+    order.sort_by_cached_key(|&i| repos[i].store_key());
+    if args.all_or_nothing {
+        return lock_all_repositories(repos, &order, args, reporter);
+    }
+
+    let options = args.lock_options();
+    let mut results: Vec<Option<(Option<RepoLock>, LockStatus)>> =
+        (0..repos.len()).map(|_| None).collect();
+    let mut failed = 0usize;
+
+    for &i in &order {
+        let repo = &repos[i];
+        if args.verbose {
+            say!("{}: {}", msg(Msg::LockingRepo), repo.path.display());
+        }
+
+        let started = Instant::now();
+        let result = lock_repo_with_timeout(&repo.path, &options, args.lock_timeout());
+        let waited_ms = started.elapsed().as_millis() as u64;
+        let (lock, error) = match result {
+            Ok(lock) => {
+                if args.verbose {
+                    say!(
+                        "{}: {} ({} ms)",
+                        msg(Msg::LockAcquired),
+                        repo.path.display(),
+                        waited_ms
+                    );
+                }
+                (Some(lock), None)
+            }
+            Err(e) => {
+                failed += 1;
+                eprintln!("{}: {} - {}", msg(Msg::LockFailed), repo.path.display(), e);
+                (None, Some(e.to_string()))
+            }
+        };
+        reporter.emit(lock_event(&repo.path, waited_ms, error.clone()));
+        results[i] = Some((
+            lock,
+            LockStatus {
+                repo: repo.path.clone(),
+                waited_ms,
+                error,
+            },
+        ));
+    }
+
+    let mut locked = RepoLocks {
+        repos: Vec::new(),
+        _locks: Vec::new(),
+        statuses: Vec::new(),
+    };
+    for (repo, (lock, status)) in repos.iter().zip(results.into_iter().flatten()) {
+        if let Some(lock) = lock {
+            locked.repos.push(repo.clone());
+            locked._locks.push(lock);
+        }
+        locked.statuses.push(status);
+    }
+
+    say!(
+        "{}: {}/{} (failed: {})",
+        msg(Msg::LockSummary),
+        locked.repos.len(),
+        repos.len(),
+        failed
+    );
+    if failed > 0 && args.fail_on_busy {
+        eprintln!("{}", msg(Msg::AbortOnLockFailure));
+        return None;
+    }
+    Some(locked)
+}
+
+/// 全てのリポジトリをロックする (`--all-or-nothing`)
+///
+/// 1つでも使用中なら獲得済みのロックを解放して待ち直し、`--lock-timeout` までに
+/// 全てを獲得できなければ何も処理しない。
+///
+/// Args:
+///     order: ロックする順に並べた `repos` の添字
+fn lock_all_repositories(
+    repos: &[GitRepository],
+    order: &[usize],
+    args: &Args,
+    reporter: &mut Reporter,
+) -> Option<RepoLocks> {
+    let paths: Vec<&Path> = order.iter().map(|&i| repos[i].path.as_path()).collect();
+    let started = Instant::now();
+    let result = lock_all_or_nothing(&paths, &args.lock_options(), args.lock_timeout());
+    let waited_ms = started.elapsed().as_millis() as u64;
+
+    match result {
+        Ok(locks) => {
+            for repo in repos {
+                if args.verbose {
+                    say!(
+                        "{}: {} ({} ms)",
+                        msg(Msg::LockAcquired),
+                        repo.path.display(),
+                        waited_ms
+                    );
+                }
+                reporter.emit(lock_event(&repo.path, waited_ms, None));
+            }
+            say!(
+                "{}: {}/{} (failed: 0)",
+                msg(Msg::LockSummary),
+                repos.len(),
+                repos.len()
+            );
+            Some(RepoLocks {
+                repos: repos.to_vec(),
+                _locks: locks,
+                statuses: repos
+                    .iter()
+                    .map(|repo| LockStatus {
+                        repo: repo.path.clone(),
+                        waited_ms,
+                        error: None,
+                    })
+                    .collect(),
+            })
+        }
+        Err((repo, e)) => {
+            eprintln!("{}: {} - {}", msg(Msg::LockFailed), repo.display(), e);
+            reporter.emit(lock_event(&repo, waited_ms, Some(e.to_string())));
+            eprintln!("{}", msg(Msg::AbortOnAllOrNothing));
+            None
+        }
+    }
+}
+
+/// 探索ルートごとのロックを獲得する (`--tree-lock`)
+///
+/// 同じルートを対象にする実行を、探索を始める前に直列化する。`--tree-lock` が無いか
+/// `--no-lock` の場合は何もしない。
+///
+/// Returns:
+///     獲得したロック (dropで解放される)。`--lock-timeout` までに獲得できなければNone
+fn lock_trees(paths: &[String], args: &Args, reporter: &mut Reporter) -> Option<Vec<RepoLock>> {
+    if !args.tree_lock || args.no_lock {
+        return Some(Vec::new());
+    }
+
+    let mut roots: Vec<PathBuf> = paths
+        .iter()
+        .map(|path| fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path)))
+        .collect();
+    roots.sort();
+    roots.dedup();
+
+    let mut locks = Vec::new();
+    for root in roots {
+        let started = Instant::now();
+        let result = lock_tree_with_timeout(
+            args.state_dirs.cache.as_deref(),
+            &root,
+            args.lock_options().mode,
+            args.lock_timeout(),
+        );
+        let waited_ms = started.elapsed().as_millis() as u64;
+        let error = result.as_ref().err().map(|e| e.to_string());
+        reporter.emit(json!({
+            "type": "tree_lock",
+            "root": path_value(&root),
+            "ok": error.is_none(),
+            "waited_ms": waited_ms,
+            "error": error,
+        }));
+        match result {
+            Ok(lock) => {
+                if args.verbose {
+                    say!(
+                        "{}: {} ({} ms)",
+                        msg(Msg::TreeLockAcquired),
+                        root.display(),
+                        waited_ms
+                    );
+                }
+                locks.push(lock);
+            }
+            Err(e) => {
+                eprintln!("{}: {} - {}", msg(Msg::TreeLockFailed), root.display(), e);
+                return None;
+            }
+        }
+    }
+    Some(locks)
+}
+
+/// ロックできずに除外したリポジトリを表示する
+fn print_lock_failures(statuses: &[LockStatus]) {
+    for status in statuses {
+        if let Some(error) = &status.error {
+            say!(
+                "  {}: {} ({})",
+                msg(Msg::ExcludedLockFailed),
+                status.repo.display(),
+                error
+            );
+        }
+    }
+}
+
+/// summaryの `locks`
+fn lock_statuses_value(statuses: &[LockStatus]) -> Value {
+    statuses
+        .iter()
+        .map(|status| {
+            json!({
+                "repo": path_value(&status.repo),
+                "ok": status.error.is_none(),
+                "waited_ms": status.waited_ms,
+                "error": status.error,
+            })
+        })
+        .collect()
+}
+
+/// `--owner-policy normalize` では、共有するsourceを全員が読めて書き込めないモードにする
+fn normalize_source(args: &Args, path: &Path, stats: &mut Stats) {
+    if args.owner_policy != OwnerPolicy::Normalize {
+        return;
+    }
+    match normalize_mode(path) {
+        Ok(true) => {
+            stats.normalized += 1;
+            if args.verbose {
+                say!("{}: {}", msg(Msg::ModeNormalized), path.display());
+            }
+        }
+        Ok(false) => {}
+        Err(e) => {
+            stats.errors += 1;
+            eprintln!("{}: {} - {}", msg(Msg::ErrorOccurred), path.display(), e);
+        }
+    }
+}
+
+/// looseオブジェクト1件を置換する (autoではreflinkに失敗したらハードリンクに切り替える)
+fn replace_object(
+    args: &Args,
+    source: &GitObjectInfo,
+    target: &Path,
+    strategy: LinkStrategy,
+) -> ReplaceResult {
+    let result = replace_with_link_verified(source, target, args.verify, strategy);
+    if args.link_mode == LinkMode::Auto && matches!(result, ReplaceResult::ReflinkUnsupported(_)) {
+        return replace_with_link_verified(source, target, args.verify, LinkStrategy::Hardlink);
+    }
+    result
+}
+
+fn handle_replace_result(result: ReplaceResult, path: String, verbose: bool, stats: &mut Stats) {
+    match result {
+        ReplaceResult::Replaced => {
+            stats.replaced += 1;
+            if verbose {
+                say!("{}: {}", msg(Msg::Replaced), path);
+            }
+        }
+        ReplaceResult::AlreadyLinked => {
+            stats.already_linked += 1;
+            if verbose {
+                say!("{}: {}", msg(Msg::AlreadyLinked), path);
+            }
+        }
+        ReplaceResult::CrossFilesystem => {
+            stats.cross_filesystem += 1;
+            say!("{}: {}", msg(Msg::CrossFilesystem), path);
+        }
+        ReplaceResult::TooManyLinks(e) => {
+            stats.new_sources += 1;
+            if verbose {
+                say!("{}: {} - {}", msg(Msg::NewSourceForLinkLimit), path, e);
+            }
+        }
+        ReplaceResult::ReflinkUnsupported(e) => {
+            stats.reflink_unsupported += 1;
+            say!("{}: {} - {}", msg(Msg::ReflinkUnsupported), path, e);
+        }
+        ReplaceResult::RolledBack(e) => {
+            stats.errors += 1;
+            eprintln!("{}: {} - {}", msg(Msg::RollbackOccurred), path, e);
+        }
+        ReplaceResult::ContentMismatch(e) => {
+            stats.content_mismatch += 1;
+            eprintln!("{}: {} - {}", msg(Msg::ContentMismatch), path, e);
+        }
+        ReplaceResult::RollbackFailed(e) => {
+            stats.errors += 1;
+            eprintln!("{}: {} - {}", msg(Msg::RollbackFailed), path, e);
+        }
+        ReplaceResult::Error(e) => {
+            stats.errors += 1;
+            eprintln!("{}: {} - {}", msg(Msg::ErrorOccurred), path, e);
+        }
+    }
+}
+
+/// packファイルの重複を探索し、付随ファイルごとまとめて置換する
+fn process_packs(
+    repos: &[GitRepository],
+    args: &Args,
+    stats: &mut Stats,
+    journal: &mut Option<Journal>,
+    touched: &mut BTreeMap<PathBuf, Touched>,
+    reporter: &mut Reporter,
+) {
+    let packs: Vec<_> = repos.iter().flat_map(scan_repository_packs).collect();
+    if args.verbose {
+        say!("\n{}: {}", msg(Msg::FoundPacks), packs.len());
+    }
+
+    let groups = find_duplicate_packs(packs);
+    if args.verbose {
+        say!("{}: {}", msg(Msg::FoundDuplicatePacks), groups.len());
+    }
+
+    let mut strategies: HashMap<u64, LinkStrategy> = HashMap::new();
+    for group in &groups {
+        if interrupted() {
+            return;
+        }
+        // packは組を分け直さず、sourceと所有者・モードが異なるduplicateを共有しない
+        let source_owner = Ownership::of(&group.source.path).ok();
+        let differs = |dup: &PackInfo| {
+            source_owner.is_none() || Ownership::of(&dup.path).ok() != source_owner
+        };
+        let refused =
+            args.owner_policy == OwnerPolicy::Refuse && group.duplicates.iter().any(differs);
+        reporter.emit(json!({
+            "type": "group",
+            "kind": "pack",
+            "device": group.source.device,
+            "format": group.source.format.as_str(),
+            "hash": group.source.name_hash,
+            "size": group.source.size,
+            "source": path_value(&group.source.path),
+            "duplicates": group.duplicates.iter().map(|d| path_value(&d.path)).collect::<Vec<_>>(),
+        }));
+        stats.total_duplicates += group.duplicates.len();
+        for dup in &group.duplicates {
+            if interrupted() {
+                return;
+            }
+            if args.owner_policy != OwnerPolicy::Normalize && differs(dup) {
+                stats.owner_mismatch += 1;
+                if args.verbose {
+                    say!("{}: {}", msg(Msg::OwnerMismatch), dup.path.display());
+                }
+                continue;
+            }
+            // refuseでは、sourceと同じ所有者・モードのduplicateも共有しない (数えない)
+            if refused {
+                continue;
+            }
+            let pairs = match group.link_pairs(dup) {
+                Ok(pairs) => pairs,
+                Err(e) => {
+                    let result = ReplaceResult::Error(e.to_string());
+                    reporter.emit(replace_event(
+                        "pack",
+                        &group.source.path,
+                        &dup.path,
+                        &result,
+                    ));
+                    handle_replace_result(
+                        result,
+                        dup.path.display().to_string(),
+                        args.verbose,
+                        stats,
+                    );
+                    continue;
+                }
+            };
+            stats.estimated_savings += pack_set_size(&pairs);
+
+            if args.dry_run {
+                if args.verbose {
+                    print_pack_pair(group, dup, pairs.len());
+                }
+                continue;
+            }
+            if let Err(result) = verify_pack_before_replace(&group.source, dup, args.verify) {
+                reporter.emit(replace_event(
+                    "pack",
+                    &group.source.path,
+                    &dup.path,
+                    &result,
+                ));
+                handle_replace_result(result, dup.path.display().to_string(), args.verbose, stats);
+                continue;
+            }
+
+            let pending: Vec<_> = pairs
+                .iter()
+                .filter(|(source, target)| !is_same_inode(source, target).unwrap_or(false))
+                .collect();
+            let entries: Vec<_> = pending
+                .iter()
+                .filter_map(|(source, target)| JournalEntry::capture(source, target).ok())
+                .collect();
+            let usages: Vec<_> = pending
+                .iter()
+                .filter_map(|(_, target)| InodeUsage::of(target).ok())
+                .collect();
+            let strategy = *strategies.entry(group.source.device).or_insert_with(|| {
+                match group.source.path.parent() {
+                    Some(dir) => args.link_mode.resolve(dir),
+                    None => LinkStrategy::Hardlink,
+                }
+            });
+            for (source, _) in &pairs {
+                normalize_source(args, source, stats);
+            }
+            let mut result = replace_set_with_links(&pairs, strategy);
+            if args.link_mode == LinkMode::Auto
+                && matches!(result, ReplaceResult::ReflinkUnsupported(_))
+            {
+                result = replace_set_with_links(&pairs, LinkStrategy::Hardlink);
+            }
+            if result == ReplaceResult::Replaced {
+                for entry in entries {
+                    record_journal(journal, Some(entry));
+                }
+                for usage in usages {
+                    stats.freed.record_unlinked(usage);
+                }
+                for backup in leftover_backups(&pairs) {
+                    eprintln!("{}: {}", msg(Msg::BackupLeftover), backup.display());
+                }
+                let objects_dir = dup.path.parent().and_then(Path::parent);
+                if let Some(repo) = repos
+                    .iter()
+                    .find(|r| Some(r.objects_dir.as_path()) == objects_dir)
+                {
+                    touched
+                        .entry(repo.path.clone())
+                        .or_default()
+                        .packs
+                        .push(dup.path.clone());
+                }
+            }
+            reporter.emit(replace_event(
+                "pack",
+                &group.source.path,
+                &dup.path,
+                &result,
+            ));
+            handle_replace_result(result, dup.path.display().to_string(), args.verbose, stats);
+        }
+    }
+}
+
+/// 置換に成功した組をjournalに追記する
+fn record_journal(journal: &mut Option<Journal>, entry: Option<JournalEntry>) {
+    let (Some(journal), Some(entry)) = (journal.as_mut(), entry) else {
+        return;
+    };
+    if let Err(e) = journal.record(&entry) {
+        eprintln!(
+            "{}: {} - {}",
+            msg(Msg::JournalWriteFailed),
+            journal.path().display(),
+            e
+        );
+    }
+}
+
+/// journalの記録を新しいものから順に元に戻す (`restore` サブコマンド)
+///
+/// Returns:
+///     終了コード (0: 成功, 1: journalが読めない, 4: 復元に失敗したファイルあり)
+fn run_restore(args: &Args, journal: Option<&Path>, reporter: &mut Reporter) -> i32 {
+    let latest = || args.state_dirs.journal.as_deref().and_then(latest_journal);
+    let Some(path) = journal.map(Path::to_path_buf).or_else(latest) else {
+        eprintln!("{}", msg(Msg::JournalNotFound));
+        return 1;
+    };
+    let entries = match read_journal(&path) {
+        Ok(entries) => entries,
+        Err(e) => {
+            eprintln!(
+                "{}: {} - {}",
+                msg(Msg::JournalReadFailed),
+                path.display(),
+                e
+            );
+            return 1;
+        }
+    };
+    say!(
+        "{}: {} ({})",
+        msg(Msg::Restoring),
+        path.display(),
+        entries.len()
+    );
+
+    // 記録されたファイルのリポジトリ (objects/xx/.. と objects/pack/.. の親) をロックする
+    let mut repos: Vec<GitRepository> = entries
+        .iter()
+        .filter_map(|e| e.target.parent()?.parent()?.parent())
+        .filter_map(resolve_repository)
+        .collect();
+    repos.sort_by(|a, b| a.path.cmp(&b.path));
+    repos.dedup_by(|a, b| a.objects_dir == b.objects_dir);
+    let Some(locked) = lock_repositories(&repos, args, reporter) else {
+        return EXIT_LOCK_FAILED;
+    };
+    let locked_repos = locked.repos;
+
+    let mut stats = UnshareStats::default();
+    for entry in entries.iter().rev() {
+        if interrupted() {
+            break;
+        }
+        let objects_dir = entry.target.parent().and_then(Path::parent);
+        if !locked_repos
+            .iter()
+            .any(|r| Some(r.objects_dir.as_path()) == objects_dir)
+        {
+            stats.skipped += 1;
+            continue;
+        }
+
+        if args.dry_run {
+            if is_same_inode(&entry.source, &entry.target).unwrap_or(false) {
+                stats.unshared += 1;
+                reporter.emit(planned_event("restore", &entry.target));
+                if args.verbose {
+                    say!("  [restore] {}", entry.target.display());
+                }
+            } else {
+                stats.skipped += 1;
+            }
+            continue;
+        }
+
+        let result = restore_entry(entry);
+        reporter.emit(unshare_event("restore", &entry.target, &result));
+        handle_unshare_result(
+            result,
+            &entry.target,
+            Msg::Restored,
+            args.verbose,
+            &mut stats,
+        );
+    }
+
+    print_unshare_summary(
+        args,
+        "restore",
+        Msg::RestoreSummary,
+        Msg::TotalRestored,
+        &stats,
+        reporter,
+    )
+}
+
+/// 1つのリポジトリのオブジェクトのハードリンクを解除する (`unshare` サブコマンド)
+///
+/// looseオブジェクトとpack (付随ファイルを含む) のうち、リンク数が2以上のものを
+/// 独立したコピーに置き換える。
+///
+/// Returns:
+///     終了コード (0: 成功, 1: リポジトリが見つからない, 4: ロック取得・解除に失敗)
+fn run_unshare(args: &Args, path: &Path, reporter: &mut Reporter) -> i32 {
+    let Some(repo) = resolve_repository(path) else {
+        eprintln!("{}: {}", msg(Msg::RepositoryNotFound), path.display());
+        return 1;
+    };
+    let Some(locked) = lock_repositories(std::slice::from_ref(&repo), args, reporter) else {
+        return EXIT_LOCK_FAILED;
+    };
+    if locked.repos.is_empty() {
+        return 4;
+    }
+
+    let mut files: Vec<PathBuf> = scan_repository_objects(&repo)
+        .into_iter()
+        .map(|o| o.path)
+        .collect();
+    for pack in scan_repository_packs(&repo) {
+        files.extend(pack.companions());
+        files.push(pack.path);
+    }
+    let shared: Vec<_> = files
+        .into_iter()
+        .filter(|f| link_count(f).is_ok_and(|n| n > 1))
+        .collect();
+    say!(
+        "{}: {} ({})",
+        msg(Msg::UnshareTarget),
+        repo.objects_dir.display(),
+        shared.len()
+    );
+
+    let mut stats = UnshareStats::default();
+    for file in &shared {
+        if interrupted() {
+            break;
+        }
+        if args.dry_run {
+            stats.unshared += 1;
+            reporter.emit(planned_event("unshare", file));
+            if args.verbose {
+                say!("  [unshare] {}", file.display());
+            }
+            continue;
+        }
+        let result = break_hardlink(file, None, None);
+        reporter.emit(unshare_event("unshare", file, &result));
+        handle_unshare_result(result, file, Msg::Unshared, args.verbose, &mut stats);
+    }
+
+    print_unshare_summary(
+        args,
+        "unshare",
+        Msg::UnshareSummary,
+        Msg::TotalUnshared,
+        &stats,
+        reporter,
+    )
+}
+
+/// restore/unshareの集計
+#[derive(Default)]
+struct UnshareStats {
+    unshared: usize,
+    skipped: usize,
+    errors: usize,
+}
+
+fn handle_unshare_result(
+    result: UnshareResult,
+    path: &Path,
+    done: Msg,
+    verbose: bool,
+    stats: &mut UnshareStats,
+) {
+    match result {
+        UnshareResult::Unshared => {
+            stats.unshared += 1;
+            if verbose {
+                say!("{}: {}", msg(done), path.display());
+            }
+        }
+        UnshareResult::NotShared => {
+            stats.skipped += 1;
+            if verbose {
+                say!("{}: {}", msg(Msg::RestoreSkipped), path.display());
+            }
+        }
+        UnshareResult::RolledBack(e) => {
+            stats.errors += 1;
+            eprintln!("{}: {} - {}", msg(Msg::RollbackOccurred), path.display(), e);
+        }
+        UnshareResult::RollbackFailed(e) => {
+            stats.errors += 1;
+            eprintln!("{}: {} - {}", msg(Msg::RollbackFailed), path.display(), e);
+        }
+        UnshareResult::Error(e) => {
+            stats.errors += 1;
+            eprintln!("{}: {} - {}", msg(Msg::ErrorOccurred), path.display(), e);
+        }
+    }
+}
+
+fn print_unshare_summary(
+    args: &Args,
+    command: &str,
+    title: Msg,
+    done: Msg,
+    stats: &UnshareStats,
+    reporter: &mut Reporter,
+) -> i32 {
+    reporter.emit(json!({
+        "type": "summary",
+        "command": command,
+        "dry_run": args.dry_run,
+        "unshared": stats.unshared,
+        "skipped": stats.skipped,
+        "errors": stats.errors,
+        "interrupted": interrupted(),
+    }));
+    say!();
+    if args.dry_run {
+        say!("{}", msg(Msg::SummaryDryRun));
+    } else {
+        say!("{}", msg(title));
+    }
+    say!("  {}: {}", msg(done), stats.unshared);
+    say!("  {}: {}", msg(Msg::TotalSkipped), stats.skipped);
+    if stats.errors > 0 {
+        say!("  {}: {}", msg(Msg::TotalErrors), stats.errors);
+    }
+    if interrupted() {
+        return interrupted_exit();
+    }
+    if stats.errors > 0 {
+        return 4;
+    }
+    0
+}
+
+/// dry-runで処理予定のファイルのイベント
+fn planned_event(command: &str, target: &Path) -> Value {
+    json!({
+        "type": command,
+        "target": path_value(target),
+        "result": "planned",
+        "detail": null,
+    })
+}
+
+fn stats_value(stats: &Stats) -> Value {
+    json!({
+        "total_duplicates": stats.total_duplicates,
+        "replaced": stats.replaced,
+        "already_linked": stats.already_linked,
+        "cross_filesystem": stats.cross_filesystem,
+        "content_mismatch": stats.content_mismatch,
+        "reflink_unsupported": stats.reflink_unsupported,
+        "new_sources": stats.new_sources,
+        "owner_mismatch": stats.owner_mismatch,
+        "normalized": stats.normalized,
+        "pool_added": stats.pool_added,
+        "errors": stats.errors,
+        "total_savings": stats.estimated_savings,
+        "actual_savings": stats.freed.freed(),
+    })
+}
+
+fn pack_set_size(pairs: &[(PathBuf, PathBuf)]) -> u64 {
+    pairs
+        .iter()
+        .filter_map(|(_, target)| std::fs::metadata(target).ok())
+        .map(|m| m.len())
+        .sum()
+}
+
+fn print_pack_pair(group: &PackGroup, dup: &PackInfo, files: usize) {
+    say!(
+        "\n{}: {} ({} files)",
+        msg(Msg::DuplicatePack),
+        group.source.name_hash,
+        files
+    );
+    say!(
+        "  [source] {} ({})",
+        group.source.path.display(),
+        format_size(group.source.size)
+    );
+    say!("  [dup]    {}", dup.path.display());
+}
+
+fn print_summary(args: &Args, stats: &Stats, pack_stats: Option<&Stats>) {
+    say!();
+    if args.dry_run {
+        say!("{}", msg(Msg::SummaryDryRun));
+    } else {
+        say!("{}", msg(Msg::SummaryComplete));
+    }
+    print_stats(args, stats);
+
+    if let Some(pack_stats) = pack_stats {
+        say!("{}", msg(Msg::PackSummary));
+        print_stats(args, pack_stats);
+    }
+}
+
+fn print_stats(args: &Args, stats: &Stats) {
+    if args.dry_run {
+        say!(
+            "  {}: {}",
+            msg(Msg::TotalDuplicates),
+            stats.total_duplicates
+        );
+        if stats.owner_mismatch > 0 {
+            say!(
+                "  {}: {}",
+                msg(Msg::TotalOwnerMismatch),
+                stats.owner_mismatch
+            );
+        }
+        if args.pool {
+            say!("  {}: {}", msg(Msg::TotalPoolAdded), stats.pool_added);
+        }
+        say!(
+            "  {}: {}",
+            msg(Msg::EstimatedSavings),
+            format_size(stats.estimated_savings)
+        );
+        return;
+    }
+
+    say!(
+        "  {}: {}",
+        msg(Msg::TotalDuplicates),
+        stats.total_duplicates
+    );
+    say!("  {}: {}", msg(Msg::TotalReplaced), stats.replaced);
+    let skipped = stats.already_linked + stats.cross_filesystem;
+    say!("  {}: {}", msg(Msg::TotalSkipped), skipped);
+    if stats.content_mismatch > 0 {
+        say!(
+            "  {}: {}",
+            msg(Msg::TotalContentMismatch),
+            stats.content_mismatch
+        );
+    }
+    if stats.reflink_unsupported > 0 {
+        say!(
+            "  {}: {}",
+            msg(Msg::TotalReflinkUnsupported),
+            stats.reflink_unsupported
+        );
+    }
+    if stats.new_sources > 0 {
+        say!("  {}: {}", msg(Msg::TotalNewSources), stats.new_sources);
+    }
+    if stats.owner_mismatch > 0 {
+        say!(
+            "  {}: {}",
+            msg(Msg::TotalOwnerMismatch),
+            stats.owner_mismatch
+        );
+    }
+    if stats.normalized > 0 {
+        say!("  {}: {}", msg(Msg::TotalNormalized), stats.normalized);
+    }
+    if args.pool {
+        say!("  {}: {}", msg(Msg::TotalPoolAdded), stats.pool_added);
+    }
+    if stats.errors > 0 {
+        say!("  {}: {}", msg(Msg::TotalErrors), stats.errors);
+    }
+    say!(
+        "  {}: {}",
+        msg(Msg::EstimatedSavings),
+        format_size(stats.estimated_savings)
+    );
+    say!(
+        "  {}: {}",
+        msg(Msg::ActualSavings),
+        format_size(stats.freed.freed())
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::alternates::alternates_file;
+    use crate::dirs::StateDirs;
+    use crate::fsck::run_git_fsck;
+    use crate::report::OutputFormat;
+    use clap::Parser;
+    use std::os::unix::fs::MetadataExt;
+    use std::process;
+    use tempfile::TempDir;
+
+    fn git(args: &[&str], dir: &Path) {
+        let status = process::Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_NAME", "test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com")
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    /// 同じコミットを持つ (looseオブジェクトが同じ) リポジトリを `names` の数だけ作る
+    fn repos_with_same_objects(root: &Path, names: &[&str]) -> Vec<PathBuf> {
+        let first = root.join(names[0]);
+        fs::create_dir_all(&first).unwrap();
+        git(&["init", "-q"], &first);
+        fs::write(first.join("a.txt"), "alpha\n").unwrap();
+        fs::write(first.join("b.txt"), "beta\n").unwrap();
+        git(&["add", "."], &first);
+        git(&["commit", "-q", "-m", "init"], &first);
+        for name in &names[1..] {
+            let status = process::Command::new("cp")
+                .arg("-a")
+                .arg(&first)
+                .arg(root.join(name))
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        names.iter().map(|name| root.join(name)).collect()
+    }
+
+    /// JSON形式でサブコマンドを実行し、終了コードとイベントを返す
+    ///
+    /// キャッシュ・journal・ロックは利用者のものではなく、テスト用の一時ディレクトリに置く。
+    fn run_json(argv: &[&str]) -> (i32, Vec<Value>) {
+        static STATE: std::sync::OnceLock<TempDir> = std::sync::OnceLock::new();
+        let state = STATE.get_or_init(|| TempDir::new().unwrap());
+        let mut args = Args::parse_from(["git-share-obj", "--format", "json"].iter().chain(argv));
+        args.state_dirs = StateDirs::under(state.path());
+        let mut reporter = Reporter::new(OutputFormat::Json);
+        let code = run_command(&args, &mut reporter);
+        (code, reporter.events().to_vec())
+    }
+
+    fn summary(events: &[Value]) -> &Value {
+        events
+            .iter()
+            .rev()
+            .find(|e| e["type"] == "summary")
+            .expect("summary event")
+    }
+
+    fn loose_objects(repo: &Path) -> Vec<GitObjectInfo> {
+        let mut objects = scan_repository_objects(&resolve_repository(repo).unwrap());
+        objects.sort_by(|a, b| a.hash.cmp(&b.hash));
+        objects
+    }
+
+    /// `repo` の `hash` のlooseオブジェクトのパス
+    fn object_path(repo: &Path, hash: &str) -> PathBuf {
+        repo.join(".git/objects").join(&hash[..2]).join(&hash[2..])
+    }
+
+    /// `b` のlooseオブジェクトのうち、`a` の同じオブジェクトとinodeを共有する数
+    fn shared_count(a: &Path, b: &Path) -> usize {
+        loose_objects(b)
+            .iter()
+            .filter(|obj| is_same_inode(&obj.path, &object_path(a, &obj.hash)).unwrap_or(false))
+            .count()
+    }
+
+    /// looseオブジェクトのパスと中身
+    fn snapshot(repo: &Path) -> Vec<(PathBuf, Vec<u8>)> {
+        loose_objects(repo)
+            .into_iter()
+            .map(|obj| {
+                let content = fs::read(&obj.path).unwrap();
+                (obj.path, content)
+            })
+            .collect()
+    }
+
+    /// 中身が変わらず、他のファイルとリンクしていない
+    fn assert_independent(snapshot: &[(PathBuf, Vec<u8>)]) {
+        for (path, content) in snapshot {
+            assert_eq!(&fs::read(path).unwrap(), content, "{}", path.display());
+            assert_eq!(fs::metadata(path).unwrap().nlink(), 1, "{}", path.display());
+        }
+    }
+
+    fn path_str(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    #[test]
+    fn test_share_and_restore() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["a", "b"]);
+        let root = path_str(temp_dir.path());
+        let before = snapshot(&repos[1]);
+        assert_eq!(before.len(), 4);
+        let inodes: Vec<u64> = before
+            .iter()
+            .map(|(path, _)| fs::metadata(path).unwrap().ino())
+            .collect();
+
+        // dry-runでは何も変えない
+        let (code, events) = run_json(&["-n", "--no-cache", root]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["loose"]["total_duplicates"], before.len());
+        assert_eq!(summary(&events)["journal"], Value::Null);
+        assert_independent(&before);
+
+        let (code, events) = run_json(&["--no-cache", root]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["loose"]["replaced"], before.len());
+        assert_eq!(shared_count(&repos[0], &repos[1]), before.len());
+        assert!(events
+            .iter()
+            .any(|e| e["type"] == "fsck" && e["phase"] == "post" && e["ok"] == true));
+        // journalには置換したbのファイルと置換前のinodeを記録する
+        let journal = summary(&events)["journal"].as_str().unwrap().to_string();
+        let mut entries = read_journal(Path::new(&journal)).unwrap();
+        entries.sort_by(|a, b| a.target.cmp(&b.target));
+        assert_eq!(entries.len(), before.len());
+        for ((entry, (path, _)), inode) in entries.iter().zip(&before).zip(&inodes) {
+            assert_eq!(&entry.target, path);
+            assert!(entry.source.starts_with(&repos[0]));
+            assert_eq!(entry.inode, *inode);
+        }
+
+        // 2回目はリンク済みのものを重複として数えない
+        let (code, events) = run_json(&["--no-cache", root]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["loose"]["total_duplicates"], 0);
+
+        let (code, events) = run_json(&["restore", &journal]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["unshared"], before.len());
+        assert_independent(&before);
+        for repo in &repos {
+            assert!(run_git_fsck(repo).success);
+        }
+
+        // journalが読めなければ1
+        let missing = temp_dir.path().join("missing.journal");
+        assert_eq!(run_json(&["restore", path_str(&missing)]).0, 1);
+    }
+
+    #[test]
+    fn test_unshare() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["a", "b"]);
+        let before = snapshot(&repos[1]);
+        assert_eq!(run_json(&["--no-cache", path_str(temp_dir.path())]).0, 0);
+        assert_eq!(shared_count(&repos[0], &repos[1]), before.len());
+
+        // ロックできなければ何もせず4
+        let lock = crate::lock::try_lock_repo(&repos[1], &Default::default()).unwrap();
+        assert_eq!(run_json(&["unshare", path_str(&repos[1])]).0, 4);
+        assert_eq!(shared_count(&repos[0], &repos[1]), before.len());
+        drop(lock);
+
+        let (code, events) = run_json(&["unshare", "-n", path_str(&repos[1])]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["unshared"], before.len());
+        assert_eq!(shared_count(&repos[0], &repos[1]), before.len());
+
+        let (code, events) = run_json(&["unshare", path_str(&repos[1])]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["unshared"], before.len());
+        assert_independent(&before);
+        // aのファイルはそのまま残る
+        assert_independent(&snapshot(&repos[0]));
+        for repo in &repos {
+            assert!(run_git_fsck(repo).success);
+        }
+
+        assert_eq!(run_json(&["unshare", path_str(temp_dir.path())]).0, 1);
+    }
+
+    #[test]
+    fn test_share_with_pool_and_pool_gc() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["a", "b"]);
+        let pool_dir = temp_dir.path().join("pool");
+        fs::create_dir_all(&pool_dir).unwrap();
+        let pool = ObjectPool::new(pool_dir.clone());
+        let objects = loose_objects(&repos[0]);
+        let before = snapshot(&repos[0]);
+
+        let (code, events) = run_json(&[
+            "--pool",
+            "--pool-dir",
+            path_str(&pool_dir),
+            "--no-cache",
+            path_str(&repos[0]),
+            path_str(&repos[1]),
+        ]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["loose"]["pool_added"], objects.len());
+        assert_eq!(summary(&events)["loose"]["cross_filesystem"], 0);
+        // 全てのリポジトリがプールのエントリとinodeを共有し、中身は変わらない
+        for (obj, (_, content)) in objects.iter().zip(&before) {
+            let entry = pool.entry_path(obj.format, &obj.hash);
+            assert_eq!(&fs::read(&entry).unwrap(), content);
+            for repo in &repos {
+                assert!(is_same_inode(&entry, &object_path(repo, &obj.hash)).unwrap());
+            }
+        }
+        for repo in &repos {
+            assert!(run_git_fsck(repo).success);
+        }
+
+        // リポジトリから参照されている間は消さない
+        let pool_dir = path_str(&pool_dir);
+        let (code, events) = run_json(&["pool", "gc", "--pool-dir", pool_dir]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["kept"], objects.len());
+        assert_eq!(summary(&events)["removed"], 0);
+        for obj in &objects {
+            assert!(pool.entry_path(obj.format, &obj.hash).exists());
+        }
+
+        for repo in &repos {
+            fs::remove_dir_all(repo).unwrap();
+        }
+        let (code, events) = run_json(&["pool", "gc", "-n", "--pool-dir", pool_dir]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["removed"], objects.len());
+        for obj in &objects {
+            assert!(pool.entry_path(obj.format, &obj.hash).exists());
+        }
+
+        let (code, events) = run_json(&["pool", "gc", "--pool-dir", pool_dir]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["removed"], objects.len());
+        for obj in &objects {
+            assert!(!pool.entry_path(obj.format, &obj.hash).exists());
+        }
+
+        let missing = temp_dir.path().join("missing");
+        assert_eq!(
+            run_json(&["pool", "gc", "--pool-dir", path_str(&missing)]).0,
+            1
+        );
+    }
+
+    /// Gitからオブジェクトを読めるか
+    fn git_has_object(repo: &Path, hash: &str) -> bool {
+        process::Command::new("git")
+            .args(["cat-file", "-e", hash])
+            .current_dir(repo)
+            .status()
+            .unwrap()
+            .success()
+    }
+
+    #[test]
+    fn test_alternates() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["a", "b"]);
+        let store_dir = temp_dir.path().join("store");
+        let objects = loose_objects(&repos[0]);
+        let store = path_str(&store_dir);
+        let root = path_str(temp_dir.path());
+
+        let (code, events) = run_json(&["alternates", "-n", "--store", store, root]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["stored"], objects.len());
+        assert_eq!(loose_objects(&repos[1]).len(), objects.len());
+        assert!(!alternates_file(&repos[0].join(".git/objects")).exists());
+
+        let (code, events) = run_json(&["alternates", "--store", store, root]);
+        assert_eq!(code, 0);
+        let summary = summary(&events);
+        assert_eq!(summary["stored"], objects.len());
+        assert_eq!(summary["linked"], repos.len());
+        assert_eq!(summary["removed"], objects.len() * repos.len());
+        let store = ObjectStore::open(&store_dir).unwrap();
+        for obj in &objects {
+            assert!(store.contains(&obj.hash));
+        }
+        // ローカルのコピーを消しても、alternates経由で全てのオブジェクトを読める
+        for repo in &repos {
+            assert!(loose_objects(repo).is_empty());
+            assert!(store.is_linked(&repo.join(".git/objects")));
+            for obj in &objects {
+                assert!(git_has_object(repo, &obj.hash));
+            }
+            assert!(run_git_fsck(repo).success);
+        }
+
+        // ストアがリポジトリのobjectsディレクトリ配下にあるものは追加しない
+        let nested = temp_dir.path().join("nested");
+        let nested_repo = &repos_with_same_objects(&nested, &["c"])[0];
+        let nested_store = nested_repo.join(".git/objects/shared");
+        let (code, events) = run_json(&[
+            "alternates",
+            "--store",
+            path_str(&nested_store),
+            path_str(&nested),
+        ]);
+        assert_eq!(code, 4);
+        assert!(events
+            .iter()
+            .any(|e| e["type"] == "alternates" && e["result"] == "rejected"));
+        assert!(!alternates_file(&nested_repo.join(".git/objects")).exists());
+        // 追加できなかったリポジトリのローカルのコピーは消さない
+        assert_eq!(loose_objects(nested_repo).len(), objects.len());
+        assert!(run_git_fsck(nested_repo).success);
+    }
+
+    #[test]
+    fn test_share_packs_and_check_them_after() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = repos_with_same_objects(temp_dir.path(), &["a"]).remove(0);
+        git(&["repack", "-a", "-d", "-q"], &first);
+        let second = temp_dir.path().join("b");
+        let status = process::Command::new("cp")
+            .arg("-a")
+            .arg(&first)
+            .arg(&second)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let repos = [first, second];
+        let packs: Vec<_> = repos
+            .iter()
+            .map(|repo| scan_repository_packs(&resolve_repository(repo).unwrap())[0].clone())
+            .collect();
+        let content = fs::read(&packs[1].path).unwrap();
+
+        let (code, events) = run_json(&["--packs", "--no-cache", path_str(temp_dir.path())]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["packs"]["replaced"], 1);
+        assert!(is_same_inode(&packs[0].path, &packs[1].path).unwrap());
+        assert_eq!(fs::read(&packs[1].path).unwrap(), content);
+        for companion in packs[1].companions() {
+            let source = packs[0].path.with_extension(companion.extension().unwrap());
+            assert!(is_same_inode(&source, &companion).unwrap());
+        }
+        // packだけを置換したリポジトリもpost-fsckの対象にする
+        let post: Vec<_> = events
+            .iter()
+            .filter(|e| e["type"] == "fsck" && e["phase"] == "post")
+            .collect();
+        assert_eq!(post.len(), 1);
+        assert_eq!(post[0]["repo"], path_str(&repos[1]));
+        assert_eq!(post[0]["ok"], true);
+        for repo in &repos {
+            assert!(run_git_fsck(repo).success);
+        }
+    }
+
+    #[test]
+    fn test_share_packs_verifies_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = repos_with_same_objects(temp_dir.path(), &["a"]).remove(0);
+        git(&["repack", "-a", "-d", "-q"], &first);
+        let second = temp_dir.path().join("b");
+        let status = process::Command::new("cp")
+            .arg("-a")
+            .arg(&first)
+            .arg(&second)
+            .status()
+            .unwrap();
+        assert!(status.success());
+        let packs: Vec<_> = [&first, &second]
+            .iter()
+            .map(|repo| {
+                scan_repository_packs(&resolve_repository(repo).unwrap())[0]
+                    .path
+                    .clone()
+            })
+            .collect();
+        // bのpackの本体だけを壊し、末尾のチェックサムは残す
+        let mut corrupted = fs::read(&packs[1]).unwrap();
+        let middle = corrupted.len() / 2;
+        corrupted[middle] ^= 0xff;
+        let overwrite = |path: &Path| {
+            let permissions = fs::metadata(path).unwrap().permissions();
+            fs::remove_file(path).unwrap();
+            fs::write(path, &corrupted).unwrap();
+            fs::set_permissions(path, permissions).unwrap();
+        };
+        overwrite(&packs[1]);
+        let root = path_str(temp_dir.path());
+
+        let (code, events) = run_json(&[
+            "--packs",
+            "--verify",
+            "content",
+            "--no-fsck",
+            "--no-cache",
+            root,
+        ]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["packs"]["replaced"], 0);
+        assert_eq!(summary(&events)["packs"]["content_mismatch"], 1);
+        assert!(!is_same_inode(&packs[0], &packs[1]).unwrap());
+        assert_eq!(fs::read(&packs[1]).unwrap(), corrupted);
+
+        // 同じように壊れたpackどうしはハッシュの再計算で検出する
+        overwrite(&packs[0]);
+        let (code, events) = run_json(&[
+            "--packs",
+            "--verify",
+            "hash",
+            "--no-fsck",
+            "--no-cache",
+            root,
+        ]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["packs"]["replaced"], 0);
+        assert_eq!(summary(&events)["packs"]["content_mismatch"], 1);
+        assert!(!is_same_inode(&packs[0], &packs[1]).unwrap());
+    }
+
+    #[test]
+    fn test_native_pre_fsck_checks_packs() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["a", "b"]);
+        for repo in &repos {
+            git(&["repack", "-a", "-d", "-q"], repo);
+        }
+        let root = path_str(temp_dir.path());
+        let pack = scan_repository_packs(&resolve_repository(&repos[1]).unwrap())[0].clone();
+        let mut content = fs::read(&pack.path).unwrap();
+        let middle = content.len() / 2;
+        content[middle] ^= 0xff;
+        let permissions = fs::metadata(&pack.path).unwrap().permissions();
+        fs::remove_file(&pack.path).unwrap();
+        fs::write(&pack.path, &content).unwrap();
+        fs::set_permissions(&pack.path, permissions).unwrap();
+
+        // 壊れたpackはpre-fsckで検出し、何も置換しない
+        let (code, events) = run_json(&["--packs", "--fsck-mode", "native", "--no-cache", root]);
+        assert_eq!(code, 2);
+        assert!(events.iter().any(|e| e["type"] == "fsck"
+            && e["phase"] == "pre"
+            && e["repo"] == path_str(&repos[1])
+            && e["ok"] == false));
+        assert!(!is_same_inode(
+            &pack.path,
+            &repos[0].join(pack.path.strip_prefix(&repos[1]).unwrap())
+        )
+        .unwrap());
+    }
+
+    #[test]
+    fn test_share_rotates_source_at_link_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        // 上限が分からない・大きすぎるファイルシステムでは上限まで埋められない
+        let Some(max_links) = link_max(temp_dir.path()).filter(|&max| max <= 100_000) else {
+            return;
+        };
+        let repos = repos_with_same_objects(temp_dir.path(), &["a", "b", "c", "d"]);
+        let output = process::Command::new("git")
+            .args(["rev-parse", "HEAD:a.txt"])
+            .current_dir(&repos[0])
+            .output()
+            .unwrap();
+        let hash = String::from_utf8(output.stdout).unwrap().trim().to_string();
+        let blob = |repo: &Path| object_path(repo, &hash);
+
+        // aのblobをリンク数の上限まであと1つの状態にする
+        let scratch = temp_dir.path().join("scratch");
+        fs::create_dir_all(&scratch).unwrap();
+        for i in 0..max_links - 2 {
+            fs::hard_link(blob(&repos[0]), scratch.join(i.to_string())).unwrap();
+        }
+        assert_eq!(link_count(&blob(&repos[0])).unwrap(), max_links - 1);
+
+        let (code, events) = run_json(&[
+            "--no-cache",
+            path_str(&repos[0]),
+            path_str(&repos[1]),
+            path_str(&repos[2]),
+            path_str(&repos[3]),
+        ]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["loose"]["new_sources"], 1);
+        assert_eq!(summary(&events)["loose"]["errors"], 0);
+        // bまででaのinodeが上限に達し、dはcを新しいsourceとして共有する
+        assert!(is_same_inode(&blob(&repos[0]), &blob(&repos[1])).unwrap());
+        assert!(!is_same_inode(&blob(&repos[0]), &blob(&repos[2])).unwrap());
+        assert!(is_same_inode(&blob(&repos[2]), &blob(&repos[3])).unwrap());
+        assert_eq!(link_count(&blob(&repos[0])).unwrap(), max_links);
+        assert_eq!(link_count(&blob(&repos[2])).unwrap(), 2);
+        for repo in &repos {
+            assert!(run_git_fsck(repo).success);
+        }
+    }
+
+    /// objects配下のディレクトリの更新時刻と、その中のファイル
+    ///
+    /// 作ってすぐ消したファイルも、ディレクトリの更新時刻で分かる。
+    fn object_files(repo: &Path) -> Vec<(PathBuf, std::time::SystemTime)> {
+        let mut files: Vec<_> = fs::read_dir(repo.join(".git/objects"))
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+            .flat_map(|dir| {
+                let modified = dir.metadata().unwrap().modified().unwrap();
+                let files = fs::read_dir(dir.path()).unwrap().map(|e| e.unwrap().path());
+                files.chain([dir.path()]).map(move |path| (path, modified))
+            })
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn test_auto_link_mode_probe_files() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["a", "b"]);
+        let root = path_str(temp_dir.path());
+        let before: Vec<_> = repos.iter().map(|repo| object_files(repo)).collect();
+
+        // dry-runではreflinkを試すファイルも書かない
+        let (code, _) = run_json(&["-n", "--link-mode", "auto", "--no-cache", root]);
+        assert_eq!(code, 0);
+        let after: Vec<_> = repos.iter().map(|repo| object_files(repo)).collect();
+        assert_eq!(after, before);
+
+        // 中断で残った試しのファイルは起動時に片付ける
+        let dir = loose_objects(&repos[0])[0]
+            .path
+            .parent()
+            .unwrap()
+            .to_path_buf();
+        let probe = dir.join(format!("{}999999", crate::hardlink::PROBE_PREFIX));
+        fs::write(&probe, "probe").unwrap();
+        let (code, events) = run_json(&["--link-mode", "auto", "--no-cache", root]);
+        assert_eq!(code, 0);
+        assert!(!probe.exists());
+        assert!(events
+            .iter()
+            .any(|e| e["type"] == "recover" && e["kind"] == "probe"));
+        for repo in &repos {
+            assert!(run_git_fsck(repo).success);
+        }
+    }
+
+    #[test]
+    fn test_recover() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["a"]);
+        let objects = loose_objects(&repos[0]);
+        let with_suffix = |path: &Path, suffix: &str| {
+            let mut name = path.as_os_str().to_os_string();
+            name.push(suffix);
+            PathBuf::from(name)
+        };
+        // targetの無い退避用リンク・不要な一時ファイル・内容の異なる退避用リンク
+        let restored = &objects[0].path;
+        let content = fs::read(restored).unwrap();
+        let backup = with_suffix(restored, crate::hardlink::BACKUP_SUFFIX);
+        fs::rename(restored, &backup).unwrap();
+        let temp = with_suffix(&objects[1].path, crate::hardlink::TEMP_SUFFIX);
+        fs::copy(&objects[1].path, &temp).unwrap();
+        let mismatched = with_suffix(&objects[2].path, crate::hardlink::BACKUP_SUFFIX);
+        fs::write(&mismatched, "garbage").unwrap();
+        let kept = fs::read(&objects[2].path).unwrap();
+        let repo = path_str(&repos[0]);
+
+        // dry-runでは何も変えない。残すものがあれば4
+        let (code, events) = run_json(&["recover", "-n", repo]);
+        assert_eq!(code, 4);
+        assert_eq!(summary(&events)["restored"], 1);
+        assert_eq!(summary(&events)["removed"], 1);
+        assert_eq!(summary(&events)["kept"], 1);
+        assert!(!restored.exists());
+        assert!(backup.exists() && temp.exists());
+
+        let (code, events) = run_json(&["recover", repo]);
+        assert_eq!(code, 4);
+        assert_eq!(summary(&events)["restored"], 1);
+        assert_eq!(summary(&events)["removed"], 1);
+        assert_eq!(summary(&events)["kept"], 1);
+        assert_eq!(fs::read(restored).unwrap(), content);
+        assert!(!backup.exists());
+        assert!(!temp.exists());
+        // 内容の異なる退避用リンクは残し、targetも変えない
+        assert_eq!(fs::read(&mismatched).unwrap(), b"garbage");
+        assert_eq!(fs::read(&objects[2].path).unwrap(), kept);
+        assert!(run_git_fsck(&repos[0]).success);
+
+        fs::remove_file(&mismatched).unwrap();
+        let (code, events) = run_json(&["recover", repo]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["kept"], 0);
+
+        let missing = temp_dir.path().join("missing");
+        assert_eq!(run_json(&["recover", path_str(&missing)]).0, 1);
+    }
+
+    #[test]
+    fn test_share_interrupted_after_replacements() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["a", "b"]);
+        let objects = loose_objects(&repos[1]).len();
+        let replaced = 2;
+        assert!(replaced < objects);
+        // 2つ置換した時点でシグナルを受けたことにする
+        let _check = {
+            let (a, b) = (repos[0].clone(), repos[1].clone());
+            signal::inject_interrupt_check(move || shared_count(&a, &b) >= replaced)
+        };
+
+        let (code, events) = run_json(&["--no-cache", path_str(temp_dir.path())]);
+        assert_eq!(code, EXIT_INTERRUPTED);
+        assert_eq!(summary(&events)["interrupted"], true);
+        assert_eq!(summary(&events)["loose"]["replaced"], replaced);
+        assert_eq!(shared_count(&repos[0], &repos[1]), replaced);
+        // journalには実際に置換したものだけを記録する
+        let journal = summary(&events)["journal"].as_str().unwrap().to_string();
+        let entries = read_journal(Path::new(&journal)).unwrap();
+        assert_eq!(entries.len(), replaced);
+        for entry in &entries {
+            assert!(entry.target.starts_with(&repos[1]));
+            assert!(is_same_inode(&entry.source, &entry.target).unwrap());
+        }
+        // 中断してもロックは解放されている
+        for repo in &repos {
+            assert!(crate::lock::try_lock_repo(repo, &Default::default()).is_ok());
+        }
+        for repo in &repos {
+            assert!(run_git_fsck(repo).success);
+        }
+    }
+
+    #[test]
+    #[cfg(unix)]
+    fn test_share_owner_policy() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let share = |policy: &str| {
+            let root = temp_dir.path().join(policy);
+            let repos = repos_with_same_objects(&root, &["a", "b", "c"]);
+            // aとbのモードをcと変える (aはsourceに選ばれる最も古いリポジトリ)
+            for obj in loose_objects(&repos[0])
+                .iter()
+                .chain(&loose_objects(&repos[1]))
+            {
+                fs::set_permissions(&obj.path, fs::Permissions::from_mode(0o400)).unwrap();
+            }
+            let (code, events) =
+                run_json(&["--no-cache", "--owner-policy", policy, path_str(&root)]);
+            assert_eq!(code, 0);
+            (repos, summary(&events)["loose"].clone())
+        };
+        let mode = |path: &Path| fs::metadata(path).unwrap().permissions().mode() & 0o7777;
+
+        // 同じ所有者・モードどうしだけを共有する
+        let (repos, stats) = share("same-owner");
+        let objects = loose_objects(&repos[0]).len();
+        assert_eq!(stats["owner_mismatch"], objects);
+        assert_eq!(stats["replaced"], objects);
+        assert_eq!(shared_count(&repos[0], &repos[1]), objects);
+        assert_eq!(shared_count(&repos[0], &repos[2]), 0);
+        assert_eq!(shared_count(&repos[1], &repos[2]), 0);
+        for obj in loose_objects(&repos[0]) {
+            assert_eq!(mode(&obj.path), 0o400);
+        }
+
+        // モードの異なるものを含むグループは置換しない (sourceと同じモードのbは数えない)
+        let (repos, stats) = share("refuse");
+        assert_eq!(stats["owner_mismatch"], objects);
+        assert_eq!(stats["replaced"], 0);
+        for repo in &repos {
+            for obj in loose_objects(repo) {
+                assert_eq!(link_count(&obj.path).unwrap(), 1);
+            }
+        }
+
+        // sourceのモードをそろえてすべて共有する
+        let (repos, stats) = share("normalize");
+        assert_eq!(stats["owner_mismatch"], 0);
+        assert_eq!(stats["normalized"], objects);
+        assert_eq!(stats["replaced"], objects * 2);
+        assert_eq!(shared_count(&repos[0], &repos[1]), objects);
+        assert_eq!(shared_count(&repos[0], &repos[2]), objects);
+        for obj in loose_objects(&repos[0]) {
+            assert_eq!(mode(&obj.path), 0o444);
+        }
+        for repo in &repos {
+            assert!(run_git_fsck(repo).success);
+        }
+    }
+
+    #[test]
+    fn test_locks() {
+        let temp_dir = TempDir::new().unwrap();
+        let repos = repos_with_same_objects(temp_dir.path(), &["held", "stale", "free", "none"]);
+        let root = path_str(temp_dir.path());
+        let held = crate::lock::try_lock_repo(&repos[0], &Default::default()).unwrap();
+        // 異常終了した保持者が残ったロックファイル
+        let stale = lock_file_path(&repos[1], None);
+        fs::write(
+            &stale,
+            "pid=999999\nhost=other-host.invalid\nstarted=1\ncommand=git-share-obj\n",
+        )
+        .unwrap();
+        let free = lock_file_path(&repos[2], None);
+        fs::write(&free, "").unwrap();
+
+        let (code, events) = run_json(&["locks", root]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["held"], 1);
+        assert_eq!(summary(&events)["stale"], 1);
+        assert_eq!(summary(&events)["free"], 1);
+        assert_eq!(summary(&events)["removed"], 0);
+        let holder = events
+            .iter()
+            .find(|e| e["type"] == "lock_file" && e["state"] == "held")
+            .map(|e| e["holder"]["pid"].clone());
+        assert_eq!(holder, Some(Value::from(process::id())));
+
+        let (code, events) = run_json(&["locks", "--clean", "-n", root]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["removed"], 2);
+        assert!(stale.exists() && free.exists());
+
+        // 保持中のものは残し、stale・空のものだけ削除する
+        let (code, events) = run_json(&["locks", "--clean", root]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["removed"], 2);
+        assert!(!stale.exists() && !free.exists());
+        let held_path = lock_file_path(&repos[0], None);
+        assert_eq!(
+            LockHolder::read(&held_path).map(|h| h.pid),
+            Some(process::id())
+        );
+        assert!(!lock_file_path(&repos[3], None).exists());
+        drop(held);
+        // 正常に解放したロックファイルは空になる
+        assert_eq!(fs::read(&held_path).unwrap(), b"");
+
+        let (code, events) = run_json(&["locks", root]);
+        assert_eq!(code, 0);
+        assert_eq!(summary(&events)["held"], 0);
+        assert_eq!(summary(&events)["free"], 1);
+
+        let missing = temp_dir.path().join("missing");
+        assert_eq!(run_json(&["locks", path_str(&missing)]).0, 1);
     }
-    println!("  {}: {}", msg(Msg::TotalSavings), format_size(stats.total_savings));
 }
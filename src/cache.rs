@@ -0,0 +1,425 @@
+//! 探索結果の永続キャッシュ (インクリメンタル実行用)
+//!
+//! 探索ルートごとに1ファイルをキャッシュディレクトリ ([`crate::dirs::StateDirs::cache`]) に置き、
+//! fan-outディレクトリ (`objects/xx`) 単位で、ディレクトリのmtimeと
+//! 中のオブジェクトの (dev, inode, mtime, size) を記録する。
+//! 次回はmtimeが変わったfan-outディレクトリだけを読み直す。
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use sha1::{Digest, Sha1};
+
+use crate::scanner::{GitObjectInfo, ObjectFormat};
+
+const CACHE_HEADER: &str = "git-share-obj scan-cache v1";
+
+/// 直近に更新されたディレクトリはmtimeの粒度内で再更新され得るためキャッシュしない
+const RACY_WINDOW: Duration = Duration::from_secs(2);
+
+/// ファイル時刻 (UNIX時刻の秒, ナノ秒)
+pub type Timestamp = (i64, u32);
+
+/// SystemTimeをTimestampに変換する
+pub fn to_timestamp(time: SystemTime) -> Timestamp {
+    match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            if d.subsec_nanos() == 0 {
+                (-(d.as_secs() as i64), 0)
+            } else {
+                (-(d.as_secs() as i64) - 1, 1_000_000_000 - d.subsec_nanos())
+            }
+        }
+    }
+}
+
+/// TimestampをSystemTimeに変換する
+pub fn from_timestamp((secs, nanos): Timestamp) -> SystemTime {
+    if secs >= 0 {
+        UNIX_EPOCH + Duration::new(secs as u64, nanos)
+    } else {
+        UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()) + Duration::from_nanos(nanos as u64)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct CachedObject {
+    name: String,
+    size: u64,
+    inode: u64,
+    device: u64,
+    modified: Timestamp,
+}
+
+#[derive(Debug, Clone)]
+struct CachedDir {
+    format: ObjectFormat,
+    modified: Timestamp,
+    objects: Vec<CachedObject>,
+}
+
+/// fan-outディレクトリ単位の探索キャッシュ
+#[derive(Debug)]
+pub struct ScanCache {
+    file: Option<PathBuf>,
+    dirs: HashMap<PathBuf, CachedDir>,
+    started: SystemTime,
+}
+
+impl ScanCache {
+    /// 何も読み書きしないキャッシュ
+    pub fn disabled() -> Self {
+        ScanCache {
+            file: None,
+            dirs: HashMap::new(),
+            started: SystemTime::now(),
+        }
+    }
+
+    /// 探索ルートのキャッシュを `dir` から読み込む (無い・壊れている場合は空)
+    pub fn load(dir: Option<&Path>, root: &Path) -> Self {
+        Self::open(dir.and_then(|dir| cache_file_for(dir, root)))
+    }
+
+    /// 探索ルートのキャッシュを空の状態から作り直す (`--rebuild-cache`)
+    pub fn rebuild(dir: Option<&Path>, root: &Path) -> Self {
+        ScanCache {
+            file: dir.and_then(|dir| cache_file_for(dir, root)),
+            dirs: HashMap::new(),
+            started: SystemTime::now(),
+        }
+    }
+
+    /// 指定したキャッシュファイルを読み込む
+    pub fn open(file: Option<PathBuf>) -> Self {
+        let dirs = file
+            .as_deref()
+            .and_then(|f| read_cache_file(f).ok())
+            .unwrap_or_default();
+        ScanCache {
+            file,
+            dirs,
+            started: SystemTime::now(),
+        }
+    }
+
+    /// キャッシュ済みのfan-outディレクトリ数
+    pub fn len(&self) -> usize {
+        self.dirs.len()
+    }
+
+    /// キャッシュが空か
+    pub fn is_empty(&self) -> bool {
+        self.dirs.is_empty()
+    }
+
+    /// fan-outディレクトリのmtimeが一致すればキャッシュ済みのオブジェクト情報を返す
+    pub fn lookup(
+        &self,
+        dir: &Path,
+        format: ObjectFormat,
+        modified: Timestamp,
+    ) -> Option<Vec<GitObjectInfo>> {
+        let cached = self.dirs.get(dir)?;
+        if cached.format != format || cached.modified != modified {
+            return None;
+        }
+        let dir_name = dir.file_name()?.to_str()?;
+        Some(
+            cached
+                .objects
+                .iter()
+                .map(|o| GitObjectInfo {
+                    path: dir.join(&o.name),
+                    hash: format!("{}{}", dir_name, o.name),
+                    format,
+                    created: from_timestamp(o.modified),
+                    size: o.size,
+                    inode: o.inode,
+                    device: o.device,
+                })
+                .collect(),
+        )
+    }
+
+    /// fan-outディレクトリの探索結果を記録する
+    pub fn store(
+        &mut self,
+        dir: &Path,
+        format: ObjectFormat,
+        modified: Timestamp,
+        objects: &[GitObjectInfo],
+    ) {
+        if self.file.is_none() {
+            return;
+        }
+        // 探索中にも更新され得る新しいディレクトリは次回も読み直す
+        if from_timestamp(modified) + RACY_WINDOW >= self.started {
+            self.dirs.remove(dir);
+            return;
+        }
+        let objects = objects
+            .iter()
+            .filter_map(|o| {
+                Some(CachedObject {
+                    name: o.path.file_name()?.to_str()?.to_string(),
+                    size: o.size,
+                    inode: o.inode,
+                    device: o.device,
+                    modified: to_timestamp(o.created),
+                })
+            })
+            .collect();
+        self.dirs.insert(
+            dir.to_path_buf(),
+            CachedDir {
+                format,
+                modified,
+                objects,
+            },
+        );
+    }
+
+    /// キャッシュを書き出す
+    ///
+    /// 既に存在しないディレクトリ (削除されたリポジトリなど) のエントリは捨てる。
+    pub fn save(&mut self) -> io::Result<()> {
+        let Some(file) = self.file.clone() else {
+            return Ok(());
+        };
+        self.dirs.retain(|dir, _| dir.is_dir());
+
+        if let Some(parent) = file.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        let tmp = file.with_extension(format!("tmp.{}", std::process::id()));
+        {
+            let mut out = BufWriter::new(File::create(&tmp)?);
+            writeln!(out, "{}", CACHE_HEADER)?;
+            let mut dirs: Vec<_> = self.dirs.iter().collect();
+            dirs.sort_by(|a, b| a.0.cmp(b.0));
+            for (dir, cached) in dirs {
+                writeln!(
+                    out,
+                    "D\t{}\t{}\t{}\t{}",
+                    escape(&dir.to_string_lossy()),
+                    cached.format.as_str(),
+                    cached.modified.0,
+                    cached.modified.1
+                )?;
+                for o in &cached.objects {
+                    writeln!(
+                        out,
+                        "O\t{}\t{}\t{}\t{}\t{}\t{}",
+                        o.name, o.size, o.inode, o.device, o.modified.0, o.modified.1
+                    )?;
+                }
+            }
+            out.flush()?;
+        }
+        fs::rename(&tmp, &file)
+    }
+}
+
+/// 探索ルートに対応する、キャッシュディレクトリ `dir` 内のキャッシュファイルのパス
+pub fn cache_file_for(dir: &Path, root: &Path) -> Option<PathBuf> {
+    Some(dir.join(format!("scan-{}.cache", root_key(root)?)))
+}
+
+/// 探索ルートごとのファイル名に使うキー (正規化したパスのSHA-1)
+pub fn root_key(root: &Path) -> Option<String> {
+    let root = fs::canonicalize(root).ok()?;
+    let digest = Sha1::digest(root.to_string_lossy().as_bytes());
+    Some(digest.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+fn read_cache_file(file: &Path) -> io::Result<HashMap<PathBuf, CachedDir>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid cache file");
+    let mut lines = BufReader::new(File::open(file)?).lines();
+    if lines.next().transpose()?.as_deref() != Some(CACHE_HEADER) {
+        return Err(invalid());
+    }
+
+    let mut dirs = HashMap::new();
+    let mut current: Option<(PathBuf, CachedDir)> = None;
+    for line in lines {
+        let line = line?;
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["D", dir, format, secs, nanos] => {
+                if let Some((dir, cached)) = current.take() {
+                    dirs.insert(dir, cached);
+                }
+                current = Some((
+                    PathBuf::from(unescape(dir)),
+                    CachedDir {
+                        format: ObjectFormat::from_config_value(format).ok_or_else(invalid)?,
+                        modified: (
+                            secs.parse().map_err(|_| invalid())?,
+                            nanos.parse().map_err(|_| invalid())?,
+                        ),
+                        objects: Vec::new(),
+                    },
+                ));
+            }
+            ["O", name, size, inode, device, secs, nanos] => {
+                let (_, cached) = current.as_mut().ok_or_else(invalid)?;
+                cached.objects.push(CachedObject {
+                    name: name.to_string(),
+                    size: size.parse().map_err(|_| invalid())?,
+                    inode: inode.parse().map_err(|_| invalid())?,
+                    device: device.parse().map_err(|_| invalid())?,
+                    modified: (
+                        secs.parse().map_err(|_| invalid())?,
+                        nanos.parse().map_err(|_| invalid())?,
+                    ),
+                });
+            }
+            _ => return Err(invalid()),
+        }
+    }
+    if let Some((dir, cached)) = current {
+        dirs.insert(dir, cached);
+    }
+    Ok(dirs)
+}
+
+/// パス中のタブ・改行・バックスラッシュをエスケープする
+pub(crate) fn escape(s: &str) -> String {
+    s.replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+}
+
+pub(crate) fn unescape(s: &str) -> String {
+    let mut out = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            out.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => out.push('\t'),
+            Some('n') => out.push('\n'),
+            Some(other) => out.push(other),
+            None => out.push('\\'),
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    fn sample_object(dir: &Path) -> GitObjectInfo {
+        GitObjectInfo {
+            path: dir.join("cdef1234567890abcdef1234567890abcdef12"),
+            hash: "abcdef1234567890abcdef1234567890abcdef12".to_string(),
+            format: ObjectFormat::Sha1,
+            created: from_timestamp((1_600_000_000, 123)),
+            size: 4,
+            inode: 42,
+            device: 7,
+        }
+    }
+
+    #[test]
+    fn test_timestamp_round_trip() {
+        for ts in [(0, 0), (1_600_000_000, 999_999_999), (-5, 250)] {
+            assert_eq!(to_timestamp(from_timestamp(ts)), ts);
+        }
+    }
+
+    #[test]
+    fn test_escape_round_trip() {
+        let s = "a\tb\nc\\d";
+        assert_eq!(unescape(&escape(s)), s);
+        assert!(!escape(s).contains('\t'));
+    }
+
+    #[test]
+    fn test_store_save_and_reload() {
+        let temp_dir = TempDir::new().unwrap();
+        let fanout = temp_dir.path().join("repo/.git/objects/ab");
+        fs::create_dir_all(&fanout).unwrap();
+        let cache_file = temp_dir.path().join("cache/scan.cache");
+        let mtime = (1_600_000_000, 5);
+
+        let mut cache = ScanCache::open(Some(cache_file.clone()));
+        cache.store(
+            &fanout,
+            ObjectFormat::Sha1,
+            mtime,
+            &[sample_object(&fanout)],
+        );
+        cache.save().unwrap();
+
+        let cache = ScanCache::open(Some(cache_file));
+        assert_eq!(cache.len(), 1);
+        let objects = cache.lookup(&fanout, ObjectFormat::Sha1, mtime).unwrap();
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].hash, "abcdef1234567890abcdef1234567890abcdef12");
+        assert_eq!(objects[0].inode, 42);
+        assert_eq!(objects[0].created, from_timestamp((1_600_000_000, 123)));
+
+        // mtimeやフォーマットが違えば使わない
+        assert!(cache
+            .lookup(&fanout, ObjectFormat::Sha1, (1_600_000_001, 5))
+            .is_none());
+        assert!(cache.lookup(&fanout, ObjectFormat::Sha256, mtime).is_none());
+    }
+
+    #[test]
+    fn test_save_drops_missing_directories() {
+        let temp_dir = TempDir::new().unwrap();
+        let fanout = temp_dir.path().join("repo/.git/objects/ab");
+        fs::create_dir_all(&fanout).unwrap();
+        let cache_file = temp_dir.path().join("scan.cache");
+
+        let mut cache = ScanCache::open(Some(cache_file.clone()));
+        cache.store(
+            &fanout,
+            ObjectFormat::Sha1,
+            (1, 0),
+            &[sample_object(&fanout)],
+        );
+        fs::remove_dir_all(temp_dir.path().join("repo")).unwrap();
+        cache.save().unwrap();
+
+        assert!(ScanCache::open(Some(cache_file)).is_empty());
+    }
+
+    #[test]
+    fn test_store_skips_recently_modified_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut cache = ScanCache::open(Some(temp_dir.path().join("scan.cache")));
+        let now = to_timestamp(SystemTime::now());
+        cache.store(temp_dir.path(), ObjectFormat::Sha1, now, &[]);
+        assert!(cache.is_empty());
+    }
+
+    #[test]
+    fn test_invalid_cache_file_is_ignored() {
+        let temp_dir = TempDir::new().unwrap();
+        let cache_file = temp_dir.path().join("scan.cache");
+        fs::write(&cache_file, "something else\n").unwrap();
+        assert!(ScanCache::open(Some(cache_file)).is_empty());
+    }
+
+    #[test]
+    fn test_disabled_cache_never_stores() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut cache = ScanCache::disabled();
+        cache.store(temp_dir.path(), ObjectFormat::Sha1, (1, 0), &[]);
+        assert!(cache.is_empty());
+        assert!(cache.save().is_ok());
+    }
+}
@@ -1,35 +1,185 @@
 //! コマンドライン引数のパースと設定
 
-use clap::Parser;
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use clap::{Parser, Subcommand};
+
+use crate::dirs::StateDirs;
+use crate::fsck::FsckMode;
+use crate::hardlink::{LinkMode, VerifyMode};
+use crate::lock::{LockMode, LockOptions};
+use crate::owner::OwnerPolicy;
+use crate::pool::ObjectPool;
+use crate::report::OutputFormat;
 
 /// Gitオブジェクトの重複ファイルをハードリンクで共有するツール
 #[derive(Parser, Debug)]
 #[command(name = "git-share-obj")]
 #[command(version, about, long_about = None)]
 pub struct Args {
+    /// サブコマンド (省略時は重複ファイルの共有)
+    #[command(subcommand)]
+    pub command: Option<Command>,
+
     /// 探索対象のディレクトリ (複数指定可能、デフォルト: カレントディレクトリ)
     #[arg(default_values_t = vec![String::from(".")])]
     pub paths: Vec<String>,
 
     /// ドライラン (実際には変更せず、検出結果のみ表示)
-    #[arg(short = 'n', long = "dry-run")]
+    #[arg(short = 'n', long = "dry-run", global = true)]
     pub dry_run: bool,
 
     /// 詳細出力
-    #[arg(short, long)]
+    #[arg(short, long, global = true)]
     pub verbose: bool,
 
     /// fsckチェックをスキップ（速度優先）
-    #[arg(long = "no-fsck")]
+    #[arg(long = "no-fsck", global = true)]
     pub no_fsck: bool,
 
     /// ハードリンク処理は行わず、fsckのみ実行
     #[arg(long = "fsck-only")]
     pub fsck_only: bool,
 
+    /// 置換後のfsckを置換したオブジェクトだけでなくリポジトリ全体に対して行う
+    #[arg(long = "full-post-fsck")]
+    pub full_post_fsck: bool,
+
+    /// fsckの方式 (git: `git fsck --full`, native: 組み込みのlooseオブジェクト検証)
+    #[arg(long = "fsck-mode", value_enum, default_value_t = FsckMode::Git, global = true)]
+    pub fsck_mode: FsckMode,
+
     /// リポジトリロックをスキップ（速度優先、非推奨）
-    #[arg(long = "no-lock")]
+    #[arg(long = "no-lock", global = true)]
     pub no_lock: bool,
+
+    /// 使用中のリポジトリロックを待つ最大秒数 (0: 待たずにそのリポジトリを除外)
+    #[arg(
+        long = "lock-timeout",
+        value_name = "SECONDS",
+        default_value_t = 0,
+        global = true
+    )]
+    pub lock_timeout: u64,
+
+    /// ロックできないリポジトリがあれば、除外して続けずに全体を中止する
+    #[arg(long = "fail-on-busy", global = true)]
+    pub fail_on_busy: bool,
+
+    /// 1つでもロックできなければ全てのロックを解放して待ち直し、全て獲得できたときだけ処理する
+    #[arg(long = "all-or-nothing", global = true)]
+    pub all_or_nothing: bool,
+
+    /// リポジトリより先に探索ルート単位のロックを獲得し、同じルートへの実行を直列化する
+    #[arg(long = "tree-lock", global = true)]
+    pub tree_lock: bool,
+
+    /// ロックファイルをリポジトリ内ではなくこのディレクトリに置く (例: $XDG_RUNTIME_DIR/git-share-obj)
+    #[arg(long = "lock-dir", value_name = "DIR", global = true)]
+    pub lock_dir: Option<PathBuf>,
+
+    /// 探索キャッシュを使わない (毎回全てのfan-outディレクトリを読む)
+    #[arg(long = "no-cache")]
+    pub no_cache: bool,
+
+    /// 探索キャッシュを破棄して作り直す
+    #[arg(long = "rebuild-cache")]
+    pub rebuild_cache: bool,
+
+    /// looseオブジェクトに加え、同一のpackファイル (.idx/.rev/.bitmap等を含む) も共有する
+    #[arg(long = "packs")]
+    pub packs: bool,
+
+    /// 共有元を専用のプールディレクトリにする (全リポジトリのlooseオブジェクトをプールのファイルにハードリンクする)
+    #[arg(long = "pool")]
+    pub pool: bool,
+
+    /// プールディレクトリ (デフォルト: $XDG_CACHE_HOME/git-share-obj/pool)
+    #[arg(long = "pool-dir", global = true)]
+    pub pool_dir: Option<PathBuf>,
+
+    /// 置換前の内容検証 (none: 検証なし, content: バイト比較, hash: バイト比較 + ハッシュ再計算)
+    #[arg(long = "verify", value_enum, default_value_t = VerifyMode::None)]
+    pub verify: VerifyMode,
+
+    /// リンク方式 (hardlink: ハードリンク, reflink: copy-on-writeの複製 (btrfs/XFS等), auto: デバイスごとにreflinkを試す)
+    #[arg(long = "link-mode", value_enum, default_value_t = LinkMode::Hardlink)]
+    pub link_mode: LinkMode,
+
+    /// 所有者・モードの異なるファイルの扱い (refuse: そのグループは置換しない, same-owner: 同じ所有者・モードどうしだけ共有, normalize: 共有して全員読み取り専用のモードにそろえる)
+    #[arg(long = "owner-policy", value_enum, default_value_t = OwnerPolicy::SameOwner)]
+    pub owner_policy: OwnerPolicy,
+
+    /// ディレクトリ探索とオブジェクト収集の並列数 (デフォルト: CPU数)
+    #[arg(short = 'j', long = "jobs")]
+    pub jobs: Option<NonZeroUsize>,
+
+    /// 出力形式 (text: 人間向け, json: 終了時に1つのJSON, ndjson: イベントごとに1行のJSON)
+    #[arg(long = "format", value_enum, default_value_t = OutputFormat::Text, global = true)]
+    pub format: OutputFormat,
+
+    /// キャッシュ・journal・ユーザー共通のロックの置き場所 (環境変数から決める)
+    #[arg(skip = StateDirs::from_env())]
+    pub state_dirs: StateDirs,
+}
+
+/// サブコマンド
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum Command {
+    /// journalの記録をもとにハードリンクを解除し、独立したファイルに戻す
+    Restore {
+        /// journalファイル (省略時は最新のもの)
+        journal: Option<PathBuf>,
+    },
+    /// 1つのリポジトリのオブジェクトのハードリンクを解除し、独立したファイルにする
+    /// (他チームへの引き渡しや別ディスクへの移動前に使う)
+    Unshare {
+        /// 対象リポジトリ
+        repo: PathBuf,
+    },
+    /// 複数リポジトリに共通するlooseオブジェクトを共有ストアに移し、
+    /// 各リポジトリの `objects/info/alternates` から参照させる
+    Alternates {
+        /// 共有オブジェクトストア (objectsディレクトリと同じ配置、無ければ作成)
+        #[arg(long = "store")]
+        store: PathBuf,
+
+        /// 探索対象のディレクトリ (複数指定可能、デフォルト: カレントディレクトリ)
+        #[arg(default_values_t = vec![String::from(".")])]
+        paths: Vec<String>,
+    },
+    /// 中断された実行が残した退避用リンク・一時ファイルを後始末する
+    /// (targetが無ければ復元し、不要なものは削除する)
+    Recover {
+        /// 探索対象のディレクトリ (複数指定可能、デフォルト: カレントディレクトリ)
+        #[arg(default_values_t = vec![String::from(".")])]
+        paths: Vec<String>,
+    },
+    /// リポジトリのロックファイルの保持者・状態を一覧する
+    Locks {
+        /// 保持されていない (stale・空の) ロックファイルを削除する
+        #[arg(long = "clean")]
+        clean: bool,
+
+        /// 探索対象のディレクトリ (複数指定可能、デフォルト: カレントディレクトリ)
+        #[arg(default_values_t = vec![String::from(".")])]
+        paths: Vec<String>,
+    },
+    /// オブジェクトプールの管理
+    Pool {
+        #[command(subcommand)]
+        command: PoolCommand,
+    },
+}
+
+/// `pool` のサブコマンド
+#[derive(Subcommand, Debug, PartialEq)]
+pub enum PoolCommand {
+    /// どのリポジトリからもリンクされなくなった (リンク数1の) エントリを削除する
+    Gc,
 }
 
 impl Args {
@@ -37,6 +187,43 @@ impl Args {
     pub fn parse_args() -> Self {
         Args::parse()
     }
+
+    /// プールディレクトリを返す (未指定なら既定の場所)
+    pub fn pool_root(&self) -> Option<PathBuf> {
+        self.pool_dir.clone().or_else(|| {
+            self.state_dirs
+                .cache
+                .as_deref()
+                .map(ObjectPool::default_dir)
+        })
+    }
+
+    /// 並列数を返す (未指定ならCPU数)
+    pub fn jobs(&self) -> usize {
+        self.jobs
+            .or_else(|| thread::available_parallelism().ok())
+            .map_or(1, NonZeroUsize::get)
+    }
+
+    /// ロックを待つ最大時間を返す
+    pub fn lock_timeout(&self) -> Duration {
+        Duration::from_secs(self.lock_timeout)
+    }
+
+    /// リポジトリロックの獲得方法を返す
+    ///
+    /// `--dry-run` と `--fsck-only` はリポジトリを変更しないため共有ロックにする。
+    pub fn lock_options(&self) -> LockOptions {
+        LockOptions {
+            mode: if self.dry_run || self.fsck_only {
+                LockMode::Shared
+            } else {
+                LockMode::Exclusive
+            },
+            lock_dir: self.lock_dir.clone(),
+            user_lock_dir: self.state_dirs.user_locks.clone(),
+        }
+    }
 }
 
 #[cfg(test)]
@@ -52,6 +239,26 @@ mod tests {
         assert!(!args.no_fsck);
         assert!(!args.fsck_only);
         assert!(!args.no_lock);
+        assert_eq!(args.lock_timeout(), Duration::ZERO);
+        assert!(!args.fail_on_busy);
+        assert!(!args.all_or_nothing);
+        assert!(!args.tree_lock);
+        assert_eq!(args.lock_dir, None);
+        assert_eq!(args.lock_options().mode, LockMode::Exclusive);
+        assert_eq!(args.verify, VerifyMode::None);
+        assert_eq!(args.fsck_mode, FsckMode::Git);
+        assert!(!args.full_post_fsck);
+        assert!(!args.packs);
+        assert!(!args.no_cache);
+        assert!(!args.rebuild_cache);
+        assert_eq!(args.jobs, None);
+        assert_eq!(args.command, None);
+        assert_eq!(args.format, OutputFormat::Text);
+        assert!(!args.pool);
+        assert_eq!(args.pool_dir, None);
+        assert_eq!(args.link_mode, LinkMode::Hardlink);
+        assert_eq!(args.owner_policy, OwnerPolicy::SameOwner);
+        assert!(args.jobs() >= 1);
     }
 
     #[test]
@@ -136,4 +343,252 @@ mod tests {
         assert!(!args.no_fsck);
         assert!(!args.fsck_only);
     }
+
+    #[test]
+    fn test_verify_modes() {
+        let args = Args::parse_from(["git-share-obj", "--verify", "content"]);
+        assert_eq!(args.verify, VerifyMode::Content);
+        let args = Args::parse_from(["git-share-obj", "--verify", "hash"]);
+        assert_eq!(args.verify, VerifyMode::Hash);
+        assert!(Args::try_parse_from(["git-share-obj", "--verify", "bogus"]).is_err());
+    }
+
+    #[test]
+    fn test_fsck_mode_native() {
+        let args = Args::parse_from(["git-share-obj", "--fsck-only", "--fsck-mode", "native"]);
+        assert!(args.fsck_only);
+        assert_eq!(args.fsck_mode, FsckMode::Native);
+    }
+
+    #[test]
+    fn test_full_post_fsck_long() {
+        let args = Args::parse_from(["git-share-obj", "--full-post-fsck"]);
+        assert!(args.full_post_fsck);
+    }
+
+    #[test]
+    fn test_packs_long() {
+        let args = Args::parse_from(["git-share-obj", "--packs", "-n"]);
+        assert!(args.packs);
+        assert!(args.dry_run);
+    }
+
+    #[test]
+    fn test_cache_options() {
+        let args = Args::parse_from(["git-share-obj", "--rebuild-cache"]);
+        assert!(args.rebuild_cache);
+        assert!(!args.no_cache);
+        let args = Args::parse_from(["git-share-obj", "--no-cache"]);
+        assert!(args.no_cache);
+    }
+
+    #[test]
+    fn test_link_mode() {
+        let args = Args::parse_from(["git-share-obj", "--link-mode", "reflink", "."]);
+        assert_eq!(args.link_mode, LinkMode::Reflink);
+        let args = Args::parse_from(["git-share-obj", "--link-mode", "auto", "."]);
+        assert_eq!(args.link_mode, LinkMode::Auto);
+        assert!(Args::try_parse_from(["git-share-obj", "--link-mode", "symlink"]).is_err());
+    }
+
+    #[test]
+    fn test_owner_policy() {
+        let args = Args::parse_from(["git-share-obj", "--owner-policy", "refuse", "."]);
+        assert_eq!(args.owner_policy, OwnerPolicy::Refuse);
+        let args = Args::parse_from(["git-share-obj", "--owner-policy", "normalize", "."]);
+        assert_eq!(args.owner_policy, OwnerPolicy::Normalize);
+        assert!(Args::try_parse_from(["git-share-obj", "--owner-policy", "ignore"]).is_err());
+    }
+
+    #[test]
+    fn test_lock_timeout_and_fail_on_busy() {
+        let args = Args::parse_from([
+            "git-share-obj",
+            "--lock-timeout",
+            "30",
+            "--fail-on-busy",
+            ".",
+        ]);
+        assert_eq!(args.lock_timeout(), Duration::from_secs(30));
+        assert!(args.fail_on_busy);
+        let args = Args::parse_from(["git-share-obj", "restore", "--lock-timeout", "5"]);
+        assert_eq!(args.lock_timeout, 5);
+        assert!(Args::try_parse_from(["git-share-obj", "--lock-timeout", "-1"]).is_err());
+    }
+
+    #[test]
+    fn test_all_or_nothing_and_tree_lock() {
+        let args = Args::parse_from(["git-share-obj", "--all-or-nothing", "--tree-lock", "."]);
+        assert!(args.all_or_nothing);
+        assert!(args.tree_lock);
+        let args = Args::parse_from(["git-share-obj", "recover", "--tree-lock", "."]);
+        assert!(args.tree_lock);
+        assert!(!args.all_or_nothing);
+    }
+
+    #[test]
+    fn test_lock_dir_and_shared_lock_for_read_only_modes() {
+        let args = Args::parse_from([
+            "git-share-obj",
+            "--lock-dir",
+            "/run/user/1000/git-share-obj",
+            "-n",
+            ".",
+        ]);
+        let options = args.lock_options();
+        assert_eq!(options.mode, LockMode::Shared);
+        assert_eq!(
+            options.lock_dir,
+            Some(PathBuf::from("/run/user/1000/git-share-obj"))
+        );
+        let args = Args::parse_from(["git-share-obj", "--fsck-only", "."]);
+        assert_eq!(args.lock_options().mode, LockMode::Shared);
+        let args = Args::parse_from(["git-share-obj", "recover", "--lock-dir", "/tmp/locks", "."]);
+        assert_eq!(args.lock_options().mode, LockMode::Exclusive);
+        assert_eq!(args.lock_dir, Some(PathBuf::from("/tmp/locks")));
+    }
+
+    #[test]
+    fn test_jobs() {
+        let args = Args::parse_from(["git-share-obj", "-j", "4"]);
+        assert_eq!(args.jobs(), 4);
+        let args = Args::parse_from(["git-share-obj", "--jobs", "1"]);
+        assert_eq!(args.jobs(), 1);
+        assert!(Args::try_parse_from(["git-share-obj", "--jobs", "0"]).is_err());
+    }
+
+    #[test]
+    fn test_restore_subcommand() {
+        let args = Args::parse_from(["git-share-obj", "restore", "/tmp/run.journal", "-n"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Restore {
+                journal: Some(PathBuf::from("/tmp/run.journal"))
+            })
+        );
+        assert!(args.dry_run);
+
+        let args = Args::parse_from(["git-share-obj", "-v", "restore"]);
+        assert_eq!(args.command, Some(Command::Restore { journal: None }));
+        assert!(args.verbose);
+    }
+
+    #[test]
+    fn test_unshare_subcommand() {
+        let args = Args::parse_from(["git-share-obj", "unshare", "/path/repo", "-n", "-v"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Unshare {
+                repo: PathBuf::from("/path/repo")
+            })
+        );
+        assert!(args.dry_run);
+        assert!(args.verbose);
+        assert!(Args::try_parse_from(["git-share-obj", "unshare"]).is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let args = Args::parse_from(["git-share-obj", "--format", "json", "-n"]);
+        assert_eq!(args.format, OutputFormat::Json);
+        let args = Args::parse_from(["git-share-obj", "restore", "--format", "ndjson"]);
+        assert_eq!(args.format, OutputFormat::Ndjson);
+        assert!(Args::try_parse_from(["git-share-obj", "--format", "xml"]).is_err());
+    }
+
+    #[test]
+    fn test_pool_options() {
+        let args = Args::parse_from([
+            "git-share-obj",
+            "--pool",
+            "--pool-dir",
+            "/srv/pool",
+            "/path/a",
+        ]);
+        assert!(args.pool);
+        assert_eq!(args.pool_root(), Some(PathBuf::from("/srv/pool")));
+        assert_eq!(args.paths, vec!["/path/a"]);
+    }
+
+    #[test]
+    fn test_pool_gc_subcommand() {
+        let args = Args::parse_from([
+            "git-share-obj",
+            "pool",
+            "gc",
+            "-n",
+            "--pool-dir",
+            "/srv/pool",
+        ]);
+        assert_eq!(
+            args.command,
+            Some(Command::Pool {
+                command: PoolCommand::Gc
+            })
+        );
+        assert!(args.dry_run);
+        assert_eq!(args.pool_dir, Some(PathBuf::from("/srv/pool")));
+    }
+
+    #[test]
+    fn test_alternates_subcommand() {
+        let args = Args::parse_from([
+            "git-share-obj",
+            "alternates",
+            "--store",
+            "/srv/shared/objects",
+            "/path/a",
+            "/path/b",
+            "--no-fsck",
+        ]);
+        assert_eq!(
+            args.command,
+            Some(Command::Alternates {
+                store: PathBuf::from("/srv/shared/objects"),
+                paths: vec!["/path/a".to_string(), "/path/b".to_string()],
+            })
+        );
+        assert!(args.no_fsck);
+        assert!(Args::try_parse_from(["git-share-obj", "alternates", "/path/a"]).is_err());
+    }
+
+    #[test]
+    fn test_locks_subcommand() {
+        let args = Args::parse_from(["git-share-obj", "locks", "--clean", "/srv/git"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Locks {
+                clean: true,
+                paths: vec!["/srv/git".to_string()],
+            })
+        );
+        let args = Args::parse_from(["git-share-obj", "locks"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Locks {
+                clean: false,
+                paths: vec![".".to_string()],
+            })
+        );
+    }
+
+    #[test]
+    fn test_recover_subcommand() {
+        let args = Args::parse_from(["git-share-obj", "recover", "/path/a", "--dry-run"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Recover {
+                paths: vec!["/path/a".to_string()],
+            })
+        );
+        assert!(args.dry_run);
+
+        let args = Args::parse_from(["git-share-obj", "recover"]);
+        assert_eq!(
+            args.command,
+            Some(Command::Recover {
+                paths: vec![".".to_string()]
+            })
+        );
+    }
 }
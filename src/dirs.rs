@@ -0,0 +1,69 @@
+//! キャッシュ・journal・ユーザー共通のロックの置き場所
+//!
+//! 既定では環境変数 (`XDG_CACHE_HOME` / `XDG_STATE_HOME` / `XDG_RUNTIME_DIR`) から決め、
+//! 引数 ([`crate::cli::Args`]) として各処理に渡す。
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// キャッシュ・journal・ユーザー共通のロックを置くディレクトリ
+///
+/// いずれもNoneなら (`HOME` も無いなど) 使わない。
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct StateDirs {
+    /// キャッシュ (`$XDG_CACHE_HOME/git-share-obj` または `~/.cache/git-share-obj`)。
+    /// スキャンキャッシュ・既定のプール・ツリーロックを置く
+    pub cache: Option<PathBuf>,
+    /// journal (`$XDG_STATE_HOME/git-share-obj/journal` または
+    /// `~/.local/state/git-share-obj/journal`)
+    pub journal: Option<PathBuf>,
+    /// ユーザー共通のロック (`$XDG_RUNTIME_DIR/git-share-obj/locks`、
+    /// `$XDG_RUNTIME_DIR` が無ければキャッシュの `locks`)
+    pub user_locks: Option<PathBuf>,
+}
+
+impl StateDirs {
+    /// 環境変数から既定の置き場所を決める
+    pub fn from_env() -> Self {
+        let home = env::var_os("HOME").map(PathBuf::from);
+        let base = |var: &str, fallback: &str| match env::var_os(var) {
+            Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir)),
+            _ => home.as_ref().map(|home| home.join(fallback)),
+        };
+        let cache = base("XDG_CACHE_HOME", ".cache").map(|dir| dir.join("git-share-obj"));
+        let journal = base("XDG_STATE_HOME", ".local/state")
+            .map(|dir| dir.join("git-share-obj").join("journal"));
+        let user_locks = match env::var_os("XDG_RUNTIME_DIR") {
+            Some(dir) if !dir.is_empty() => Some(PathBuf::from(dir).join("git-share-obj")),
+            _ => cache.clone(),
+        }
+        .map(|dir| dir.join("locks"));
+        StateDirs {
+            cache,
+            journal,
+            user_locks,
+        }
+    }
+
+    /// すべてを `root` 配下に置く
+    pub fn under(root: &Path) -> Self {
+        StateDirs {
+            cache: Some(root.join("cache")),
+            journal: Some(root.join("journal")),
+            user_locks: Some(root.join("locks")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_under() {
+        let dirs = StateDirs::under(Path::new("/state"));
+        assert_eq!(dirs.cache, Some(PathBuf::from("/state/cache")));
+        assert_eq!(dirs.journal, Some(PathBuf::from("/state/journal")));
+        assert_eq!(dirs.user_locks, Some(PathBuf::from("/state/locks")));
+    }
+}
@@ -1,7 +1,29 @@
 //! git fsck 実行処理
 
+use std::io::Write;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
+use std::thread;
+
+use clap::ValueEnum;
+
+use crate::object::verify_loose_object;
+use crate::pack::{scan_repository_packs, verify_pack_checksums};
+use crate::repository::resolve_repository;
+use crate::scanner::scan_repository_objects;
+
+/// 検証に含めるエラー行の上限 (超過分は件数のみ表示)
+const MAX_NATIVE_ERRORS: usize = 20;
+
+/// fsckの実行方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum FsckMode {
+    /// `git fsck --full` を実行する
+    #[default]
+    Git,
+    /// looseオブジェクトを展開してハッシュを再計算し、packはチェックサムを照合する (gitコマンド不要)
+    Native,
+}
 
 /// 単一リポジトリのfsck結果
 #[derive(Debug, Clone)]
@@ -57,18 +79,241 @@ pub fn run_git_fsck(repo: &Path) -> FsckResult {
     }
 }
 
+/// 単一リポジトリのlooseオブジェクトを組み込みの検証器でチェックする
+///
+/// 各looseオブジェクトを展開し、ヘッダ (種別・サイズ) を検証したうえで
+/// ハッシュを再計算してファイル名と照合する。packファイルは展開せず、
+/// `.pack` と `.idx` のチェックサムだけを照合する。
+pub fn run_native_fsck(repo: &Path) -> FsckResult {
+    let Some(git_repo) = resolve_repository(repo) else {
+        return failed_result(
+            repo,
+            None,
+            format!("not a git repository: {}", repo.display()),
+        );
+    };
+
+    let mut errors: Vec<String> = scan_repository_objects(&git_repo)
+        .into_iter()
+        .filter_map(|obj| {
+            verify_loose_object(&obj.path, &obj.hash, obj.format)
+                .err()
+                .map(|e| format!("{}: {}", obj.path.display(), e))
+        })
+        .collect();
+    errors.extend(
+        scan_repository_packs(&git_repo)
+            .into_iter()
+            .filter_map(|pack| {
+                verify_pack_checksums(&pack.path, pack.format)
+                    .err()
+                    .map(|e| format!("{}: {}", pack.path.display(), e))
+            }),
+    );
+
+    FsckResult {
+        repo: repo.to_path_buf(),
+        success: errors.is_empty(),
+        code: None,
+        stderr: summarize_errors(&errors),
+    }
+}
+
+/// 指定方式で単一リポジトリのfsckを実行
+pub fn run_fsck(repo: &Path, mode: FsckMode) -> FsckResult {
+    match mode {
+        FsckMode::Git => run_git_fsck(repo),
+        FsckMode::Native => run_native_fsck(repo),
+    }
+}
+
 /// 複数リポジトリで fsck を実行して集約
 pub fn run_fsck_for_repos(repos: &[PathBuf]) -> FsckSummary {
+    run_fsck_for_repos_with_mode(repos, FsckMode::Git)
+}
+
+/// 複数リポジトリで指定方式の fsck を実行して集約
+pub fn run_fsck_for_repos_with_mode(repos: &[PathBuf], mode: FsckMode) -> FsckSummary {
     let mut summary = FsckSummary::default();
     for repo in repos {
-        summary.results.push(run_git_fsck(repo));
+        summary.results.push(run_fsck(repo, mode));
     }
     summary
 }
 
+/// 指定したオブジェクトIDだけを `git cat-file --batch-check` で確認する
+///
+/// 置換したオブジェクトが読み出せる (missingにならない) ことを確認する軽量チェック。
+pub fn run_git_object_check(repo: &Path, ids: &[String]) -> FsckResult {
+    let child = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("cat-file")
+        .arg("--batch-check")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn();
+    let mut child = match child {
+        Ok(child) => child,
+        Err(e) => return failed_result(repo, None, e.to_string()),
+    };
+
+    // 出力を読みながら書き込まないと、gitの出力がパイプを埋めたところで互いに待ち続ける
+    let writer = child.stdin.take().map(|mut stdin| {
+        let input: String = ids.iter().map(|id| format!("{}\n", id)).collect();
+        thread::spawn(move || stdin.write_all(input.as_bytes()))
+    });
+
+    let out = match child.wait_with_output() {
+        Ok(out) => out,
+        Err(e) => return failed_result(repo, None, e.to_string()),
+    };
+    if let Some(Ok(Err(e))) = writer.map(|writer| writer.join()) {
+        return failed_result(repo, out.status.code(), e.to_string());
+    }
+
+    let mut errors: Vec<String> = String::from_utf8_lossy(&out.stdout)
+        .lines()
+        .filter(|line| line.ends_with(" missing") || line.ends_with(" ambiguous"))
+        .map(|line| line.to_string())
+        .collect();
+    let stderr = String::from_utf8_lossy(&out.stderr).trim().to_string();
+    if !stderr.is_empty() {
+        errors.push(stderr);
+    }
+
+    FsckResult {
+        repo: repo.to_path_buf(),
+        success: out.status.success() && errors.is_empty(),
+        code: out.status.code(),
+        stderr: summarize_errors(&errors),
+    }
+}
+
+/// 指定したオブジェクトIDだけを組み込みの検証器で確認する
+pub fn run_native_object_check(repo: &Path, ids: &[String]) -> FsckResult {
+    let Some(git_repo) = resolve_repository(repo) else {
+        return failed_result(
+            repo,
+            None,
+            format!("not a git repository: {}", repo.display()),
+        );
+    };
+
+    let errors: Vec<String> = ids
+        .iter()
+        .filter_map(|id| {
+            if id.len() != git_repo.format.hex_len() {
+                return Some(format!("{}: invalid object id", id));
+            }
+            let path = git_repo.objects_dir.join(&id[..2]).join(&id[2..]);
+            verify_loose_object(&path, id, git_repo.format)
+                .err()
+                .map(|e| format!("{}: {}", path.display(), e))
+        })
+        .collect();
+
+    FsckResult {
+        repo: repo.to_path_buf(),
+        success: errors.is_empty(),
+        code: None,
+        stderr: summarize_errors(&errors),
+    }
+}
+
+/// 指定方式で、指定したオブジェクトIDだけを確認する
+pub fn run_object_check(repo: &Path, ids: &[String], mode: FsckMode) -> FsckResult {
+    match mode {
+        FsckMode::Git => run_git_object_check(repo, ids),
+        FsckMode::Native => run_native_object_check(repo, ids),
+    }
+}
+
+/// 置換したpackを `git verify-pack` で確認する
+///
+/// looseオブジェクトの確認では対象にならないpackについて、`.idx` と `.pack` の
+/// チェックサムと各オブジェクトを検証する。
+///
+/// Args:
+///     repo: リポジトリのパス
+///     packs: 置換した `.pack` ファイルのパス
+pub fn run_git_pack_check(repo: &Path, packs: &[PathBuf]) -> FsckResult {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repo)
+        .arg("verify-pack")
+        .args(packs.iter().map(|pack| pack.with_extension("idx")))
+        .output();
+
+    match output {
+        Ok(out) => FsckResult {
+            repo: repo.to_path_buf(),
+            success: out.status.success(),
+            code: out.status.code(),
+            stderr: String::from_utf8_lossy(&out.stderr).trim().to_string(),
+        },
+        Err(e) => failed_result(repo, None, e.to_string()),
+    }
+}
+
+/// 置換したpackの `.pack` と `.idx` のチェックサムを組み込みの検証器で確認する
+pub fn run_native_pack_check(repo: &Path, packs: &[PathBuf]) -> FsckResult {
+    let Some(git_repo) = resolve_repository(repo) else {
+        return failed_result(
+            repo,
+            None,
+            format!("not a git repository: {}", repo.display()),
+        );
+    };
+
+    let errors: Vec<String> = packs
+        .iter()
+        .filter_map(|pack| {
+            verify_pack_checksums(pack, git_repo.format)
+                .err()
+                .map(|e| format!("{}: {}", pack.display(), e))
+        })
+        .collect();
+
+    FsckResult {
+        repo: repo.to_path_buf(),
+        success: errors.is_empty(),
+        code: None,
+        stderr: summarize_errors(&errors),
+    }
+}
+
+/// 指定方式で、置換したpackを確認する
+pub fn run_pack_check(repo: &Path, packs: &[PathBuf], mode: FsckMode) -> FsckResult {
+    match mode {
+        FsckMode::Git => run_git_pack_check(repo, packs),
+        FsckMode::Native => run_native_pack_check(repo, packs),
+    }
+}
+
+fn failed_result(repo: &Path, code: Option<i32>, stderr: String) -> FsckResult {
+    FsckResult {
+        repo: repo.to_path_buf(),
+        success: false,
+        code,
+        stderr,
+    }
+}
+
+fn summarize_errors(errors: &[String]) -> String {
+    let mut lines: Vec<_> = errors.iter().take(MAX_NATIVE_ERRORS).cloned().collect();
+    if errors.len() > MAX_NATIVE_ERRORS {
+        lines.push(format!("... and {} more", errors.len() - MAX_NATIVE_ERRORS));
+    }
+    lines.join("\n")
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::object::tests::write_loose_object;
+    use crate::scanner::ObjectFormat;
     use std::fs;
     use tempfile::TempDir;
 
@@ -123,6 +368,192 @@ mod tests {
         assert_eq!(summary.failed(), 1);
         assert!(!summary.all_success());
         assert!(summary.results.iter().any(|r| r.repo == repo && r.success));
-        assert!(summary.results.iter().any(|r| r.repo == non_repo && !r.success));
+        assert!(summary
+            .results
+            .iter()
+            .any(|r| r.repo == non_repo && !r.success));
+    }
+
+    fn init_repo_with_object(repo: &Path) -> PathBuf {
+        fs::create_dir_all(repo).unwrap();
+        let init = Command::new("git")
+            .arg("init")
+            .arg("-q")
+            .arg(repo)
+            .status()
+            .unwrap();
+        assert!(init.success());
+        let (path, _) = write_loose_object(&repo.join(".git/objects"), ObjectFormat::Sha1, b"hi\n");
+        path
+    }
+
+    #[test]
+    fn test_run_native_fsck_success() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        init_repo_with_object(&repo);
+
+        let result = run_native_fsck(&repo);
+        assert!(result.success, "{}", result.stderr);
+        assert_eq!(result.repo, repo);
+    }
+
+    #[test]
+    fn test_run_native_fsck_detects_corrupt_object() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        let object = init_repo_with_object(&repo);
+        fs::write(&object, b"corrupt").unwrap();
+
+        let result = run_native_fsck(&repo);
+        assert!(!result.success);
+        assert!(result.stderr.contains(&object.display().to_string()));
+    }
+
+    #[test]
+    fn test_run_native_fsck_failure_on_non_repo() {
+        let temp_dir = TempDir::new().unwrap();
+        let non_repo = temp_dir.path().join("not-repo");
+        fs::create_dir_all(&non_repo).unwrap();
+
+        let result = run_fsck(&non_repo, FsckMode::Native);
+        assert!(!result.success);
+    }
+
+    #[test]
+    fn test_native_and_git_fsck_agree_on_valid_repo() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        init_repo_with_object(&repo);
+
+        let summary = run_fsck_for_repos_with_mode(std::slice::from_ref(&repo), FsckMode::Native);
+        assert!(summary.all_success());
+        assert!(run_fsck(&repo, FsckMode::Git).success);
+    }
+
+    #[test]
+    fn test_summarize_errors_truncates() {
+        let errors: Vec<_> = (0..MAX_NATIVE_ERRORS + 3)
+            .map(|i| format!("e{}", i))
+            .collect();
+        let summary = summarize_errors(&errors);
+        assert_eq!(summary.lines().count(), MAX_NATIVE_ERRORS + 1);
+        assert!(summary.ends_with("... and 3 more"));
+    }
+
+    #[test]
+    fn test_run_object_check_touched_objects() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        let object = init_repo_with_object(&repo);
+        let id = "45b983be36b73c0788dc9cbcb76cbb80fc7bb057".to_string();
+
+        for mode in [FsckMode::Git, FsckMode::Native] {
+            let result = run_object_check(&repo, std::slice::from_ref(&id), mode);
+            assert!(result.success, "{:?}: {}", mode, result.stderr);
+        }
+
+        fs::remove_file(&object).unwrap();
+        for mode in [FsckMode::Git, FsckMode::Native] {
+            let result = run_object_check(&repo, std::slice::from_ref(&id), mode);
+            assert!(!result.success, "{:?} should detect missing object", mode);
+        }
+    }
+
+    #[test]
+    fn test_run_git_object_check_many_ids() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        init_repo_with_object(&repo);
+        // 出力がパイプの容量を大きく超える件数
+        let ids = vec!["45b983be36b73c0788dc9cbcb76cbb80fc7bb057".to_string(); 50_000];
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        thread::spawn(move || sender.send(run_git_object_check(&repo, &ids)).unwrap());
+        let result = receiver
+            .recv_timeout(std::time::Duration::from_secs(60))
+            .expect("git cat-file --batch-check did not finish");
+        assert!(result.success, "{}", result.stderr);
+    }
+
+    #[test]
+    fn test_run_pack_check_detects_corrupt_pack() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        init_repo_with_object(&repo);
+        for args in [
+            &[
+                "-c",
+                "user.name=t",
+                "-c",
+                "user.email=t@t",
+                "commit",
+                "-q",
+                "--allow-empty",
+                "-m",
+                "m",
+            ][..],
+            &["repack", "-a", "-d", "-q"][..],
+        ] {
+            let status = Command::new("git")
+                .arg("-C")
+                .arg(&repo)
+                .args(args)
+                .status()
+                .unwrap();
+            assert!(status.success());
+        }
+        let pack = fs::read_dir(repo.join(".git/objects/pack"))
+            .unwrap()
+            .map(|e| e.unwrap().path())
+            .find(|p| p.extension().is_some_and(|ext| ext == "pack"))
+            .unwrap();
+
+        let packs = std::slice::from_ref(&pack);
+        for mode in [FsckMode::Git, FsckMode::Native] {
+            assert!(run_pack_check(&repo, packs, mode).success, "{:?}", mode);
+        }
+        assert!(run_native_fsck(&repo).success);
+
+        // 本体だけを壊し、末尾のチェックサムは残す
+        let original = fs::read(&pack).unwrap();
+        let mut content = original.clone();
+        let middle = content.len() / 2;
+        content[middle] ^= 0xff;
+        fs::write(&pack, &content).unwrap();
+        for mode in [FsckMode::Git, FsckMode::Native] {
+            assert!(!run_pack_check(&repo, packs, mode).success, "{:?}", mode);
+        }
+        assert!(!run_native_fsck(&repo).success);
+
+        // チェックサムは内容と合っているが、`.idx` に記録されたものと異なるpack
+        use sha1::{Digest, Sha1};
+        let body = content.len() - 20;
+        let checksum = Sha1::digest(&content[..body]);
+        content[body..].copy_from_slice(&checksum);
+        fs::write(&pack, &content).unwrap();
+        let result = run_native_pack_check(&repo, packs);
+        assert!(
+            result.stderr.contains("does not match the pack"),
+            "{}",
+            result.stderr
+        );
+        fs::write(&pack, &original).unwrap();
+        assert!(run_native_pack_check(&repo, packs).success);
+    }
+
+    #[test]
+    fn test_run_native_object_check_ignores_untouched_corruption() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        init_repo_with_object(&repo);
+        let (other, _) =
+            write_loose_object(&repo.join(".git/objects"), ObjectFormat::Sha1, b"other\n");
+        fs::write(&other, b"corrupt").unwrap();
+
+        // 指定したオブジェクトだけを確認するので、他の破損は検出しない
+        let id = "45b983be36b73c0788dc9cbcb76cbb80fc7bb057".to_string();
+        assert!(run_native_object_check(&repo, &[id]).success);
+        assert!(!run_native_fsck(&repo).success);
     }
 }
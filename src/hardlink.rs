@@ -1,11 +1,29 @@
-//! ハードリンク処理
+//! ハードリンク処理 (reflinkによる置換を含む)
 
-use std::fs;
-use std::io;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufReader, Read};
 use std::path::{Path, PathBuf};
+use std::time::SystemTime;
 
 #[cfg(unix)]
-use std::os::unix::fs::MetadataExt;
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+#[cfg(target_os = "linux")]
+use std::os::unix::io::AsRawFd;
+
+use clap::ValueEnum;
+
+use crate::object::verify_loose_object;
+use crate::scanner::GitObjectInfo;
+
+/// 置換中の退避用リンクの接尾辞 (`recover` で後始末する)
+pub const BACKUP_SUFFIX: &str = ".git-share-obj.bak";
+
+/// 置換・解除中の一時ファイルの接尾辞 (`recover` で後始末する)
+pub const TEMP_SUFFIX: &str = ".git-share-obj.tmp";
+
+/// reflinkの確認に使う試しのファイルの接頭辞 (`recover` で後始末する)
+pub const PROBE_PREFIX: &str = ".git-share-obj-probe-";
 
 /// ハードリンク置換の結果
 #[derive(Debug, PartialEq)]
@@ -20,10 +38,200 @@ pub enum ReplaceResult {
     RolledBack(String),
     /// ハードリンク作成失敗後のロールバックも失敗
     RollbackFailed(String),
+    /// 内容検証で不一致を検出したため置換しなかった
+    ContentMismatch(String),
+    /// ファイルシステムがreflinkに対応していないため置換しなかった (targetは元のまま)
+    ReflinkUnsupported(String),
+    /// sourceのリンク数が上限 (EMLINK) に達したため置換しなかった (targetは元のまま)
+    TooManyLinks(String),
     /// エラー発生
     Error(String),
 }
 
+/// ハードリンク解除の結果
+#[derive(Debug, PartialEq)]
+pub enum UnshareResult {
+    /// 独立したファイルに置き換えた
+    Unshared,
+    /// 共有されていない (リンク数1) ためそのまま
+    NotShared,
+    /// 置き換え失敗後にロールバック成功
+    RolledBack(String),
+    /// 置き換え失敗後のロールバックも失敗
+    RollbackFailed(String),
+    /// エラー発生
+    Error(String),
+}
+
+/// 置換前の内容検証方法
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum VerifyMode {
+    /// 検証しない (ファイル名のハッシュのみを信頼)
+    #[default]
+    None,
+    /// sourceとtargetをバイト単位で比較する
+    Content,
+    /// バイト比較に加え、sourceを展開してハッシュを再計算する
+    Hash,
+}
+
+/// リンク方式の指定 (`--link-mode`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum LinkMode {
+    /// ハードリンク (inodeを共有する)
+    #[default]
+    Hardlink,
+    /// reflink (`FICLONE`、データブロックだけを共有するcopy-on-writeの複製)
+    Reflink,
+    /// デバイスごとにreflinkを試し、使えなければハードリンク
+    Auto,
+}
+
+/// 実際に使うリンク方式
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LinkStrategy {
+    Hardlink,
+    Reflink,
+}
+
+impl LinkMode {
+    /// デバイスで使うリンク方式を決める
+    ///
+    /// Args:
+    ///     dir: そのデバイス上の書き込み可能なディレクトリ (autoのときreflinkを試す)
+    pub fn resolve(self, dir: &Path) -> LinkStrategy {
+        match self {
+            LinkMode::Hardlink => LinkStrategy::Hardlink,
+            LinkMode::Reflink => LinkStrategy::Reflink,
+            LinkMode::Auto if probe_reflink(dir) => LinkStrategy::Reflink,
+            LinkMode::Auto => LinkStrategy::Hardlink,
+        }
+    }
+}
+
+impl LinkStrategy {
+    /// 表示用の名前
+    pub fn as_str(self) -> &'static str {
+        match self {
+            LinkStrategy::Hardlink => "hardlink",
+            LinkStrategy::Reflink => "reflink",
+        }
+    }
+
+    /// sourceと同じ内容のファイルをtargetに作る (targetは存在しないこと)
+    pub fn create(self, source: &Path, target: &Path) -> io::Result<()> {
+        match self {
+            LinkStrategy::Hardlink => fs::hard_link(source, target),
+            LinkStrategy::Reflink => reflink(source, target),
+        }
+    }
+
+    /// 作成失敗時のメッセージ
+    fn failure(self, e: &io::Error) -> String {
+        match self {
+            LinkStrategy::Hardlink => format!("ハードリンク作成失敗: {}", e),
+            LinkStrategy::Reflink => format!("reflink作成失敗: {}", e),
+        }
+    }
+}
+
+/// sourceのデータブロックを共有する新しいファイルをtargetに作る (`FICLONE`)
+///
+/// パーミッションはsourceと同じにする。失敗した場合はtargetを残さない。
+#[cfg(target_os = "linux")]
+pub fn reflink(source: &Path, target: &Path) -> io::Result<()> {
+    let src = File::open(source)?;
+    let permissions = src.metadata()?.permissions();
+    let dst = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(target)?;
+    // SAFETY: 両方とも開いているファイルディスクリプタで、FICLONEは引数にfdを取る
+    let ret = unsafe { libc::ioctl(dst.as_raw_fd(), libc::FICLONE, src.as_raw_fd()) };
+    let result = if ret == -1 {
+        Err(io::Error::last_os_error())
+    } else {
+        fs::set_permissions(target, permissions)
+    };
+    if result.is_err() {
+        drop(dst);
+        let _ = fs::remove_file(target);
+    }
+    result
+}
+
+#[cfg(not(target_os = "linux"))]
+pub fn reflink(_source: &Path, _target: &Path) -> io::Result<()> {
+    Err(io::Error::new(
+        io::ErrorKind::Unsupported,
+        "reflink is not supported on this platform",
+    ))
+}
+
+/// reflinkに対応していないことを示すエラーか
+pub fn is_reflink_unsupported(e: &io::Error) -> bool {
+    if e.kind() == io::ErrorKind::Unsupported {
+        return true;
+    }
+    #[cfg(target_os = "linux")]
+    if let Some(code) = e.raw_os_error() {
+        return [
+            libc::EOPNOTSUPP,
+            libc::EXDEV,
+            libc::EINVAL,
+            libc::ENOTTY,
+            libc::ENOSYS,
+        ]
+        .contains(&code);
+    }
+    false
+}
+
+/// ディレクトリのあるファイルシステムがreflinkに対応しているか、一時ファイルで試す
+pub fn probe_reflink(dir: &Path) -> bool {
+    let source = dir.join(format!("{}{}", PROBE_PREFIX, std::process::id()));
+    let target = source.with_extension("clone");
+    let supported = fs::write(&source, b"probe").is_ok() && reflink(&source, &target).is_ok();
+    let _ = fs::remove_file(&target);
+    let _ = fs::remove_file(&source);
+    supported
+}
+
+/// ファイルシステムのハードリンク数の上限 (`pathconf(_PC_LINK_MAX)`)
+///
+/// Linuxでglibcが上限を知らないファイルシステム (tmpfs等) では既定値の127が
+/// 返るため、不明 (None) として扱う。その場合も置換時のEMLINKで検出できる。
+#[cfg(unix)]
+pub fn link_max(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::unix::ffi::OsStrExt;
+
+    /// glibcが不明なファイルシステムに返す値 (LINUX_LINK_MAX)
+    const UNKNOWN_LINK_MAX: libc::c_long = 127;
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    // SAFETY: pathはNUL終端された有効な文字列
+    let max = unsafe { libc::pathconf(path.as_ptr(), libc::_PC_LINK_MAX) };
+    if max <= 0 || (cfg!(target_os = "linux") && max == UNKNOWN_LINK_MAX) {
+        return None;
+    }
+    Some(max as u64)
+}
+
+#[cfg(not(unix))]
+pub fn link_max(_path: &Path) -> Option<u64> {
+    None
+}
+
+/// リンク数の上限に達したことを示すエラーか
+fn is_too_many_links(e: &io::Error) -> bool {
+    #[cfg(unix)]
+    if e.raw_os_error() == Some(libc::EMLINK) {
+        return true;
+    }
+    e.kind() == io::ErrorKind::TooManyLinks
+}
+
 /// 2つのパスが同一ファイルシステム上にあるか確認する
 ///
 /// Args:
@@ -65,6 +273,80 @@ pub fn is_same_inode(_path1: &Path, _path2: &Path) -> io::Result<bool> {
     Ok(false)
 }
 
+/// ファイルのハードリンク数を返す
+#[cfg(unix)]
+pub fn link_count(path: &Path) -> io::Result<u64> {
+    Ok(fs::metadata(path)?.nlink())
+}
+
+#[cfg(not(unix))]
+pub fn link_count(path: &Path) -> io::Result<u64> {
+    fs::metadata(path).map(|_| 1)
+}
+
+/// 2つのファイルの内容がバイト単位で一致するか確認する
+///
+/// Args:
+///     path1: 比較対象のパス1
+///     path2: 比較対象のパス2
+///
+/// Returns:
+///     内容が一致すればtrue
+pub fn is_same_content(path1: &Path, path2: &Path) -> io::Result<bool> {
+    if fs::metadata(path1)?.len() != fs::metadata(path2)?.len() {
+        return Ok(false);
+    }
+
+    let mut reader1 = BufReader::new(File::open(path1)?);
+    let mut reader2 = BufReader::new(File::open(path2)?);
+    let mut buf1 = [0u8; 8192];
+    let mut buf2 = [0u8; 8192];
+    loop {
+        let n = reader1.read(&mut buf1)?;
+        if n == 0 {
+            // 長さは一致しているので、もう片方も終端のはず
+            return Ok(reader2.read(&mut buf2)? == 0);
+        }
+        reader2.read_exact(&mut buf2[..n])?;
+        if buf1[..n] != buf2[..n] {
+            return Ok(false);
+        }
+    }
+}
+
+/// 置換前の内容検証を行う
+///
+/// Returns:
+///     不一致や破損を検出した場合はその内容を示すErr
+fn verify_before_replace(
+    source: &GitObjectInfo,
+    target: &Path,
+    mode: VerifyMode,
+) -> Result<(), ReplaceResult> {
+    if mode == VerifyMode::None {
+        return Ok(());
+    }
+
+    if mode == VerifyMode::Hash {
+        if let Err(e) = verify_loose_object(&source.path, &source.hash, source.format) {
+            return Err(ReplaceResult::ContentMismatch(format!(
+                "source破損: {} ({})",
+                source.path.display(),
+                e
+            )));
+        }
+    }
+
+    match is_same_content(&source.path, target) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(ReplaceResult::ContentMismatch(format!(
+            "内容不一致: {}",
+            source.path.display()
+        ))),
+        Err(e) => Err(ReplaceResult::Error(e.to_string())),
+    }
+}
+
 /// ファイルをハードリンクに置換する
 ///
 /// Args:
@@ -74,6 +356,62 @@ pub fn is_same_inode(_path1: &Path, _path2: &Path) -> io::Result<bool> {
 /// Returns:
 ///     置換結果
 pub fn replace_with_hardlink(source: &Path, target: &Path) -> ReplaceResult {
+    replace_checked(source, target, LinkStrategy::Hardlink, || Ok(()))
+}
+
+/// 内容を検証してからファイルをハードリンクに置換する
+///
+/// 検証で不一致を検出した場合は `ReplaceResult::ContentMismatch` を返し、
+/// targetには一切触れない。
+///
+/// Args:
+///     source: 基準オブジェクト (リンク元)
+///     target: 置換対象ファイル
+///     mode: 検証方法
+///
+/// Returns:
+///     置換結果
+pub fn replace_with_hardlink_verified(
+    source: &GitObjectInfo,
+    target: &Path,
+    mode: VerifyMode,
+) -> ReplaceResult {
+    replace_with_link_verified(source, target, mode, LinkStrategy::Hardlink)
+}
+
+/// 内容を検証してから指定のリンク方式でファイルを置換する
+///
+/// reflinkに対応していないファイルシステムでは、targetを元に戻して
+/// `ReplaceResult::ReflinkUnsupported` を返す。
+///
+/// Args:
+///     source: 基準オブジェクト (リンク元)
+///     target: 置換対象ファイル
+///     mode: 検証方法
+///     strategy: リンク方式
+///
+/// Returns:
+///     置換結果
+pub fn replace_with_link_verified(
+    source: &GitObjectInfo,
+    target: &Path,
+    mode: VerifyMode,
+    strategy: LinkStrategy,
+) -> ReplaceResult {
+    replace_checked(&source.path, target, strategy, || {
+        verify_before_replace(source, target, mode)
+    })
+}
+
+fn replace_checked<F>(
+    source: &Path,
+    target: &Path,
+    strategy: LinkStrategy,
+    verify: F,
+) -> ReplaceResult
+where
+    F: FnOnce() -> Result<(), ReplaceResult>,
+{
     // ファイルシステムの確認
     match is_same_filesystem(source, target) {
         Ok(true) => {}
@@ -88,42 +426,236 @@ pub fn replace_with_hardlink(source: &Path, target: &Path) -> ReplaceResult {
         Err(e) => return ReplaceResult::Error(e.to_string()),
     }
 
-    let backup = backup_path(target);
-    if let Err(e) = fs::rename(target, &backup) {
-        return ReplaceResult::Error(format!("退避リネーム失敗: {}", e));
+    // 退避リネームの前に内容を検証する
+    if let Err(result) = verify() {
+        return result;
     }
 
-    if let Err(e) = fs::hard_link(source, target) {
-        remove_if_regular_file(target);
-        return match fs::rename(&backup, target) {
-            Ok(()) => ReplaceResult::RolledBack(format!(
-                "ハードリンク作成失敗: {} (ロールバック成功)",
-                e
-            )),
-            Err(rollback_err) => ReplaceResult::RollbackFailed(format!(
-                "ハードリンク作成失敗: {} (ロールバック失敗: {})",
-                e, rollback_err
-            )),
-        };
+    // 一時名でリンクを作り、renameでtargetを置き換える (読み手には常に有効なオブジェクトが見える)
+    let temp = temp_path(target);
+    remove_if_regular_file(&temp);
+    if let Err(e) = strategy.create(source, &temp) {
+        remove_if_regular_file(&temp);
+        return link_failure(strategy, &e, ReplaceResult::Error(strategy.failure(&e)));
+    }
+
+    if let Err(e) = fs::rename(&temp, target) {
+        remove_if_regular_file(&temp);
+        return ReplaceResult::Error(format!("置換リネーム失敗: {}", e));
+    }
+
+    ReplaceResult::Replaced
+}
+
+/// 複数ファイルの組をまとめてハードリンクに置換する
+///
+/// packファイルとその付随ファイル (.idx/.rev/.bitmap など) のように、一部だけが
+/// 置換された状態を残したくない組に使う。途中で失敗した場合は、それまでに
+/// 置換したファイルも含めて全てを元に戻す。
+///
+/// Args:
+///     pairs: (source, target) の組のリスト
+///
+/// Returns:
+///     置換結果 (全てリンク済みならAlreadyLinked)
+pub fn replace_set_with_hardlinks(pairs: &[(PathBuf, PathBuf)]) -> ReplaceResult {
+    replace_set_with_links(pairs, LinkStrategy::Hardlink)
+}
+
+/// 複数ファイルの組をまとめて指定のリンク方式で置換する
+///
+/// `replace_set_with_hardlinks` と同じく、途中で失敗した場合は全てを元に戻す。
+pub fn replace_set_with_links(
+    pairs: &[(PathBuf, PathBuf)],
+    strategy: LinkStrategy,
+) -> ReplaceResult {
+    let mut pending = Vec::new();
+    for (source, target) in pairs {
+        match is_same_filesystem(source, target) {
+            Ok(true) => {}
+            Ok(false) => return ReplaceResult::CrossFilesystem,
+            Err(e) => return ReplaceResult::Error(e.to_string()),
+        }
+        match is_same_inode(source, target) {
+            Ok(true) => {}
+            Ok(false) => pending.push((source, target)),
+            Err(e) => return ReplaceResult::Error(e.to_string()),
+        }
+    }
+    if pending.is_empty() {
+        return ReplaceResult::AlreadyLinked;
     }
 
-    if let Err(e) = fs::remove_file(&backup) {
-        return ReplaceResult::Error(format!(
-            "退避ファイル削除失敗: {} (退避ファイル: {})",
-            e,
-            backup.display()
-        ));
+    // 各targetは退避用のハードリンクを残してから一時名のリンクをrenameで被せる
+    let mut done: Vec<(&PathBuf, PathBuf)> = Vec::new();
+    for (source, target) in pending {
+        let temp = temp_path(target);
+        remove_if_regular_file(&temp);
+        if let Err(e) = strategy.create(source, &temp) {
+            remove_if_regular_file(&temp);
+            return link_failure(strategy, &e, rollback_set(&done, strategy.failure(&e)));
+        }
+        let backup = backup_path(target);
+        remove_if_regular_file(&backup);
+        if let Err(e) = fs::hard_link(target, &backup) {
+            remove_if_regular_file(&temp);
+            return rollback_set(&done, format!("退避リンク作成失敗: {}", e));
+        }
+        if let Err(e) = fs::rename(&temp, target) {
+            remove_if_regular_file(&temp);
+            remove_if_regular_file(&backup);
+            return rollback_set(&done, format!("置換リネーム失敗: {}", e));
+        }
+        done.push((target, backup));
+    }
+
+    // 置換は完了しているため、削除できなかった退避ファイルは残して `recover` で後始末する
+    // ([`leftover_backups`] で確認できる)
+    for (_, backup) in &done {
+        let _ = fs::remove_file(backup);
     }
 
     ReplaceResult::Replaced
 }
 
+/// 組の置換後に残っている退避ファイルを返す
+///
+/// Args:
+///     pairs: [`replace_set_with_links`] に渡した (source, target) の組のリスト
+pub fn leftover_backups(pairs: &[(PathBuf, PathBuf)]) -> Vec<PathBuf> {
+    pairs
+        .iter()
+        .map(|(_, target)| backup_path(target))
+        .filter(|backup| fs::symlink_metadata(backup).is_ok())
+        .collect()
+}
+
+/// リンク作成の失敗を、reflink非対応・リンク数上限なら専用の結果に置き換える
+///
+/// Args:
+///     strategy: リンク方式
+///     e: リンク作成のエラー
+///     result: targetを元に戻した (または触れていない) ときの結果
+fn link_failure(strategy: LinkStrategy, e: &io::Error, result: ReplaceResult) -> ReplaceResult {
+    match result {
+        ReplaceResult::RolledBack(cause) | ReplaceResult::Error(cause)
+            if strategy == LinkStrategy::Reflink && is_reflink_unsupported(e) =>
+        {
+            ReplaceResult::ReflinkUnsupported(cause)
+        }
+        ReplaceResult::RolledBack(cause) | ReplaceResult::Error(cause) if is_too_many_links(e) => {
+            ReplaceResult::TooManyLinks(cause)
+        }
+        result => result,
+    }
+}
+
+/// 組の置換途中で失敗したとき、退避しておいたリンクで置換済みのtargetを全て元に戻す
+fn rollback_set(done: &[(&PathBuf, PathBuf)], cause: String) -> ReplaceResult {
+    if done.is_empty() {
+        return ReplaceResult::Error(cause);
+    }
+
+    let mut failures = Vec::new();
+    for (target, backup) in done.iter().rev() {
+        if let Err(e) = fs::rename(backup, target) {
+            failures.push(format!("{}: {}", target.display(), e));
+        }
+    }
+
+    if failures.is_empty() {
+        ReplaceResult::RolledBack(format!("{} (ロールバック成功)", cause))
+    } else {
+        ReplaceResult::RollbackFailed(format!(
+            "{} (ロールバック失敗: {})",
+            cause,
+            failures.join(", ")
+        ))
+    }
+}
+
+/// ハードリンクされたファイルを内容の同じ独立したファイル (別inode) に置き換える
+///
+/// 同じディレクトリに一時コピーを作り、`replace_with_hardlink` と同じく
+/// renameでtargetに被せる。失敗してもtargetは元のまま。
+///
+/// Args:
+///     target: ハードリンクを解除するファイル
+///     mode: コピーに設定するパーミッション (Noneならtargetのもの)
+///     modified: コピーに設定する更新時刻 (Noneならtargetのもの)
+///
+/// Returns:
+///     解除結果
+#[cfg(unix)]
+pub fn break_hardlink(
+    target: &Path,
+    mode: Option<u32>,
+    modified: Option<SystemTime>,
+) -> UnshareResult {
+    let metadata = match fs::metadata(target) {
+        Ok(m) => m,
+        Err(e) => return UnshareResult::Error(e.to_string()),
+    };
+    if metadata.nlink() <= 1 {
+        return UnshareResult::NotShared;
+    }
+
+    let copy = temp_path(target);
+    let mode = mode.unwrap_or(metadata.mode() & 0o7777);
+    if let Err(e) = write_private_copy(target, &copy, mode, modified.or(metadata.modified().ok())) {
+        remove_if_regular_file(&copy);
+        return UnshareResult::Error(format!("コピー作成失敗: {}", e));
+    }
+
+    // 元のinodeは他のリンクが残っているので、renameで直接置き換える
+    if let Err(e) = fs::rename(&copy, target) {
+        remove_if_regular_file(&copy);
+        return UnshareResult::Error(format!("コピー配置失敗: {}", e));
+    }
+
+    UnshareResult::Unshared
+}
+
+#[cfg(not(unix))]
+pub fn break_hardlink(
+    _target: &Path,
+    _mode: Option<u32>,
+    _modified: Option<SystemTime>,
+) -> UnshareResult {
+    UnshareResult::NotShared
+}
+
+#[cfg(unix)]
+fn write_private_copy(
+    source: &Path,
+    copy: &Path,
+    mode: u32,
+    modified: Option<SystemTime>,
+) -> io::Result<()> {
+    fs::copy(source, copy)?;
+    // looseオブジェクトは読み取り専用なので、時刻は読み取りで開いたまま設定する
+    let file = File::open(copy)?;
+    if let Some(modified) = modified {
+        file.set_modified(modified)?;
+    }
+    fs::set_permissions(copy, fs::Permissions::from_mode(mode))?;
+    file.sync_all()
+}
+
+fn temp_path(target: &Path) -> PathBuf {
+    let file_name = target
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_else(|| "target".to_string());
+    target.with_file_name(format!("{}{}", file_name, TEMP_SUFFIX))
+}
+
 fn backup_path(target: &Path) -> PathBuf {
     let file_name = target
         .file_name()
         .map(|n| n.to_string_lossy().to_string())
         .unwrap_or_else(|| "target".to_string());
-    target.with_file_name(format!("{}.git-share-obj.bak", file_name))
+    target.with_file_name(format!("{}{}", file_name, BACKUP_SUFFIX))
 }
 
 fn remove_if_regular_file(path: &Path) {
@@ -138,10 +670,36 @@ fn remove_if_regular_file(path: &Path) {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::fs::File;
+    use crate::object::tests::write_loose_object;
+    use crate::scanner::ObjectFormat;
     use std::io::Write;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread;
     use tempfile::TempDir;
 
+    /// 置換を繰り返す間、別スレッドでtargetを読み続け、読めない瞬間が無いことを確かめる
+    fn assert_always_readable<F: FnMut()>(target: &Path, expected: &[u8], mut replace: F) {
+        let stop = Arc::new(AtomicBool::new(false));
+        let reader = {
+            let (target, expected, stop) =
+                (target.to_path_buf(), expected.to_vec(), Arc::clone(&stop));
+            thread::spawn(move || {
+                let mut reads = 0usize;
+                while !stop.load(Ordering::Relaxed) {
+                    assert_eq!(fs::read(&target).unwrap(), expected);
+                    reads += 1;
+                }
+                reads
+            })
+        };
+        for _ in 0..200 {
+            replace();
+        }
+        stop.store(true, Ordering::Relaxed);
+        assert!(reader.join().unwrap() > 0);
+    }
+
     #[test]
     fn test_is_same_filesystem_same_dir() {
         let temp_dir = TempDir::new().unwrap();
@@ -217,4 +775,236 @@ mod tests {
         assert!(matches!(result, ReplaceResult::Error(_)));
     }
 
+    #[test]
+    fn test_is_same_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let file1 = temp_dir.path().join("file1");
+        let file2 = temp_dir.path().join("file2");
+        let file3 = temp_dir.path().join("file3");
+        fs::write(&file1, b"same content").unwrap();
+        fs::write(&file2, b"same content").unwrap();
+        fs::write(&file3, b"diff content").unwrap();
+
+        assert!(is_same_content(&file1, &file2).unwrap());
+        assert!(!is_same_content(&file1, &file3).unwrap());
+    }
+
+    #[test]
+    fn test_replace_verified_content_mismatch_keeps_target() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo1 = temp_dir.path().join("repo1");
+        let repo2 = temp_dir.path().join("repo2");
+        let (source_path, _) = write_loose_object(&repo1, ObjectFormat::Sha1, b"hi\n");
+        let (target, _) = write_loose_object(&repo2, ObjectFormat::Sha1, b"hi\n");
+        // targetを破損させる (同じ名前で内容が異なる)
+        fs::write(&target, b"truncated").unwrap();
+
+        let source = GitObjectInfo::from_path(&source_path).unwrap();
+        let result = replace_with_hardlink_verified(&source, &target, VerifyMode::Content);
+        assert!(matches!(result, ReplaceResult::ContentMismatch(_)));
+        assert!(!is_same_inode(&source_path, &target).unwrap());
+        assert_eq!(fs::read(&target).unwrap(), b"truncated");
+    }
+
+    #[test]
+    fn test_replace_verified_hash_detects_corrupt_source() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo1 = temp_dir.path().join("repo1");
+        let repo2 = temp_dir.path().join("repo2");
+        let (source_path, _) = write_loose_object(&repo1, ObjectFormat::Sha1, b"hi\n");
+        let (target, _) = write_loose_object(&repo2, ObjectFormat::Sha1, b"hi\n");
+        // 両方を同じ壊れた内容にする (バイト比較では一致する)
+        fs::write(&source_path, b"corrupt").unwrap();
+        fs::write(&target, b"corrupt").unwrap();
+
+        let source = GitObjectInfo::from_path(&source_path).unwrap();
+        assert_eq!(
+            replace_with_hardlink_verified(&source, &target, VerifyMode::Content),
+            ReplaceResult::Replaced
+        );
+
+        fs::remove_file(&target).unwrap();
+        fs::write(&target, b"corrupt").unwrap();
+        let result = replace_with_hardlink_verified(&source, &target, VerifyMode::Hash);
+        assert!(matches!(result, ReplaceResult::ContentMismatch(_)));
+        assert!(!is_same_inode(&source_path, &target).unwrap());
+    }
+
+    #[test]
+    fn test_replace_verified_hash_success() {
+        let temp_dir = TempDir::new().unwrap();
+        let (source_path, _) =
+            write_loose_object(&temp_dir.path().join("repo1"), ObjectFormat::Sha1, b"hi\n");
+        let (target, _) =
+            write_loose_object(&temp_dir.path().join("repo2"), ObjectFormat::Sha1, b"hi\n");
+
+        let source = GitObjectInfo::from_path(&source_path).unwrap();
+        let result = replace_with_hardlink_verified(&source, &target, VerifyMode::Hash);
+        assert_eq!(result, ReplaceResult::Replaced);
+        assert!(is_same_inode(&source_path, &target).unwrap());
+    }
+
+    #[test]
+    fn test_replace_set_with_hardlinks_success() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut pairs = Vec::new();
+        for ext in ["pack", "idx"] {
+            let source = temp_dir.path().join(format!("a.{}", ext));
+            let target = temp_dir.path().join(format!("b.{}", ext));
+            fs::write(&source, ext).unwrap();
+            fs::write(&target, ext).unwrap();
+            pairs.push((source, target));
+        }
+
+        assert_eq!(replace_set_with_hardlinks(&pairs), ReplaceResult::Replaced);
+        for (source, target) in &pairs {
+            assert!(is_same_inode(source, target).unwrap());
+        }
+        assert!(leftover_backups(&pairs).is_empty());
+        assert_eq!(
+            replace_set_with_hardlinks(&pairs),
+            ReplaceResult::AlreadyLinked
+        );
+    }
+
+    #[test]
+    fn test_replace_set_with_hardlinks_rolls_back_all() {
+        let temp_dir = TempDir::new().unwrap();
+        let source_pack = temp_dir.path().join("a.pack");
+        let target_pack = temp_dir.path().join("b.pack");
+        fs::write(&source_pack, b"pack").unwrap();
+        fs::write(&target_pack, b"pack").unwrap();
+
+        // 2つ目の組はsourceがディレクトリなのでハードリンク作成に失敗する
+        let source_idx = temp_dir.path().join("a.idx");
+        let target_idx = temp_dir.path().join("b.idx");
+        fs::create_dir(&source_idx).unwrap();
+        fs::write(&target_idx, b"idx").unwrap();
+        let pairs = vec![
+            (source_pack.clone(), target_pack.clone()),
+            (source_idx, target_idx.clone()),
+        ];
+
+        let result = replace_set_with_hardlinks(&pairs);
+        assert!(
+            matches!(result, ReplaceResult::RolledBack(_)),
+            "{:?}",
+            result
+        );
+        assert!(!is_same_inode(&source_pack, &target_pack).unwrap());
+        assert_eq!(fs::read(&target_pack).unwrap(), b"pack");
+        assert_eq!(fs::read(&target_idx).unwrap(), b"idx");
+        assert!(!temp_dir.path().join("b.pack.git-share-obj.bak").exists());
+    }
+
+    #[test]
+    fn test_break_hardlink_restores_independent_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let target = temp_dir.path().join("target");
+        fs::write(&source, b"content").unwrap();
+        fs::hard_link(&source, &target).unwrap();
+        fs::set_permissions(&target, fs::Permissions::from_mode(0o444)).unwrap();
+
+        let modified = SystemTime::UNIX_EPOCH + std::time::Duration::from_secs(1_600_000_000);
+        assert_eq!(
+            break_hardlink(&target, Some(0o440), Some(modified)),
+            UnshareResult::Unshared
+        );
+        assert!(!is_same_inode(&source, &target).unwrap());
+        assert_eq!(fs::read(&target).unwrap(), b"content");
+
+        let meta = fs::metadata(&target).unwrap();
+        assert_eq!(link_count(&source).unwrap(), 1);
+        assert_eq!(meta.mode() & 0o777, 0o440);
+        assert_eq!(meta.modified().unwrap(), modified);
+        assert!(!temp_dir.path().join("target.git-share-obj.bak").exists());
+        assert!(!temp_dir.path().join("target.git-share-obj.tmp").exists());
+
+        assert_eq!(
+            break_hardlink(&target, None, None),
+            UnshareResult::NotShared
+        );
+    }
+
+    #[test]
+    fn test_replace_with_reflink_or_unsupported() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let target = temp_dir.path().join("target");
+        fs::write(&source, b"same content").unwrap();
+        fs::write(&target, b"same content").unwrap();
+
+        let result = replace_checked(&source, &target, LinkStrategy::Reflink, || Ok(()));
+        if probe_reflink(temp_dir.path()) {
+            // btrfs/XFSなど: inodeは別のまま内容を共有する
+            assert_eq!(result, ReplaceResult::Replaced);
+            assert!(!is_same_inode(&source, &target).unwrap());
+            assert_eq!(
+                LinkMode::Auto.resolve(temp_dir.path()),
+                LinkStrategy::Reflink
+            );
+        } else {
+            // tmpfs/ext4など: 区別できる結果を返し、targetは元のまま
+            assert!(matches!(result, ReplaceResult::ReflinkUnsupported(_)));
+            assert_eq!(
+                LinkMode::Auto.resolve(temp_dir.path()),
+                LinkStrategy::Hardlink
+            );
+        }
+        assert_eq!(fs::read(&target).unwrap(), b"same content");
+        assert!(!temp_dir.path().join("target.git-share-obj.bak").exists());
+        assert_eq!(fs::read_dir(temp_dir.path()).unwrap().count(), 2);
+    }
+
+    #[test]
+    fn test_replace_keeps_target_readable_for_concurrent_readers() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let target = temp_dir.path().join("target");
+        let fresh = temp_dir.path().join("fresh");
+        fs::write(&source, b"object").unwrap();
+        fs::write(&target, b"object").unwrap();
+
+        assert_always_readable(&target, b"object", || {
+            // targetを別inodeの同じ内容に戻してから置換する
+            fs::write(&fresh, b"object").unwrap();
+            fs::rename(&fresh, &target).unwrap();
+            assert_eq!(
+                replace_with_hardlink(&source, &target),
+                ReplaceResult::Replaced
+            );
+            assert!(is_same_inode(&source, &target).unwrap());
+        });
+        assert!(!temp_dir.path().join("target.git-share-obj.tmp").exists());
+    }
+
+    #[test]
+    fn test_replace_set_keeps_targets_readable_for_concurrent_readers() {
+        let temp_dir = TempDir::new().unwrap();
+        let pairs: Vec<_> = ["pack", "idx"]
+            .iter()
+            .map(|ext| {
+                (
+                    temp_dir.path().join(format!("a.{}", ext)),
+                    temp_dir.path().join(format!("b.{}", ext)),
+                )
+            })
+            .collect();
+        for (source, target) in &pairs {
+            fs::write(source, b"data").unwrap();
+            fs::write(target, b"data").unwrap();
+        }
+        let fresh = temp_dir.path().join("fresh");
+
+        assert_always_readable(&pairs[0].1, b"data", || {
+            for (_, target) in &pairs {
+                fs::write(&fresh, b"data").unwrap();
+                fs::rename(&fresh, target).unwrap();
+            }
+            assert_eq!(replace_set_with_hardlinks(&pairs), ReplaceResult::Replaced);
+        });
+        assert!(!temp_dir.path().join("b.pack.git-share-obj.bak").exists());
+        assert!(!temp_dir.path().join("b.pack.git-share-obj.tmp").exists());
+    }
 }
@@ -48,14 +48,26 @@ pub enum Msg {
     DuplicateFiles,
     Processing,
     ProcessingDevice,
+    LinkStrategyForDevice,
     DeviceGroups,
     ScanningPath,
     CheckingDirectory,
+    FoundPacks,
+    FoundDuplicatePacks,
+    DuplicatePack,
+    ScanningObjects,
+    CacheLoaded,
+    CacheSaveFailed,
 
     // 結果メッセージ
     Replaced,
     AlreadyLinked,
     CrossFilesystem,
+    ReflinkUnsupported,
+    NewSourceForLinkLimit,
+    OwnerMismatch,
+    ModeNormalized,
+    ContentMismatch,
     ErrorOccurred,
 
     // サマリー
@@ -64,12 +76,18 @@ pub enum Msg {
     TotalDuplicates,
     TotalReplaced,
     TotalSkipped,
+    TotalContentMismatch,
+    TotalReflinkUnsupported,
+    TotalNewSources,
+    TotalOwnerMismatch,
+    TotalNormalized,
     TotalErrors,
+    PackSummary,
 
     // 削減容量
     GroupSavings,
     EstimatedSavings,
-    TotalSavings,
+    ActualSavings,
 
     // fsck
     FsckRunning,
@@ -79,6 +97,9 @@ pub enum Msg {
     FsckOnlyComplete,
     FsckSkipped,
     AbortOnFsckFailure,
+    SignalHandlerFailed,
+    Interrupted,
+    FsckTouchedOnly,
 
     // lock
     LockingRepo,
@@ -86,10 +107,74 @@ pub enum Msg {
     LockFailed,
     LockSkipped,
     LockSummary,
+    AbortOnLockFailure,
+    ExcludedLockFailed,
+    AbortOnAllOrNothing,
+    TreeLockAcquired,
+    TreeLockFailed,
 
     // rollback
     RollbackOccurred,
     RollbackFailed,
+
+    // journal / restore
+    JournalWritten,
+    JournalWriteFailed,
+    BackupLeftover,
+    JournalReadFailed,
+    JournalNotFound,
+    Restoring,
+    Restored,
+    RestoreSkipped,
+    RestoreSummary,
+    TotalRestored,
+
+    // unshare
+    UnshareTarget,
+    Unshared,
+    UnshareSummary,
+    TotalUnshared,
+
+    // pool
+    PoolDir,
+    PoolAdded,
+    PoolCrossFilesystem,
+    PoolUnavailable,
+    PoolRemoved,
+    PoolGcSummary,
+    TotalPoolAdded,
+    TotalPoolKept,
+    TotalPoolRemoved,
+
+    // alternates
+    AlternatesStore,
+    AlternatesStoreUnavailable,
+    AlternatesRejected,
+    AlternatesLinked,
+    AlternatesRemoved,
+    AlternatesSummary,
+    TotalAlternatesLinked,
+    TotalStored,
+    TotalLocalRemoved,
+
+    // recover
+    RecoverFound,
+    RecoverRestored,
+    RecoverRemoved,
+    RecoverKept,
+    RecoverSummary,
+    TotalRecovered,
+    TotalLeftoverRemoved,
+    TotalLeftoverKept,
+
+    // locks
+    LockFileRemoved,
+    LocksSummary,
+    TotalLocksHeld,
+    TotalLocksStale,
+    TotalLocksFree,
+    TotalLockFilesRemoved,
+    RepositoryNotFound,
 }
 
 /// ローカライズされたメッセージを取得する
@@ -116,14 +201,26 @@ fn msg_ja(key: Msg) -> &'static str {
         Msg::DuplicateFiles => "重複ファイル",
         Msg::Processing => "処理中...",
         Msg::ProcessingDevice => "デバイス処理中",
+        Msg::LinkStrategyForDevice => "リンク方式",
         Msg::DeviceGroups => "デバイスグループ",
         Msg::ScanningPath => "探索ルート",
         Msg::CheckingDirectory => "確認中ディレクトリ",
+        Msg::FoundPacks => "packファイル発見",
+        Msg::FoundDuplicatePacks => "重複packグループ発見",
+        Msg::DuplicatePack => "重複pack",
+        Msg::ScanningObjects => "オブジェクト探索中",
+        Msg::CacheLoaded => "探索キャッシュ読み込み",
+        Msg::CacheSaveFailed => "探索キャッシュ保存失敗",
 
         // 結果メッセージ
         Msg::Replaced => "置換完了",
         Msg::AlreadyLinked => "既にリンク済み",
         Msg::CrossFilesystem => "ファイルシステム跨ぎのためスキップ",
+        Msg::ReflinkUnsupported => "reflink非対応のためスキップ",
+        Msg::NewSourceForLinkLimit => "リンク数の上限のため新しいsourceにする",
+        Msg::OwnerMismatch => "所有者・モードが異なるため共有しない",
+        Msg::ModeNormalized => "共有するファイルのモードをそろえました",
+        Msg::ContentMismatch => "内容不一致のためスキップ",
         Msg::ErrorOccurred => "エラー",
 
         // サマリー
@@ -132,12 +229,18 @@ fn msg_ja(key: Msg) -> &'static str {
         Msg::TotalDuplicates => "重複ファイル総数",
         Msg::TotalReplaced => "置換成功",
         Msg::TotalSkipped => "スキップ",
+        Msg::TotalContentMismatch => "内容不一致",
+        Msg::TotalReflinkUnsupported => "reflink非対応",
+        Msg::TotalNewSources => "リンク数上限による新しいsource",
+        Msg::TotalOwnerMismatch => "所有者・モードの違いで共有しなかった数",
+        Msg::TotalNormalized => "モードをそろえたファイル",
         Msg::TotalErrors => "エラー",
+        Msg::PackSummary => "--- packファイル ---",
 
         // 削減容量
         Msg::GroupSavings => "グループ削減容量",
-        Msg::EstimatedSavings => "見込み削減容量",
-        Msg::TotalSavings => "合計削減容量",
+        Msg::EstimatedSavings => "見込み削減容量 (論理サイズ)",
+        Msg::ActualSavings => "実削減容量 (ディスク使用量)",
 
         // fsck
         Msg::FsckRunning => "fsck実行中",
@@ -147,6 +250,9 @@ fn msg_ja(key: Msg) -> &'static str {
         Msg::FsckOnlyComplete => "=== fsckのみ完了 ===",
         Msg::FsckSkipped => "fsckスキップ (--no-fsck)",
         Msg::AbortOnFsckFailure => "fsck失敗のため置換処理を中止",
+        Msg::SignalHandlerFailed => "シグナルハンドラを設定できません",
+        Msg::Interrupted => "シグナルを受け取ったため、処理中の置換を終えて中断しました",
+        Msg::FsckTouchedOnly => "post-fsck対象 (置換したオブジェクト・packのみ)",
 
         // lock
         Msg::LockingRepo => "ロック取得中",
@@ -154,10 +260,74 @@ fn msg_ja(key: Msg) -> &'static str {
         Msg::LockFailed => "ロック取得失敗",
         Msg::LockSkipped => "ロック処理スキップ (--no-lock)",
         Msg::LockSummary => "ロック集計",
+        Msg::AbortOnLockFailure => "ロックできないリポジトリがあるため中止 (--fail-on-busy)",
+        Msg::ExcludedLockFailed => "ロックできずに除外",
+        Msg::AbortOnAllOrNothing => "ロックできないリポジトリがあるため中止 (--all-or-nothing)",
+        Msg::TreeLockAcquired => "ツリーロック取得",
+        Msg::TreeLockFailed => "ツリーロック取得失敗",
 
         // rollback
         Msg::RollbackOccurred => "ロールバック",
         Msg::RollbackFailed => "ロールバック失敗",
+
+        // journal / restore
+        Msg::JournalWritten => "journal記録先",
+        Msg::JournalWriteFailed => "journal書き込み失敗",
+        Msg::BackupLeftover => "退避ファイルを削除できず残した (recoverで後始末)",
+        Msg::JournalReadFailed => "journal読み込み失敗",
+        Msg::JournalNotFound => "journalが見つかりません",
+        Msg::Restoring => "restore対象journal",
+        Msg::Restored => "独立したファイルに復元",
+        Msg::RestoreSkipped => "リンクされていないためスキップ",
+        Msg::RestoreSummary => "=== restore完了 ===",
+        Msg::TotalRestored => "復元",
+
+        // unshare
+        Msg::UnshareTarget => "unshare対象",
+        Msg::Unshared => "ハードリンク解除",
+        Msg::UnshareSummary => "=== unshare完了 ===",
+        Msg::TotalUnshared => "解除",
+
+        // pool
+        Msg::PoolDir => "プール",
+        Msg::PoolAdded => "プールに登録",
+        Msg::PoolCrossFilesystem => "プールと異なるファイルシステムのためスキップ (デバイス)",
+        Msg::PoolUnavailable => "プールを使用できません",
+        Msg::PoolRemoved => "プールから削除",
+        Msg::PoolGcSummary => "=== pool gc完了 ===",
+        Msg::TotalPoolAdded => "プール登録",
+        Msg::TotalPoolKept => "残したエントリ",
+        Msg::TotalPoolRemoved => "削除したエントリ",
+
+        // alternates
+        Msg::AlternatesStore => "共有ストア",
+        Msg::AlternatesStoreUnavailable => "共有ストアを使用できません",
+        Msg::AlternatesRejected => "alternatesを追加できないためスキップ",
+        Msg::AlternatesLinked => "alternatesに追加",
+        Msg::AlternatesRemoved => "ローカルのコピーを削除",
+        Msg::AlternatesSummary => "=== alternates完了 ===",
+        Msg::TotalAlternatesLinked => "alternatesを追加したリポジトリ",
+        Msg::TotalStored => "ストアに追加したオブジェクト",
+        Msg::TotalLocalRemoved => "削除したローカルのオブジェクト",
+
+        // recover
+        Msg::RecoverFound => "中断された実行の残りファイル",
+        Msg::RecoverRestored => "残りファイルから復元",
+        Msg::RecoverRemoved => "不要な残りファイルを削除",
+        Msg::RecoverKept => "自動で処理できないため残しました",
+        Msg::RecoverSummary => "=== recover完了 ===",
+        Msg::TotalRecovered => "復元したファイル",
+        Msg::TotalLeftoverRemoved => "削除した残りファイル",
+        Msg::TotalLeftoverKept => "残した残りファイル",
+
+        // locks
+        Msg::LockFileRemoved => "ロックファイルを削除",
+        Msg::LocksSummary => "=== ロックファイル ===",
+        Msg::TotalLocksHeld => "使用中のロック",
+        Msg::TotalLocksStale => "保持者が異常終了したロック (stale)",
+        Msg::TotalLocksFree => "解放済みのロックファイル",
+        Msg::TotalLockFilesRemoved => "削除したロックファイル",
+        Msg::RepositoryNotFound => "Gitリポジトリではありません",
     }
 }
 
@@ -176,14 +346,26 @@ fn msg_en(key: Msg) -> &'static str {
         Msg::DuplicateFiles => "duplicate files",
         Msg::Processing => "Processing...",
         Msg::ProcessingDevice => "Processing device",
+        Msg::LinkStrategyForDevice => "Link strategy",
         Msg::DeviceGroups => "device groups",
         Msg::ScanningPath => "Scanning root",
         Msg::CheckingDirectory => "Checking directory",
+        Msg::FoundPacks => "pack files found",
+        Msg::FoundDuplicatePacks => "duplicate pack groups found",
+        Msg::DuplicatePack => "duplicate pack",
+        Msg::ScanningObjects => "Scanning objects",
+        Msg::CacheLoaded => "Scan cache loaded",
+        Msg::CacheSaveFailed => "Failed to save scan cache",
 
         // Results
         Msg::Replaced => "Replaced",
         Msg::AlreadyLinked => "Already linked",
         Msg::CrossFilesystem => "Skipped (cross-filesystem)",
+        Msg::ReflinkUnsupported => "Skipped (reflink not supported)",
+        Msg::NewSourceForLinkLimit => "Link limit reached, using as a new source",
+        Msg::OwnerMismatch => "Not shared (different owner or mode)",
+        Msg::ModeNormalized => "Normalized mode of shared file",
+        Msg::ContentMismatch => "Skipped (content mismatch)",
         Msg::ErrorOccurred => "Error",
 
         // Summary
@@ -192,12 +374,18 @@ fn msg_en(key: Msg) -> &'static str {
         Msg::TotalDuplicates => "Total duplicates",
         Msg::TotalReplaced => "Replaced",
         Msg::TotalSkipped => "Skipped",
+        Msg::TotalContentMismatch => "Content mismatches",
+        Msg::TotalReflinkUnsupported => "Reflink not supported",
+        Msg::TotalNewSources => "New sources (link limit)",
+        Msg::TotalOwnerMismatch => "Not shared (owner or mode)",
+        Msg::TotalNormalized => "Files with normalized mode",
         Msg::TotalErrors => "Errors",
+        Msg::PackSummary => "--- pack files ---",
 
         // Savings
         Msg::GroupSavings => "Group savings",
-        Msg::EstimatedSavings => "Estimated savings",
-        Msg::TotalSavings => "Total savings",
+        Msg::EstimatedSavings => "Estimated savings (logical size)",
+        Msg::ActualSavings => "Actual savings (disk usage)",
 
         // fsck
         Msg::FsckRunning => "Running fsck",
@@ -207,6 +395,9 @@ fn msg_en(key: Msg) -> &'static str {
         Msg::FsckOnlyComplete => "=== fsck-only complete ===",
         Msg::FsckSkipped => "fsck skipped (--no-fsck)",
         Msg::AbortOnFsckFailure => "Aborting replacement due to fsck failure",
+        Msg::SignalHandlerFailed => "Failed to install signal handler",
+        Msg::Interrupted => "Interrupted by signal; stopped after the in-flight replacement",
+        Msg::FsckTouchedOnly => "post-fsck targets (replaced objects and packs only)",
 
         // lock
         Msg::LockingRepo => "Acquiring lock",
@@ -214,10 +405,78 @@ fn msg_en(key: Msg) -> &'static str {
         Msg::LockFailed => "Lock failed",
         Msg::LockSkipped => "Locking skipped (--no-lock)",
         Msg::LockSummary => "lock summary",
+        Msg::AbortOnLockFailure => {
+            "Aborted: some repositories could not be locked (--fail-on-busy)"
+        }
+        Msg::ExcludedLockFailed => "Excluded (lock not acquired)",
+        Msg::AbortOnAllOrNothing => {
+            "Aborted: not all repositories could be locked (--all-or-nothing)"
+        }
+        Msg::TreeLockAcquired => "Tree lock acquired",
+        Msg::TreeLockFailed => "Tree lock failed",
 
         // rollback
         Msg::RollbackOccurred => "Rollback",
         Msg::RollbackFailed => "Rollback failed",
+
+        // journal / restore
+        Msg::JournalWritten => "Journal written",
+        Msg::JournalWriteFailed => "Failed to write journal",
+        Msg::BackupLeftover => "Backup file left behind (clean up with recover)",
+        Msg::JournalReadFailed => "Failed to read journal",
+        Msg::JournalNotFound => "No journal found",
+        Msg::Restoring => "Restoring from journal",
+        Msg::Restored => "Restored as independent file",
+        Msg::RestoreSkipped => "Skipped (no longer linked)",
+        Msg::RestoreSummary => "=== Restore Complete ===",
+        Msg::TotalRestored => "Restored",
+
+        // unshare
+        Msg::UnshareTarget => "Unshare target",
+        Msg::Unshared => "Unshared",
+        Msg::UnshareSummary => "=== Unshare Complete ===",
+        Msg::TotalUnshared => "Unshared",
+
+        // pool
+        Msg::PoolDir => "Pool",
+        Msg::PoolAdded => "Added to pool",
+        Msg::PoolCrossFilesystem => "Skipped (different filesystem from pool, device)",
+        Msg::PoolUnavailable => "Pool is not available",
+        Msg::PoolRemoved => "Removed from pool",
+        Msg::PoolGcSummary => "=== Pool GC Complete ===",
+        Msg::TotalPoolAdded => "Added to pool",
+        Msg::TotalPoolKept => "Kept entries",
+        Msg::TotalPoolRemoved => "Removed entries",
+
+        // alternates
+        Msg::AlternatesStore => "Shared store",
+        Msg::AlternatesStoreUnavailable => "Shared store is not available",
+        Msg::AlternatesRejected => "Skipped (cannot add alternates)",
+        Msg::AlternatesLinked => "Added to alternates",
+        Msg::AlternatesRemoved => "Removed local copy",
+        Msg::AlternatesSummary => "=== Alternates Complete ===",
+        Msg::TotalAlternatesLinked => "Repositories linked",
+        Msg::TotalStored => "Objects added to store",
+        Msg::TotalLocalRemoved => "Local objects removed",
+
+        // recover
+        Msg::RecoverFound => "Leftover from an interrupted run",
+        Msg::RecoverRestored => "Restored from leftover",
+        Msg::RecoverRemoved => "Removed redundant leftover",
+        Msg::RecoverKept => "Kept leftover (needs manual check)",
+        Msg::RecoverSummary => "=== Recover Complete ===",
+        Msg::TotalRecovered => "Files restored",
+        Msg::TotalLeftoverRemoved => "Leftovers removed",
+        Msg::TotalLeftoverKept => "Leftovers kept",
+
+        // locks
+        Msg::LockFileRemoved => "Removed lock file",
+        Msg::LocksSummary => "=== Lock Files ===",
+        Msg::TotalLocksHeld => "Held",
+        Msg::TotalLocksStale => "Stale (holder exited abnormally)",
+        Msg::TotalLocksFree => "Free",
+        Msg::TotalLockFilesRemoved => "Lock files removed",
+        Msg::RepositoryNotFound => "Not a Git repository",
     }
 }
 
@@ -246,22 +505,40 @@ mod tests {
             Msg::DuplicateFiles,
             Msg::Processing,
             Msg::ProcessingDevice,
+            Msg::LinkStrategyForDevice,
             Msg::DeviceGroups,
             Msg::ScanningPath,
             Msg::CheckingDirectory,
+            Msg::FoundPacks,
+            Msg::FoundDuplicatePacks,
+            Msg::DuplicatePack,
+            Msg::ScanningObjects,
+            Msg::CacheLoaded,
+            Msg::CacheSaveFailed,
             Msg::Replaced,
             Msg::AlreadyLinked,
             Msg::CrossFilesystem,
+            Msg::ReflinkUnsupported,
+            Msg::NewSourceForLinkLimit,
+            Msg::OwnerMismatch,
+            Msg::ModeNormalized,
+            Msg::ContentMismatch,
             Msg::ErrorOccurred,
             Msg::SummaryDryRun,
             Msg::SummaryComplete,
             Msg::TotalDuplicates,
             Msg::TotalReplaced,
             Msg::TotalSkipped,
+            Msg::TotalContentMismatch,
+            Msg::TotalReflinkUnsupported,
+            Msg::TotalNewSources,
+            Msg::TotalOwnerMismatch,
+            Msg::TotalNormalized,
             Msg::TotalErrors,
+            Msg::PackSummary,
             Msg::GroupSavings,
             Msg::EstimatedSavings,
-            Msg::TotalSavings,
+            Msg::ActualSavings,
             Msg::FsckRunning,
             Msg::FsckOk,
             Msg::FsckFailed,
@@ -269,13 +546,68 @@ mod tests {
             Msg::FsckOnlyComplete,
             Msg::FsckSkipped,
             Msg::AbortOnFsckFailure,
+            Msg::SignalHandlerFailed,
+            Msg::Interrupted,
+            Msg::FsckTouchedOnly,
             Msg::LockingRepo,
             Msg::LockAcquired,
             Msg::LockFailed,
             Msg::LockSkipped,
             Msg::LockSummary,
+            Msg::AbortOnLockFailure,
+            Msg::ExcludedLockFailed,
+            Msg::AbortOnAllOrNothing,
+            Msg::TreeLockAcquired,
+            Msg::TreeLockFailed,
             Msg::RollbackOccurred,
             Msg::RollbackFailed,
+            Msg::JournalWritten,
+            Msg::JournalWriteFailed,
+            Msg::BackupLeftover,
+            Msg::JournalReadFailed,
+            Msg::JournalNotFound,
+            Msg::Restoring,
+            Msg::Restored,
+            Msg::RestoreSkipped,
+            Msg::RestoreSummary,
+            Msg::TotalRestored,
+            Msg::UnshareTarget,
+            Msg::Unshared,
+            Msg::UnshareSummary,
+            Msg::TotalUnshared,
+            Msg::PoolDir,
+            Msg::PoolAdded,
+            Msg::PoolCrossFilesystem,
+            Msg::PoolUnavailable,
+            Msg::PoolRemoved,
+            Msg::PoolGcSummary,
+            Msg::TotalPoolAdded,
+            Msg::TotalPoolKept,
+            Msg::TotalPoolRemoved,
+            Msg::AlternatesStore,
+            Msg::AlternatesStoreUnavailable,
+            Msg::AlternatesRejected,
+            Msg::AlternatesLinked,
+            Msg::AlternatesRemoved,
+            Msg::AlternatesSummary,
+            Msg::TotalAlternatesLinked,
+            Msg::TotalStored,
+            Msg::TotalLocalRemoved,
+            Msg::RecoverFound,
+            Msg::RecoverRestored,
+            Msg::RecoverRemoved,
+            Msg::RecoverKept,
+            Msg::RecoverSummary,
+            Msg::TotalRecovered,
+            Msg::TotalLeftoverRemoved,
+            Msg::TotalLeftoverKept,
+            Msg::LockFileRemoved,
+            Msg::LocksSummary,
+            Msg::TotalLocksHeld,
+            Msg::TotalLocksStale,
+            Msg::TotalLocksFree,
+            Msg::TotalLockFilesRemoved,
+            Msg::RepositoryNotFound,
         ];
 
         for key in keys {
@@ -0,0 +1,306 @@
+//! 置換の記録 (undo journal) と復元
+//!
+//! 置換を行う実行ごとに1ファイルをjournalディレクトリ ([`crate::dirs::StateDirs::journal`]) に作り、
+//! 置換したファイルごとに (source, target, 置換前のinode/パーミッション/更新時刻) を
+//! 1行ずつ追記する。`restore` はこの記録をもとにハードリンクを解除する。
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{self, Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::SystemTime;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+use crate::cache::{escape, from_timestamp, to_timestamp, unescape, Timestamp};
+use crate::hardlink::{break_hardlink, is_same_inode, UnshareResult};
+
+const JOURNAL_HEADER: &str = "git-share-obj journal v1";
+
+/// 置換1件の記録
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JournalEntry {
+    /// リンク元
+    pub source: PathBuf,
+    /// 置換したファイル
+    pub target: PathBuf,
+    /// 置換前のtargetのinode番号
+    pub inode: u64,
+    /// 置換前のtargetのパーミッション
+    pub mode: u32,
+    /// 置換前のtargetの更新時刻
+    pub modified: Timestamp,
+}
+
+impl JournalEntry {
+    /// 置換前のtargetの状態を記録する
+    ///
+    /// Args:
+    ///     source: リンク元
+    ///     target: これから置換するファイル
+    ///
+    /// Returns:
+    ///     targetのメタデータが読めればOk(JournalEntry)
+    pub fn capture(source: &Path, target: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(target)?;
+
+        #[cfg(unix)]
+        let (inode, mode) = (metadata.ino(), metadata.mode() & 0o7777);
+
+        #[cfg(not(unix))]
+        let (inode, mode) = (0, 0o644);
+
+        // 別のディレクトリから restore できるよう絶対パスで記録する
+        Ok(JournalEntry {
+            source: path::absolute(source)?,
+            target: path::absolute(target)?,
+            inode,
+            mode,
+            modified: to_timestamp(metadata.modified()?),
+        })
+    }
+}
+
+/// 実行中に追記するjournal
+///
+/// ファイルは最初の記録時に作成する (置換が無かった実行では作らない)。
+#[derive(Debug)]
+pub struct Journal {
+    path: PathBuf,
+    file: Option<File>,
+    entries: usize,
+}
+
+impl Journal {
+    /// 指定パスに書き込むjournal
+    pub fn new(path: PathBuf) -> Self {
+        Journal {
+            path,
+            file: None,
+            entries: 0,
+        }
+    }
+
+    /// 今回の実行用のjournal (`<dir>/run-<UNIX時刻>-<pid>.journal`)
+    ///
+    /// 同じプロセスで2回目以降に作るものは、同じ秒の実行と混ざらないよう
+    /// `run-<UNIX時刻>-<pid>-<番号>.journal` にする。
+    pub fn for_run(dir: &Path) -> Self {
+        static RUNS: AtomicUsize = AtomicUsize::new(0);
+        let (secs, _) = to_timestamp(SystemTime::now());
+        let name = match RUNS.fetch_add(1, Ordering::Relaxed) {
+            0 => format!("run-{}-{}.journal", secs, std::process::id()),
+            n => format!("run-{}-{}-{}.journal", secs, std::process::id(), n),
+        };
+        Self::new(dir.join(name))
+    }
+
+    /// journalファイルのパス
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// 記録した件数
+    pub fn len(&self) -> usize {
+        self.entries
+    }
+
+    /// まだ何も記録していないか
+    pub fn is_empty(&self) -> bool {
+        self.entries == 0
+    }
+
+    /// 1件追記する
+    ///
+    /// 途中でプロセスが止まっても記録済みの分は復元できるよう、1行ごとにflushする。
+    pub fn record(&mut self, entry: &JournalEntry) -> io::Result<()> {
+        if self.file.is_none() {
+            if let Some(parent) = self.path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            let mut file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)?;
+            if file.metadata()?.len() == 0 {
+                writeln!(file, "{}", JOURNAL_HEADER)?;
+            }
+            self.file = Some(file);
+        }
+        let Some(file) = self.file.as_mut() else {
+            return Ok(());
+        };
+
+        writeln!(
+            file,
+            "L\t{}\t{}\t{}\t{:o}\t{}\t{}",
+            escape(&entry.source.to_string_lossy()),
+            escape(&entry.target.to_string_lossy()),
+            entry.inode,
+            entry.mode,
+            entry.modified.0,
+            entry.modified.1
+        )?;
+        file.flush()?;
+        self.entries += 1;
+        Ok(())
+    }
+}
+
+/// journalディレクトリ `dir` 内の最新のjournalファイルを返す
+pub fn latest_journal(dir: &Path) -> Option<PathBuf> {
+    fs::read_dir(dir)
+        .ok()?
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "journal"))
+        .max_by_key(|p| fs::metadata(p).and_then(|m| m.modified()).ok())
+}
+
+/// journalファイルを読み込む
+///
+/// Args:
+///     path: journalファイルのパス
+///
+/// Returns:
+///     記録順のエントリ
+pub fn read_journal(path: &Path) -> io::Result<Vec<JournalEntry>> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, "invalid journal file");
+    let mut lines = BufReader::new(File::open(path)?).lines();
+    if lines.next().transpose()?.as_deref() != Some(JOURNAL_HEADER) {
+        return Err(invalid());
+    }
+
+    let lines = lines.collect::<io::Result<Vec<_>>>()?;
+    let mut entries = Vec::new();
+    for (i, line) in lines.iter().enumerate() {
+        let fields: Vec<&str> = line.split('\t').collect();
+        match fields.as_slice() {
+            ["L", source, target, inode, mode, secs, nanos] => entries.push(JournalEntry {
+                source: PathBuf::from(unescape(source)),
+                target: PathBuf::from(unescape(target)),
+                inode: inode.parse().map_err(|_| invalid())?,
+                mode: u32::from_str_radix(mode, 8).map_err(|_| invalid())?,
+                modified: (
+                    secs.parse().map_err(|_| invalid())?,
+                    nanos.parse().map_err(|_| invalid())?,
+                ),
+            }),
+            // 書き込み途中で止まった最終行は無視する
+            _ if i + 1 == lines.len() => {}
+            _ => return Err(invalid()),
+        }
+    }
+    Ok(entries)
+}
+
+/// journalの1件を元に戻す (targetを置換前のパーミッション・更新時刻の独立したファイルにする)
+///
+/// targetが既にsourceとリンクされていない場合 (復元済み、Gitが書き換えた等) は
+/// `UnshareResult::NotShared` を返し、何もしない。
+pub fn restore_entry(entry: &JournalEntry) -> UnshareResult {
+    if entry.source.exists() {
+        match is_same_inode(&entry.source, &entry.target) {
+            Ok(true) => {}
+            Ok(false) => return UnshareResult::NotShared,
+            Err(e) => return UnshareResult::Error(e.to_string()),
+        }
+    }
+    break_hardlink(
+        &entry.target,
+        Some(entry.mode),
+        Some(from_timestamp(entry.modified)),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardlink::replace_with_hardlink;
+    use std::collections::HashSet;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_journal_round_trip() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("src\tfile");
+        let target = temp_dir.path().join("target");
+        fs::write(&source, b"x").unwrap();
+        fs::write(&target, b"x").unwrap();
+
+        let mut journal = Journal::new(temp_dir.path().join("j/run.journal"));
+        assert!(journal.is_empty());
+        let entry = JournalEntry::capture(&source, &target).unwrap();
+        journal.record(&entry).unwrap();
+        journal.record(&entry).unwrap();
+        assert_eq!(journal.len(), 2);
+
+        let entries = read_journal(journal.path()).unwrap();
+        assert_eq!(entries, vec![entry.clone(), entry]);
+    }
+
+    #[test]
+    fn test_for_run_names_are_unique_in_process() {
+        let temp_dir = TempDir::new().unwrap();
+        let first = Journal::for_run(temp_dir.path());
+        let second = Journal::for_run(temp_dir.path());
+        assert_ne!(first.path(), second.path());
+        assert_eq!(first.path().parent(), Some(temp_dir.path()));
+    }
+
+    #[test]
+    fn test_read_journal_rejects_unknown_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("bogus.journal");
+        fs::write(&path, "something else\n").unwrap();
+        assert!(read_journal(&path).is_err());
+    }
+
+    #[test]
+    fn test_dedup_then_restore_restores_inode_set() {
+        let temp_dir = TempDir::new().unwrap();
+        let files: Vec<_> = (0..4)
+            .map(|i| temp_dir.path().join(format!("obj{}", i)))
+            .collect();
+        for file in &files {
+            fs::write(file, b"same content").unwrap();
+        }
+        let inodes = |files: &[PathBuf]| -> HashSet<u64> {
+            files
+                .iter()
+                .map(|f| fs::metadata(f).unwrap().ino())
+                .collect()
+        };
+        assert_eq!(inodes(&files).len(), 4);
+
+        let mut journal = Journal::new(temp_dir.path().join("run.journal"));
+        for target in &files[1..] {
+            let entry = JournalEntry::capture(&files[0], target).unwrap();
+            assert_eq!(
+                replace_with_hardlink(&files[0], target),
+                crate::hardlink::ReplaceResult::Replaced
+            );
+            journal.record(&entry).unwrap();
+        }
+        assert_eq!(inodes(&files).len(), 1);
+
+        let entries = read_journal(journal.path()).unwrap();
+        for entry in entries.iter().rev() {
+            assert_eq!(restore_entry(entry), UnshareResult::Unshared);
+            assert_eq!(
+                to_timestamp(fs::metadata(&entry.target).unwrap().modified().unwrap()),
+                entry.modified
+            );
+        }
+        assert_eq!(inodes(&files).len(), 4);
+        for file in &files {
+            assert_eq!(fs::read(file).unwrap(), b"same content");
+        }
+
+        // 2回目は何もしない
+        for entry in &entries {
+            assert_eq!(restore_entry(entry), UnshareResult::NotShared);
+        }
+    }
+}
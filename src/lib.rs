@@ -1,9 +1,22 @@
 //! Gitオブジェクトの重複ファイルをハードリンクで共有するライブラリ
 
+pub mod alternates;
 pub mod app;
+pub mod cache;
 pub mod cli;
+pub mod dirs;
 pub mod fsck;
 pub mod hardlink;
 pub mod i18n;
+pub mod journal;
 pub mod lock;
+pub mod object;
+pub mod owner;
+pub mod pack;
+pub mod pool;
+pub mod recover;
+pub mod report;
+pub mod repository;
+pub mod savings;
 pub mod scanner;
+pub mod signal;
@@ -1,16 +1,91 @@
 //! リポジトリロック処理（lock file + OS advisory lock）
+//!
+//! Git自身は `git-share-obj.lock` を見ないため、ロックの獲得後にGitのロック
+//! ファイルなどを調べ、Gitの処理が進行中のリポジトリも使用中として扱う。
+//!
+//! ロックファイルには保持者 (PID、ホスト名、開始時刻、コマンドライン) を書き、
+//! 解放時に空にする。保持されていないのに中身が残っているロックファイルは、
+//! 保持者が異常終了したもの (stale) とみなせる。
+//!
+//! 読むだけの処理 (`--dry-run`, `--fsck-only`) は共有ロックにし、リポジトリに
+//! ロックファイルを作らない。`--lock-dir` ではロックファイルをリポジトリの外に置く。
+//! どちらの場合も排他ロックの実行から外れないよう、ロックファイルとは別に
+//! ユーザー共通のディレクトリ ([`crate::dirs::StateDirs::user_locks`]) のロックも同じ種類で獲得する。
 
-use std::fs::{self, File, OpenOptions};
+use std::collections::HashMap;
+use std::env;
+use std::ffi::CStr;
 use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
 use std::os::fd::AsRawFd;
+use std::os::unix::fs::MetadataExt;
 use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::cache::root_key;
+use crate::repository::{resolve_repository, GitRepository};
+use crate::signal::interrupted;
+
+/// ロックファイル名 (実体のオブジェクトディレクトリに置く)
+pub const LOCK_FILE_NAME: &str = "git-share-obj.lock";
+
+/// ロックの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LockMode {
+    /// 変更する処理 (`LOCK_EX`)
+    #[default]
+    Exclusive,
+    /// 読むだけの処理 (`LOCK_SH`)。保持者は記録せず、リポジトリ内のロックファイルは作らない
+    Shared,
+}
+
+/// リポジトリロックの獲得方法
+#[derive(Debug, Clone, Default)]
+pub struct LockOptions {
+    pub mode: LockMode,
+    /// ロックファイルを置くディレクトリ (`--lock-dir`)。Noneならリポジトリのobjectsディレクトリ
+    pub lock_dir: Option<PathBuf>,
+    /// ユーザー共通のロックを置くディレクトリ。Noneならユーザー共通のロックは獲得しない
+    pub user_lock_dir: Option<PathBuf>,
+}
+
+/// `gc.pid` を別ホストのものとみなして無視するまでの時間 (Gitと同じ12時間)
+const GC_PID_EXPIRY: Duration = Duration::from_secs(12 * 60 * 60);
+/// 別ホストの受信中の `.keep` を、受信が中断されて残ったものとみなすまでの時間
+///
+/// 別ホストのPIDは確かめられないため、`gc.pid` と同じ長さだけ待つ。
+const INCOMING_KEEP_EXPIRY: Duration = GC_PID_EXPIRY;
+
+/// 使用中のロックを再試行するまでの最初の間隔
+const INITIAL_RETRY_INTERVAL: Duration = Duration::from_millis(50);
+/// 再試行の間隔の上限
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(1);
 
 /// リポジトリロック獲得結果
 #[derive(Debug)]
 pub enum LockError {
     LockPathCreateFailed(String),
     LockFileOpenFailed(String),
-    LockBusy(String),
+    LockBusy(BusyReason),
+}
+
+/// リポジトリが使用中である理由
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BusyReason {
+    /// 他のgit-share-objがロック中 (ロックファイルのパス、読み取れた保持者)
+    LockFile(PathBuf, Option<LockHolder>),
+    /// `git gc` が実行中 (`gc.pid` のPID)
+    GitGc(u32),
+    /// インデックスを更新中 (`index.lock`)
+    IndexLock(PathBuf),
+    /// refsを更新中 (`packed-refs.lock`)
+    PackedRefsLock(PathBuf),
+    /// shallowを更新中 (`shallow.lock`)
+    ShallowLock(PathBuf),
+    /// fetch/pushでpackを受信中 (`objects/pack/*.keep`)
+    IncomingPack(PathBuf),
 }
 
 impl fmt::Display for LockError {
@@ -18,7 +93,108 @@ impl fmt::Display for LockError {
         match self {
             LockError::LockPathCreateFailed(e) => write!(f, "lock path create failed: {}", e),
             LockError::LockFileOpenFailed(e) => write!(f, "lock file open failed: {}", e),
-            LockError::LockBusy(path) => write!(f, "lock busy: {}", path),
+            LockError::LockBusy(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+impl fmt::Display for BusyReason {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BusyReason::LockFile(path, None) => write!(f, "lock busy: {}", path.display()),
+            BusyReason::LockFile(path, Some(holder)) => {
+                write!(f, "lock busy: {} (held by {})", path.display(), holder)
+            }
+            BusyReason::GitGc(pid) => write!(f, "git gc in progress (pid {})", pid),
+            BusyReason::IndexLock(path) => write!(f, "index.lock exists: {}", path.display()),
+            BusyReason::PackedRefsLock(path) => {
+                write!(f, "packed-refs.lock exists: {}", path.display())
+            }
+            BusyReason::ShallowLock(path) => write!(f, "shallow.lock exists: {}", path.display()),
+            BusyReason::IncomingPack(path) => write!(f, "pack being received: {}", path.display()),
+        }
+    }
+}
+
+/// ロックファイルに記録する保持者
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockHolder {
+    pub pid: u32,
+    pub host: String,
+    /// ロックを獲得した時刻 (UNIX秒)
+    pub started: u64,
+    /// 保持者のコマンドライン
+    pub command: String,
+}
+
+impl LockHolder {
+    /// このプロセスを保持者とする
+    fn current() -> Self {
+        LockHolder {
+            pid: std::process::id(),
+            host: local_hostname().unwrap_or_default(),
+            started: SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs()),
+            command: env::args().collect::<Vec<_>>().join(" "),
+        }
+    }
+
+    /// ロックファイルから保持者を読み取る
+    ///
+    /// Returns:
+    ///     空 (解放済み) や形式が違う場合はNone
+    pub fn read(path: &Path) -> Option<Self> {
+        let content = fs::read_to_string(path).ok()?;
+        let fields: HashMap<&str, &str> = content
+            .lines()
+            .filter_map(|line| line.split_once('='))
+            .collect();
+        let field = |key: &str| fields.get(key).map(|v| v.to_string());
+        Some(LockHolder {
+            pid: field("pid")?.parse().ok()?,
+            host: field("host")?,
+            started: field("started")?.parse().ok()?,
+            command: field("command").unwrap_or_default(),
+        })
+    }
+
+    /// ロックファイルに書く内容 (`key=value` の行)
+    fn to_file_content(&self) -> String {
+        format!(
+            "pid={}\nhost={}\nstarted={}\ncommand={}\n",
+            self.pid, self.host, self.started, self.command
+        )
+    }
+}
+
+impl fmt::Display for LockHolder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "pid {} on {}, started at {}: {}",
+            self.pid, self.host, self.started, self.command
+        )
+    }
+}
+
+/// ロックファイルの状態 (`locks` サブコマンド)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum LockState {
+    /// 保持されている (読み取れた保持者)
+    Held(Option<LockHolder>),
+    /// 保持されていないが保持者が残っている (保持者が異常終了した)
+    Stale(LockHolder),
+    /// 保持されておらず空 (正常に解放済み)
+    Free,
+}
+
+impl LockState {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            LockState::Held(_) => "held",
+            LockState::Stale(_) => "stale",
+            LockState::Free => "free",
         }
     }
 }
@@ -28,45 +204,444 @@ impl fmt::Display for LockError {
 pub struct RepoLock {
     pub repo: PathBuf,
     pub lock_path: PathBuf,
-    file: File,
+    pub mode: LockMode,
+    /// ロックしているファイル (共有ロックでリポジトリにロックファイルが無い場合はNone)
+    file: Option<File>,
+    /// 同時に保持する補助のロック (ユーザー共通のロック、`--lock-dir` でのリポジトリ内のロック)
+    ///
+    /// 保持者は書かず、dropで閉じると解放される。
+    companions: Vec<File>,
 }
 
 impl Drop for RepoLock {
     fn drop(&mut self) {
-        let _ = unsafe { libc::flock(self.file.as_raw_fd(), libc::LOCK_UN) };
+        let Some(file) = &self.file else {
+            return;
+        };
+        // 正常に解放したことが分かるよう、保持者の記録を消してから解放する
+        if self.mode == LockMode::Exclusive {
+            let _ = file.set_len(0);
+        }
+        let _ = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
     }
 }
 
 /// ロックファイルパスを返す
-pub fn lock_file_path(repo: &Path) -> PathBuf {
-    repo.join(".git").join("objects").join("git-share-obj.lock")
+///
+/// ロックは実体のオブジェクトディレクトリに置くため、オブジェクトを共有する
+/// worktreeやsubmoduleの作業ツリーからも同じロックファイルになる。
+/// `lock_dir` を指定した場合は、そのディレクトリの `repo-<device>-<inode>.lock`
+/// (objectsディレクトリのデバイス番号とinode番号) にする。
+pub fn lock_file_path(repo: &Path, lock_dir: Option<&Path>) -> PathBuf {
+    let objects_dir = repo_objects_dir(repo);
+    lock_dir
+        .and_then(|lock_dir| keyed_lock_path(lock_dir, &objects_dir))
+        .unwrap_or_else(|| objects_dir.join(LOCK_FILE_NAME))
+}
+
+/// ユーザー共通のロックファイルのパス (`<user_lock_dir>/repo-<device>-<inode>.lock`)
+fn user_lock_path(repo: &Path, user_lock_dir: Option<&Path>) -> Option<PathBuf> {
+    keyed_lock_path(user_lock_dir?, &repo_objects_dir(repo))
+}
+
+/// リポジトリの実体のobjectsディレクトリ
+fn repo_objects_dir(repo: &Path) -> PathBuf {
+    resolve_repository(repo)
+        .map(|r| r.objects_dir)
+        .unwrap_or_else(|| repo.join(".git").join("objects"))
+}
+
+/// objectsディレクトリのデバイス番号とinode番号で名付けたロックファイルのパス
+fn keyed_lock_path(dir: &Path, objects_dir: &Path) -> Option<PathBuf> {
+    let metadata = fs::metadata(objects_dir).ok()?;
+    Some(dir.join(format!("repo-{}-{}.lock", metadata.dev(), metadata.ino())))
 }
 
 /// 単一リポジトリのロックを試行
-pub fn try_lock_repo(repo: &Path) -> Result<RepoLock, LockError> {
-    let lock_path = lock_file_path(repo);
-    if let Some(parent) = lock_path.parent() {
-        fs::create_dir_all(parent)
-            .map_err(|e| LockError::LockPathCreateFailed(e.to_string()))?;
+///
+/// ロックファイルに加えて、`user_lock_dir` があればユーザー共通のロック ([`user_lock_path`]) を
+/// 同じ種類で獲得する。
+/// 共有ロックでリポジトリ内のロックファイルがまだ無い場合は作らずに、ユーザー共通の
+/// ロックだけで後から始まる排他ロックの実行を待たせる。`--lock-dir` では、
+/// リポジトリ内のロックファイルがあればそれもロックし、`--lock-dir` 無しの実行と排他する。
+pub fn try_lock_repo(repo: &Path, options: &LockOptions) -> Result<RepoLock, LockError> {
+    let lock_path = lock_file_path(repo, options.lock_dir.as_deref());
+    let create = options.mode == LockMode::Exclusive || options.lock_dir.is_some();
+    let mut lock = lock_file(repo, lock_path, options.mode, create)?;
+    let user_lock = match user_lock_path(repo, options.user_lock_dir.as_deref()) {
+        // ユーザー共通のロックには保持者が無いため、ロックファイルの保持者を示す
+        Some(path) => lock_companion(repo, path, options.mode, true).map_err(|e| match e {
+            LockError::LockBusy(BusyReason::LockFile(..)) => LockError::LockBusy(
+                BusyReason::LockFile(lock.lock_path.clone(), LockHolder::read(&lock.lock_path)),
+            ),
+            e => e,
+        })?,
+        None => None,
+    };
+    if lock.file.is_none() && user_lock.is_none() {
+        return Err(LockError::LockPathCreateFailed(format!(
+            "no lock file for {}",
+            repo.display()
+        )));
+    }
+    lock.companions.extend(user_lock);
+    if options.lock_dir.is_some() {
+        let in_repo = lock_file_path(repo, None);
+        if in_repo != lock.lock_path {
+            lock.companions
+                .extend(lock_companion(repo, in_repo, options.mode, false)?);
+        }
+    }
+    // Gitの処理が進行中ならロックを解放して使用中とする
+    if let Some(reason) = resolve_repository(repo).and_then(|r| detect_git_activity(&r)) {
+        return Err(LockError::LockBusy(reason));
     }
+    if options.mode == LockMode::Exclusive {
+        // 記録できなくてもロック自体は有効
+        let _ = lock.file.as_mut().map(write_holder);
+    }
+    Ok(lock)
+}
+
+/// 補助のロックファイルをロックし、ロックしたファイルを返す (保持者は書かない)
+///
+/// Returns:
+///     ロックしたファイル。`create` がfalseでファイルが無ければNone
+fn lock_companion(
+    owner: &Path,
+    lock_path: PathBuf,
+    mode: LockMode,
+    create: bool,
+) -> Result<Option<File>, LockError> {
+    let mut lock = lock_file(owner, lock_path, mode, create)?;
+    Ok(lock.file.take())
+}
 
-    let file = OpenOptions::new()
+/// ロックファイルをロックする (使用中なら待たない)
+///
+/// Args:
+///     owner: ロックの対象 (リポジトリまたは探索ルート)
+///     lock_path: ロックファイルのパス
+///     mode: 排他ロックなら `LOCK_EX`、共有ロックなら `LOCK_SH`
+///     create: ロックファイル (と親ディレクトリ) が無ければ作成する (読み書きで開く)。
+///         falseなら読み取り専用で開き、無ければロック無しで返す
+fn lock_file(
+    owner: &Path,
+    lock_path: PathBuf,
+    mode: LockMode,
+    create: bool,
+) -> Result<RepoLock, LockError> {
+    if create {
+        if let Some(parent) = lock_path.parent() {
+            fs::create_dir_all(parent)
+                .map_err(|e| LockError::LockPathCreateFailed(e.to_string()))?;
+        }
+    }
+
+    let exclusive = mode == LockMode::Exclusive;
+    let file = match OpenOptions::new()
         .read(true)
-        .write(true)
-        .create(true)
+        .write(create)
+        .create(create)
         .truncate(false)
         .open(&lock_path)
-        .map_err(|e| LockError::LockFileOpenFailed(e.to_string()))?;
+    {
+        Ok(file) => file,
+        Err(e) if !create && e.kind() == io::ErrorKind::NotFound => {
+            return Ok(RepoLock {
+                repo: owner.to_path_buf(),
+                lock_path,
+                mode,
+                file: None,
+                companions: Vec::new(),
+            });
+        }
+        Err(e) => return Err(LockError::LockFileOpenFailed(e.to_string())),
+    };
 
-    let rc = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    let operation = if exclusive {
+        libc::LOCK_EX
+    } else {
+        libc::LOCK_SH
+    };
+    let rc = unsafe { libc::flock(file.as_raw_fd(), operation | libc::LOCK_NB) };
     if rc != 0 {
-        return Err(LockError::LockBusy(format!("{}", lock_path.display())));
+        let holder = LockHolder::read(&lock_path);
+        return Err(LockError::LockBusy(BusyReason::LockFile(lock_path, holder)));
+    }
+    // 開いてからロックするまでに `locks --clean` が削除したファイルなら、使用中として開き直させる
+    if !is_same_file(&file, &lock_path) {
+        return Err(LockError::LockBusy(BusyReason::LockFile(lock_path, None)));
     }
 
     Ok(RepoLock {
-        repo: repo.to_path_buf(),
+        repo: owner.to_path_buf(),
         lock_path,
-        file,
+        mode,
+        file: Some(file),
+        companions: Vec::new(),
+    })
+}
+
+/// ロックファイルに保持者を書く
+fn write_holder(file: &mut File) -> io::Result<()> {
+    file.set_len(0)?;
+    (&*file).write_all(LockHolder::current().to_file_content().as_bytes())
+}
+
+/// 開いているファイルがパスの指すファイルと同じか
+fn is_same_file(file: &File, path: &Path) -> bool {
+    match (file.metadata(), fs::metadata(path)) {
+        (Ok(a), Ok(b)) => (a.dev(), a.ino()) == (b.dev(), b.ino()),
+        _ => false,
+    }
+}
+
+/// ロックファイルの状態を調べる (ロックは獲得しない)
+pub fn inspect_lock(lock_path: &Path) -> io::Result<LockState> {
+    let file = File::open(lock_path)?;
+    let rc = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    let holder = LockHolder::read(lock_path);
+    if rc != 0 {
+        return Ok(LockState::Held(holder));
+    }
+    let _ = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_UN) };
+    Ok(match holder {
+        Some(holder) => LockState::Stale(holder),
+        None => LockState::Free,
+    })
+}
+
+/// 保持されていないロックファイルを削除する
+///
+/// 削除の間はロックを保持し、同時にロックしようとしたプロセスは
+/// [`try_lock_repo`] のinode確認で開き直す。
+pub fn remove_lock_file(lock_path: &Path) -> Result<(), LockError> {
+    let file = File::open(lock_path).map_err(|e| LockError::LockFileOpenFailed(e.to_string()))?;
+    let rc = unsafe { libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB) };
+    if rc != 0 {
+        let holder = LockHolder::read(lock_path);
+        return Err(LockError::LockBusy(BusyReason::LockFile(
+            lock_path.to_path_buf(),
+            holder,
+        )));
+    }
+    fs::remove_file(lock_path).map_err(|e| LockError::LockFileOpenFailed(e.to_string()))
+}
+
+/// Git自身の処理が進行中かを調べる
+///
+/// `gc.pid` (PIDが生きている場合)、`index.lock`、`packed-refs.lock`、`shallow.lock`、
+/// fetch/receive-packが受信中に作る `objects/pack/*.keep` を見る。
+/// 利用者が置いた (内容がfetch-pack/receive-packでない) `.keep` は対象外。
+///
+/// Returns:
+///     進行中の処理があればSome(理由)
+pub fn detect_git_activity(repo: &GitRepository) -> Option<BusyReason> {
+    let common_dir = repo.objects_dir.parent().unwrap_or(&repo.git_dir);
+
+    if let Some(pid) = running_gc_pid(&common_dir.join("gc.pid")) {
+        return Some(BusyReason::GitGc(pid));
+    }
+    let index_lock = repo.git_dir.join("index.lock");
+    if index_lock.exists() {
+        return Some(BusyReason::IndexLock(index_lock));
+    }
+    let packed_refs_lock = common_dir.join("packed-refs.lock");
+    if packed_refs_lock.exists() {
+        return Some(BusyReason::PackedRefsLock(packed_refs_lock));
+    }
+    let shallow_lock = common_dir.join("shallow.lock");
+    if shallow_lock.exists() {
+        return Some(BusyReason::ShallowLock(shallow_lock));
+    }
+
+    let entries = fs::read_dir(repo.objects_dir.join("pack")).ok()?;
+    entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "keep"))
+        .find(|p| is_incoming_keep(p))
+        .map(BusyReason::IncomingPack)
+}
+
+/// `gc.pid` (`<pid> <hostname>`) の `git gc` が実行中ならそのPID
+///
+/// 別ホストのものはPIDを確かめられないため、Gitと同じく12時間以内なら実行中とみなす。
+fn running_gc_pid(path: &Path) -> Option<u32> {
+    let content = fs::read_to_string(path).ok()?;
+    let (pid, host) = content.trim().split_once(' ')?;
+    let pid: u32 = pid.parse().ok()?;
+    let running = if local_hostname().is_some_and(|h| h == host) {
+        is_process_alive(pid)
+    } else {
+        is_modified_within(path, GC_PID_EXPIRY)
+    };
+    running.then_some(pid)
+}
+
+/// ファイルの更新時刻が `expiry` 以内か
+fn is_modified_within(path: &Path, expiry: Duration) -> bool {
+    fs::metadata(path)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| SystemTime::now().duration_since(t).ok())
+        .is_some_and(|age| age < expiry)
+}
+
+/// fetch-pack/receive-packが受信中に作った `.keep` か
+///
+/// 内容は `fetch-pack <pid> on <hostname>` (receive-packも同様)。
+/// このホストのものはPIDが生きている場合だけ、別ホストのものは12時間以内に
+/// 更新されたものだけ受信中とみなす。
+fn is_incoming_keep(path: &Path) -> bool {
+    let Ok(content) = fs::read_to_string(path) else {
+        return false;
+    };
+    let mut words = content.split_whitespace();
+    let (Some(command), Some(pid), Some("on"), Some(host)) =
+        (words.next(), words.next(), words.next(), words.next())
+    else {
+        return false;
+    };
+    if command != "fetch-pack" && command != "receive-pack" {
+        return false;
+    }
+    match pid.parse() {
+        Ok(pid) if local_hostname().is_some_and(|h| h == host) => is_process_alive(pid),
+        Ok(_) => is_modified_within(path, INCOMING_KEEP_EXPIRY),
+        Err(_) => false,
+    }
+}
+
+/// このホストのホスト名
+fn local_hostname() -> Option<String> {
+    let mut buf = [0 as libc::c_char; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr(), buf.len()) };
+    if rc != 0 {
+        return None;
+    }
+    buf[buf.len() - 1] = 0;
+    let name = unsafe { CStr::from_ptr(buf.as_ptr()) };
+    Some(name.to_string_lossy().into_owned())
+}
+
+/// プロセスが存在するか (権限が無くても存在すればtrue)
+fn is_process_alive(pid: u32) -> bool {
+    let Ok(pid) = libc::pid_t::try_from(pid) else {
+        return false;
+    };
+    if pid <= 0 {
+        return false;
+    }
+    let rc = unsafe { libc::kill(pid, 0) };
+    rc == 0 || std::io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+/// 使用中の間、最大 `timeout` まで `attempt` を再試行する
+///
+/// 50msから倍々に (最大1秒) 間隔を空けて再試行する。`timeout` が0なら1回だけ試行し、
+/// シグナルで中断された場合は待つのをやめる。
+///
+/// Returns:
+///     成功した結果、または最後の試行のエラー
+fn retry_while_busy<T, F>(timeout: Duration, mut attempt: F) -> Result<T, LockError>
+where
+    F: FnMut() -> Result<T, LockError>,
+{
+    let deadline = Instant::now() + timeout;
+    let mut interval = INITIAL_RETRY_INTERVAL;
+    loop {
+        match attempt() {
+            Err(LockError::LockBusy(reason)) => {
+                let now = Instant::now();
+                if now >= deadline || interrupted() {
+                    return Err(LockError::LockBusy(reason));
+                }
+                thread::sleep(interval.min(deadline - now));
+                interval = (interval * 2).min(MAX_RETRY_INTERVAL);
+            }
+            result => return result,
+        }
+    }
+}
+
+/// 単一リポジトリのロックを、使用中なら最大 `timeout` まで待って獲得する
+///
+/// Gitの処理が進行中の場合 ([`detect_git_activity`]) も使用中として待つ。
+pub fn lock_repo_with_timeout(
+    repo: &Path,
+    options: &LockOptions,
+    timeout: Duration,
+) -> Result<RepoLock, LockError> {
+    retry_while_busy(timeout, || try_lock_repo(repo, options))
+}
+
+/// 複数のリポジトリを全てロックする (`--all-or-nothing`)
+///
+/// 1つでも使用中なら獲得済みのロックを全て解放してから待ち、最初からやり直す。
+/// 一部だけを保持したまま待たないため、他の実行がロックを獲得する妨げにならない。
+///
+/// Args:
+///     repos: ロックする順に並べたリポジトリ
+///     options: ロックの種類と置き場所
+///     timeout: 全体で待つ最大時間
+///
+/// Returns:
+///     全てのロック、または最後の試行で獲得できなかったリポジトリとエラー
+pub fn lock_all_or_nothing(
+    repos: &[&Path],
+    options: &LockOptions,
+    timeout: Duration,
+) -> Result<Vec<RepoLock>, (PathBuf, LockError)> {
+    let mut failed = PathBuf::new();
+    retry_while_busy(timeout, || {
+        let mut locks = Vec::with_capacity(repos.len());
+        for repo in repos {
+            match try_lock_repo(repo, options) {
+                Ok(lock) => locks.push(lock),
+                Err(e) => {
+                    failed = repo.to_path_buf();
+                    return Err(e);
+                }
+            }
+        }
+        Ok(locks)
+    })
+    .map_err(|e| (failed, e))
+}
+
+/// 探索ルートのロックファイルのパス (`<cache_dir>/locks/tree-<正規化したパスのSHA-1>.lock`)
+pub fn tree_lock_path(cache_dir: &Path, root: &Path) -> Option<PathBuf> {
+    Some(
+        cache_dir
+            .join("locks")
+            .join(format!("tree-{}.lock", root_key(root)?)),
+    )
+}
+
+/// 探索ルート単位のロックを、使用中なら最大 `timeout` まで待って獲得する (`--tree-lock`)
+///
+/// 同じルートに対する実行 (cronなど) を、リポジトリのロックより前で直列化する。
+/// 共有ロックどうし (読むだけの実行) は同時に獲得できる。
+pub fn lock_tree_with_timeout(
+    cache_dir: Option<&Path>,
+    root: &Path,
+    mode: LockMode,
+    timeout: Duration,
+) -> Result<RepoLock, LockError> {
+    let Some(lock_path) = cache_dir.and_then(|dir| tree_lock_path(dir, root)) else {
+        return Err(LockError::LockPathCreateFailed(format!(
+            "cannot resolve tree lock for {}",
+            root.display()
+        )));
+    };
+    retry_while_busy(timeout, || {
+        let mut lock = lock_file(root, lock_path.clone(), mode, true)?;
+        if mode == LockMode::Exclusive {
+            let _ = lock.file.as_mut().map(write_holder);
+        }
+        Ok(lock)
     })
 }
 
@@ -86,6 +661,15 @@ mod tests {
         assert!(status.success());
     }
 
+    /// ユーザー共通のロックを `dir/user-locks` に置くロックの獲得方法
+    fn with_user_locks(dir: &Path, mode: LockMode) -> LockOptions {
+        LockOptions {
+            mode,
+            lock_dir: None,
+            user_lock_dir: Some(dir.join("user-locks")),
+        }
+    }
+
     #[test]
     fn test_try_lock_repo_success() {
         let temp_dir = TempDir::new().unwrap();
@@ -93,7 +677,7 @@ mod tests {
         std::fs::create_dir_all(&repo).unwrap();
         init_repo(&repo);
 
-        let lock = try_lock_repo(&repo);
+        let lock = try_lock_repo(&repo, &LockOptions::default());
         assert!(lock.is_ok());
     }
 
@@ -104,11 +688,165 @@ mod tests {
         std::fs::create_dir_all(&repo).unwrap();
         init_repo(&repo);
 
-        let _lock1 = try_lock_repo(&repo).unwrap();
-        let lock2 = try_lock_repo(&repo);
+        let _lock1 = try_lock_repo(&repo, &LockOptions::default()).unwrap();
+        let lock2 = try_lock_repo(&repo, &LockOptions::default());
         assert!(matches!(lock2, Err(LockError::LockBusy(_))));
     }
 
+    #[test]
+    fn test_try_lock_repo_bare_repository() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("foo.git");
+        let status = Command::new("git")
+            .arg("init")
+            .arg("-q")
+            .arg("--bare")
+            .arg(&repo)
+            .status()
+            .unwrap();
+        assert!(status.success());
+
+        let lock = try_lock_repo(&repo, &LockOptions::default()).unwrap();
+        assert_eq!(lock.lock_path, repo.join("objects").join(LOCK_FILE_NAME));
+        assert!(!repo.join(".git").exists());
+    }
+
+    #[test]
+    fn test_lock_repo_with_timeout_waits_for_release() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        init_repo(&repo);
+
+        let lock1 = try_lock_repo(&repo, &LockOptions::default()).unwrap();
+        let busy =
+            lock_repo_with_timeout(&repo, &LockOptions::default(), Duration::from_millis(100));
+        assert!(matches!(busy, Err(LockError::LockBusy(_))));
+
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(lock1);
+        });
+        let started = Instant::now();
+        let lock2 = lock_repo_with_timeout(&repo, &LockOptions::default(), Duration::from_secs(10));
+        assert!(lock2.is_ok());
+        assert!(started.elapsed() >= Duration::from_millis(150));
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_lock_file_records_holder_and_state() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        init_repo(&repo);
+
+        let lock = try_lock_repo(&repo, &LockOptions::default()).unwrap();
+        let lock_path = lock.lock_path.clone();
+        let holder = LockHolder::read(&lock_path).unwrap();
+        assert_eq!(holder.pid, std::process::id());
+        assert!(matches!(
+            inspect_lock(&lock_path).unwrap(),
+            LockState::Held(Some(_))
+        ));
+        match try_lock_repo(&repo, &LockOptions::default()) {
+            Err(LockError::LockBusy(BusyReason::LockFile(_, Some(busy)))) => {
+                assert_eq!(busy, holder)
+            }
+            other => panic!("unexpected: {:?}", other),
+        }
+        assert!(remove_lock_file(&lock_path).is_err());
+
+        drop(lock);
+        assert_eq!(inspect_lock(&lock_path).unwrap(), LockState::Free);
+
+        // 異常終了した保持者の記録が残っている
+        std::fs::write(&lock_path, holder.to_file_content()).unwrap();
+        assert_eq!(inspect_lock(&lock_path).unwrap(), LockState::Stale(holder));
+        remove_lock_file(&lock_path).unwrap();
+        assert!(!lock_path.exists());
+    }
+
+    #[test]
+    fn test_git_lock_files_make_repo_busy() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        init_repo(&repo);
+        let git_dir = repo.join(".git");
+
+        for name in ["index.lock", "packed-refs.lock", "shallow.lock"] {
+            std::fs::write(git_dir.join(name), b"").unwrap();
+            let err = try_lock_repo(&repo, &LockOptions::default()).unwrap_err();
+            assert!(
+                matches!(err, LockError::LockBusy(ref r) if r.to_string().contains(name)),
+                "{}",
+                err
+            );
+            std::fs::remove_file(git_dir.join(name)).unwrap();
+        }
+        // Gitの処理で使用中と判断した場合、ロックファイルのロックは残さない
+        assert!(try_lock_repo(&repo, &LockOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_gc_pid_and_incoming_keep() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        init_repo(&repo);
+        let git_dir = repo.join(".git");
+        let host = local_hostname().unwrap();
+        let pid = std::process::id();
+
+        std::fs::write(git_dir.join("gc.pid"), format!("{} {}", pid, host)).unwrap();
+        assert!(matches!(
+            try_lock_repo(&repo, &LockOptions::default()),
+            Err(LockError::LockBusy(BusyReason::GitGc(p))) if p == pid
+        ));
+        // 終了したgcのgc.pidは無視する
+        std::fs::write(git_dir.join("gc.pid"), format!("{} {}", i32::MAX, host)).unwrap();
+        assert!(try_lock_repo(&repo, &LockOptions::default()).is_ok());
+
+        let pack_dir = git_dir.join("objects/pack");
+        std::fs::create_dir_all(&pack_dir).unwrap();
+        // 利用者が置いた.keepは対象外
+        std::fs::write(pack_dir.join("pack-1.keep"), b"").unwrap();
+        assert!(try_lock_repo(&repo, &LockOptions::default()).is_ok());
+        std::fs::write(
+            pack_dir.join("pack-2.keep"),
+            format!("fetch-pack {} on {}\n", pid, host),
+        )
+        .unwrap();
+        assert!(matches!(
+            try_lock_repo(&repo, &LockOptions::default()),
+            Err(LockError::LockBusy(BusyReason::IncomingPack(_)))
+        ));
+        // 受信を終えた.keepは無視する
+        std::fs::write(
+            pack_dir.join("pack-2.keep"),
+            format!("fetch-pack {} on {}\n", i32::MAX, host),
+        )
+        .unwrap();
+        assert!(try_lock_repo(&repo, &LockOptions::default()).is_ok());
+
+        // 別ホストのものは12時間以内なら受信中とみなし、それより古ければ無視する
+        let other_host = pack_dir.join("pack-3.keep");
+        std::fs::write(&other_host, "receive-pack 1 on other-host.invalid\n").unwrap();
+        assert!(matches!(
+            try_lock_repo(&repo, &LockOptions::default()),
+            Err(LockError::LockBusy(BusyReason::IncomingPack(p))) if p == other_host
+        ));
+        let old = SystemTime::now() - INCOMING_KEEP_EXPIRY - Duration::from_secs(60);
+        std::fs::File::options()
+            .write(true)
+            .open(&other_host)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        assert!(try_lock_repo(&repo, &LockOptions::default()).is_ok());
+    }
+
     #[test]
     fn test_try_lock_repo_can_reacquire_after_drop() {
         let temp_dir = TempDir::new().unwrap();
@@ -117,10 +855,193 @@ mod tests {
         init_repo(&repo);
 
         {
-            let _lock = try_lock_repo(&repo).unwrap();
+            let _lock = try_lock_repo(&repo, &LockOptions::default()).unwrap();
         }
 
-        let lock2 = try_lock_repo(&repo);
+        let lock2 = try_lock_repo(&repo, &LockOptions::default());
         assert!(lock2.is_ok());
     }
+
+    #[test]
+    fn test_shared_lock_does_not_create_lock_file_in_repo() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        init_repo(&repo);
+        let shared = with_user_locks(temp_dir.path(), LockMode::Shared);
+        let exclusive = with_user_locks(temp_dir.path(), LockMode::Exclusive);
+
+        let lock = try_lock_repo(&repo, &shared).unwrap();
+        assert!(!lock.lock_path.exists());
+        drop(lock);
+
+        // ファイルがあれば共有ロックどうしは両立し、排他ロックとは両立しない
+        drop(try_lock_repo(&repo, &exclusive).unwrap());
+        let reader1 = try_lock_repo(&repo, &shared).unwrap();
+        let _reader2 = try_lock_repo(&repo, &shared).unwrap();
+        assert!(matches!(
+            try_lock_repo(&repo, &exclusive),
+            Err(LockError::LockBusy(_))
+        ));
+        drop(reader1);
+        let writer = try_lock_repo(&repo, &exclusive);
+        assert!(matches!(writer, Err(LockError::LockBusy(_))));
+    }
+
+    #[test]
+    fn test_shared_and_exclusive_contend_without_lock_file() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        init_repo(&repo);
+        let shared = with_user_locks(temp_dir.path(), LockMode::Shared);
+        let exclusive = with_user_locks(temp_dir.path(), LockMode::Exclusive);
+        let in_repo = lock_file_path(&repo, None);
+
+        // リポジトリ内のロックファイルが無くても、共有ロックの間は排他ロックを獲得できない
+        let reader = try_lock_repo(&repo, &shared).unwrap();
+        assert!(!in_repo.exists());
+        let user_lock = user_lock_path(&repo, shared.user_lock_dir.as_deref()).unwrap();
+        assert!(user_lock.starts_with(temp_dir.path().join("user-locks")));
+        assert!(user_lock.exists());
+        assert!(matches!(
+            try_lock_repo(&repo, &exclusive),
+            Err(LockError::LockBusy(_))
+        ));
+        // 共有ロックどうしは両立する
+        drop(try_lock_repo(&repo, &shared).unwrap());
+        drop(reader);
+
+        // 排他ロックの間は、ファイルを削除されても共有ロックを獲得できない
+        let writer = try_lock_repo(&repo, &exclusive).unwrap();
+        std::fs::remove_file(&in_repo).unwrap();
+        assert!(matches!(
+            try_lock_repo(&repo, &shared),
+            Err(LockError::LockBusy(_))
+        ));
+        drop(writer);
+        assert!(try_lock_repo(&repo, &shared).is_ok());
+    }
+
+    #[test]
+    fn test_lock_dir_and_in_repo_lock_exclude_each_other() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        init_repo(&repo);
+        let lock_dir = LockOptions {
+            mode: LockMode::Exclusive,
+            lock_dir: Some(temp_dir.path().join("locks")),
+            user_lock_dir: None,
+        };
+
+        let plain = try_lock_repo(&repo, &LockOptions::default()).unwrap();
+        assert!(matches!(
+            try_lock_repo(&repo, &lock_dir),
+            Err(LockError::LockBusy(_))
+        ));
+        drop(plain);
+        let with_lock_dir = try_lock_repo(&repo, &lock_dir).unwrap();
+        assert!(matches!(
+            try_lock_repo(&repo, &LockOptions::default()),
+            Err(LockError::LockBusy(_))
+        ));
+        drop(with_lock_dir);
+
+        // ユーザー共通のロックが別の場所でも、リポジトリ内のロックファイルで排他する
+        let plain = try_lock_repo(&repo, &LockOptions::default()).unwrap();
+        let in_repo = lock_companion(
+            &repo,
+            lock_file_path(&repo, None),
+            LockMode::Exclusive,
+            false,
+        );
+        assert!(matches!(in_repo, Err(LockError::LockBusy(_))));
+        drop(plain);
+        assert!(lock_companion(
+            &repo,
+            lock_file_path(&repo, None),
+            LockMode::Exclusive,
+            false
+        )
+        .unwrap()
+        .is_some());
+    }
+
+    #[test]
+    fn test_lock_dir_keeps_repo_untouched() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        std::fs::create_dir_all(&repo).unwrap();
+        init_repo(&repo);
+        let options = LockOptions {
+            mode: LockMode::Exclusive,
+            lock_dir: Some(temp_dir.path().join("locks")),
+            user_lock_dir: None,
+        };
+
+        let objects = std::fs::metadata(repo.join(".git/objects")).unwrap();
+        let expected = temp_dir.path().join(format!(
+            "locks/repo-{}-{}.lock",
+            objects.dev(),
+            objects.ino()
+        ));
+        let lock = try_lock_repo(&repo, &options).unwrap();
+        assert_eq!(lock.lock_path, expected);
+        assert!(LockHolder::read(&expected).is_some());
+        assert!(!repo.join(".git/objects").join(LOCK_FILE_NAME).exists());
+        assert!(matches!(
+            try_lock_repo(&repo, &options),
+            Err(LockError::LockBusy(_))
+        ));
+    }
+
+    #[test]
+    fn test_lock_all_or_nothing_releases_partial_locks() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo_a = temp_dir.path().join("a");
+        let repo_b = temp_dir.path().join("b");
+        for repo in [&repo_a, &repo_b] {
+            std::fs::create_dir_all(repo).unwrap();
+            init_repo(repo);
+        }
+        let repos = [repo_a.as_path(), repo_b.as_path()];
+
+        let lock_b = try_lock_repo(&repo_b, &LockOptions::default()).unwrap();
+        match lock_all_or_nothing(&repos, &LockOptions::default(), Duration::from_millis(100)) {
+            Err((failed, LockError::LockBusy(_))) => assert_eq!(failed, repo_b),
+            other => panic!("unexpected: {:?}", other.map(|locks| locks.len())),
+        }
+        // 先に獲得したaのロックは解放されている
+        drop(try_lock_repo(&repo_a, &LockOptions::default()).unwrap());
+
+        let holder = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(200));
+            drop(lock_b);
+        });
+        let locks =
+            lock_all_or_nothing(&repos, &LockOptions::default(), Duration::from_secs(10)).unwrap();
+        assert_eq!(locks.len(), 2);
+        holder.join().unwrap();
+    }
+
+    #[test]
+    fn test_tree_lock_path_is_keyed_by_canonical_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let root = temp_dir.path().join("root");
+        std::fs::create_dir_all(root.join("sub")).unwrap();
+
+        let cache_dir = temp_dir.path().join("cache");
+        let path = |root: &Path| tree_lock_path(&cache_dir, root);
+        let key = path(&root).unwrap();
+        assert_eq!(key.parent(), Some(cache_dir.join("locks").as_path()));
+        assert!(key
+            .file_name()
+            .unwrap()
+            .to_string_lossy()
+            .starts_with("tree-"));
+        assert_eq!(path(&root.join("sub/..")), Some(key.clone()));
+        assert_ne!(path(&root.join("sub")), Some(key));
+        assert_eq!(path(&temp_dir.path().join("missing")), None);
+    }
 }
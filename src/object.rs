@@ -0,0 +1,287 @@
+//! looseオブジェクトの展開とハッシュ検証
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, Read};
+use std::path::Path;
+
+use flate2::read::ZlibDecoder;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+
+use crate::scanner::ObjectFormat;
+
+/// ヘッダ ("<type> <size>\0") の最大長
+const MAX_HEADER_LEN: usize = 64;
+
+/// looseオブジェクト検証のエラー
+#[derive(Debug)]
+pub enum ObjectError {
+    /// 読み込み・展開失敗
+    Io(io::Error),
+    /// ヘッダが不正
+    InvalidHeader(String),
+    /// ヘッダのサイズと実データのサイズが不一致
+    SizeMismatch { expected: u64, actual: u64 },
+    /// 再計算したハッシュがファイル名と不一致
+    HashMismatch { expected: String, actual: String },
+}
+
+impl fmt::Display for ObjectError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjectError::Io(e) => write!(f, "read failed: {}", e),
+            ObjectError::InvalidHeader(h) => write!(f, "invalid header: {}", h),
+            ObjectError::SizeMismatch { expected, actual } => {
+                write!(f, "size mismatch: header {} / actual {}", expected, actual)
+            }
+            ObjectError::HashMismatch { expected, actual } => {
+                write!(
+                    f,
+                    "hash mismatch: expected {} / actual {}",
+                    expected, actual
+                )
+            }
+        }
+    }
+}
+
+impl From<io::Error> for ObjectError {
+    fn from(e: io::Error) -> Self {
+        ObjectError::Io(e)
+    }
+}
+
+/// looseオブジェクトのヘッダ情報
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ObjectHeader {
+    /// オブジェクト種別 (blob, tree, commit, tag)
+    pub kind: String,
+    /// 展開後の内容サイズ
+    pub size: u64,
+}
+
+/// オブジェクトフォーマットに応じたハッシュ計算
+pub(crate) enum Hasher {
+    Sha1(Sha1),
+    Sha256(Sha256),
+}
+
+impl Hasher {
+    pub(crate) fn new(format: ObjectFormat) -> Self {
+        match format {
+            ObjectFormat::Sha1 => Hasher::Sha1(Sha1::new()),
+            ObjectFormat::Sha256 => Hasher::Sha256(Sha256::new()),
+        }
+    }
+
+    pub(crate) fn update(&mut self, data: &[u8]) {
+        match self {
+            Hasher::Sha1(h) => h.update(data),
+            Hasher::Sha256(h) => h.update(data),
+        }
+    }
+
+    pub(crate) fn finalize_hex(self) -> String {
+        let digest = match self {
+            Hasher::Sha1(h) => h.finalize().to_vec(),
+            Hasher::Sha256(h) => h.finalize().to_vec(),
+        };
+        digest.iter().map(|b| format!("{:02x}", b)).collect()
+    }
+}
+
+/// looseオブジェクトを展開し、ヘッダを検証してハッシュを再計算する
+///
+/// Args:
+///     path: looseオブジェクトファイルのパス
+///     format: ハッシュ計算に使うオブジェクトフォーマット
+///
+/// Returns:
+///     ヘッダ情報と再計算したハッシュ (16進小文字)
+pub fn hash_loose_object(
+    path: &Path,
+    format: ObjectFormat,
+) -> Result<(ObjectHeader, String), ObjectError> {
+    let mut decoder = ZlibDecoder::new(BufReader::new(File::open(path)?));
+    let mut hasher = Hasher::new(format);
+
+    // ヘッダをNULまで読む
+    let mut header = Vec::new();
+    let mut byte = [0u8; 1];
+    loop {
+        if decoder.read(&mut byte)? == 0 {
+            return Err(ObjectError::InvalidHeader(
+                String::from_utf8_lossy(&header).into_owned(),
+            ));
+        }
+        if byte[0] == 0 {
+            break;
+        }
+        header.push(byte[0]);
+        if header.len() > MAX_HEADER_LEN {
+            return Err(ObjectError::InvalidHeader(
+                String::from_utf8_lossy(&header).into_owned(),
+            ));
+        }
+    }
+    let header_text = String::from_utf8_lossy(&header).into_owned();
+    let parsed = parse_header(&header_text)
+        .ok_or_else(|| ObjectError::InvalidHeader(header_text.clone()))?;
+    hasher.update(&header);
+    hasher.update(&[0]);
+
+    let mut actual = 0u64;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = decoder.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        actual += n as u64;
+    }
+
+    if actual != parsed.size {
+        return Err(ObjectError::SizeMismatch {
+            expected: parsed.size,
+            actual,
+        });
+    }
+
+    Ok((parsed, hasher.finalize_hex()))
+}
+
+/// looseオブジェクトの内容がファイル名のハッシュと一致するか検証する
+///
+/// Args:
+///     path: looseオブジェクトファイルのパス
+///     expected_hash: 期待するハッシュ (ディレクトリ名 + ファイル名)
+///     format: オブジェクトフォーマット
+///
+/// Returns:
+///     一致すればOk(ヘッダ情報)
+pub fn verify_loose_object(
+    path: &Path,
+    expected_hash: &str,
+    format: ObjectFormat,
+) -> Result<ObjectHeader, ObjectError> {
+    let (header, actual) = hash_loose_object(path, format)?;
+    if !actual.eq_ignore_ascii_case(expected_hash) {
+        return Err(ObjectError::HashMismatch {
+            expected: expected_hash.to_string(),
+            actual,
+        });
+    }
+    Ok(header)
+}
+
+fn parse_header(header: &str) -> Option<ObjectHeader> {
+    let (kind, size) = header.split_once(' ')?;
+    if !matches!(kind, "blob" | "tree" | "commit" | "tag") {
+        return None;
+    }
+    if size.is_empty() || !size.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(ObjectHeader {
+        kind: kind.to_string(),
+        size: size.parse().ok()?,
+    })
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use flate2::write::ZlibEncoder;
+    use flate2::Compression;
+    use std::fs;
+    use std::io::Write;
+    use std::path::PathBuf;
+    use tempfile::TempDir;
+
+    /// テスト用のlooseオブジェクトを書き込み、そのハッシュを返す
+    pub(crate) fn write_loose_object(
+        objects_dir: &Path,
+        format: ObjectFormat,
+        content: &[u8],
+    ) -> (PathBuf, String) {
+        let mut raw = format!("blob {}\0", content.len()).into_bytes();
+        raw.extend_from_slice(content);
+
+        let mut hasher = Hasher::new(format);
+        hasher.update(&raw);
+        let hash = hasher.finalize_hex();
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(&raw).unwrap();
+        let compressed = encoder.finish().unwrap();
+
+        let dir = objects_dir.join(&hash[..2]);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(&hash[2..]);
+        fs::write(&path, compressed).unwrap();
+        (path, hash)
+    }
+
+    #[test]
+    fn test_verify_loose_object_sha1_known_hash() {
+        let temp_dir = TempDir::new().unwrap();
+        let (path, hash) = write_loose_object(temp_dir.path(), ObjectFormat::Sha1, b"hi\n");
+        // `echo hi | git hash-object --stdin`
+        assert_eq!(hash, "45b983be36b73c0788dc9cbcb76cbb80fc7bb057");
+
+        let header = verify_loose_object(&path, &hash, ObjectFormat::Sha1).unwrap();
+        assert_eq!(header.kind, "blob");
+        assert_eq!(header.size, 3);
+    }
+
+    #[test]
+    fn test_verify_loose_object_sha256() {
+        let temp_dir = TempDir::new().unwrap();
+        let (path, hash) = write_loose_object(temp_dir.path(), ObjectFormat::Sha256, b"hi\n");
+        assert_eq!(hash.len(), 64);
+        assert!(verify_loose_object(&path, &hash, ObjectFormat::Sha256).is_ok());
+    }
+
+    #[test]
+    fn test_verify_loose_object_hash_mismatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let (path, _) = write_loose_object(temp_dir.path(), ObjectFormat::Sha1, b"hi\n");
+        let result = verify_loose_object(&path, &"0".repeat(40), ObjectFormat::Sha1);
+        assert!(matches!(result, Err(ObjectError::HashMismatch { .. })));
+    }
+
+    #[test]
+    fn test_verify_loose_object_truncated() {
+        let temp_dir = TempDir::new().unwrap();
+        let (path, hash) = write_loose_object(temp_dir.path(), ObjectFormat::Sha1, &[b'x'; 4096]);
+        let data = fs::read(&path).unwrap();
+        fs::write(&path, &data[..data.len() / 2]).unwrap();
+
+        assert!(verify_loose_object(&path, &hash, ObjectFormat::Sha1).is_err());
+    }
+
+    #[test]
+    fn test_verify_loose_object_not_zlib() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("garbage");
+        fs::write(&path, b"not a zlib stream").unwrap();
+
+        assert!(verify_loose_object(&path, &"0".repeat(40), ObjectFormat::Sha1).is_err());
+    }
+
+    #[test]
+    fn test_parse_header() {
+        assert_eq!(
+            parse_header("commit 123"),
+            Some(ObjectHeader {
+                kind: "commit".to_string(),
+                size: 123
+            })
+        );
+        assert_eq!(parse_header("blob"), None);
+        assert_eq!(parse_header("unknown 1"), None);
+        assert_eq!(parse_header("blob -1"), None);
+    }
+}
@@ -0,0 +1,219 @@
+//! 所有者・モードの異なるファイルの扱い (`--owner-policy`)
+//!
+//! ハードリンクは1つのinodeを共有するため、所有者 (uid, gid) とモードも共有される。
+//! 別ユーザーのリポジトリどうしで共有すると、一方のリポジトリに他人の所有する
+//! ファイルが現れ、読み取りできない・`git gc` で消せないといった問題が起きる。
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+
+use clap::ValueEnum;
+
+use crate::scanner::{select_source_and_duplicates, DuplicateGroup, GitObjectInfo};
+
+/// 所有者・モードの異なるファイルを含むグループの扱い
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OwnerPolicy {
+    /// 所有者・モードの異なるファイルを含むグループは置換しない
+    Refuse,
+    /// 所有者・モードが同じファイルどうしだけを共有する
+    #[default]
+    SameOwner,
+    /// 所有者が異なっても共有し、共有するinodeを全員が読めて書き込めないモードにそろえる
+    Normalize,
+}
+
+/// ファイルの所有者とモード
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Ownership {
+    pub uid: u32,
+    pub gid: u32,
+    /// パーミッションビット (`st_mode & 0o7777`)
+    pub mode: u32,
+}
+
+impl Ownership {
+    /// ファイルの所有者とモードを取得する
+    #[cfg(unix)]
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(Ownership {
+            uid: metadata.uid(),
+            gid: metadata.gid(),
+            mode: metadata.mode() & 0o7777,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(path: &Path) -> io::Result<Self> {
+        fs::metadata(path)?;
+        Ok(Ownership {
+            uid: 0,
+            gid: 0,
+            mode: 0,
+        })
+    }
+
+    /// `normalize` でそろえるモード (全員読み取り可、書き込み不可)
+    pub fn normalized_mode(self) -> u32 {
+        (self.mode & !0o222) | 0o444
+    }
+}
+
+/// ポリシーを適用した重複グループ
+#[derive(Debug)]
+pub struct OwnerPlan {
+    /// 置換するグループ
+    pub groups: Vec<DuplicateGroup>,
+    /// 所有者・モードが異なるため元のグループのsourceと共有しなかったファイル
+    pub mismatched: Vec<PathBuf>,
+}
+
+/// 重複グループに所有者ポリシーを適用する
+///
+/// - `refuse`: sourceと所有者・モードが異なるduplicateがあるグループを丸ごと除く
+///   (`mismatched` には異なるduplicateだけを入れる)
+/// - `same-owner`: sourceと異なるduplicateを所有者・モードごとに分け、それぞれで
+///   sourceを選び直す
+/// - `normalize`: そのまま (置換前に [`normalize_mode`] でsourceのモードをそろえる)
+///
+/// 所有者を取得できないファイルは異なるものとして扱う。
+pub fn apply_owner_policy(groups: Vec<DuplicateGroup>, policy: OwnerPolicy) -> OwnerPlan {
+    let mut plan = OwnerPlan {
+        groups: Vec::new(),
+        mismatched: Vec::new(),
+    };
+    if policy == OwnerPolicy::Normalize {
+        plan.groups = groups;
+        return plan;
+    }
+
+    for group in groups {
+        let source_owner = Ownership::of(&group.source.path).ok();
+        let (same, others): (Vec<_>, Vec<_>) = group.duplicates.into_iter().partition(|dup| {
+            source_owner.is_some() && Ownership::of(&dup.path).ok() == source_owner
+        });
+        if others.is_empty() {
+            plan.groups.push(DuplicateGroup {
+                source: group.source,
+                duplicates: same,
+            });
+            continue;
+        }
+        plan.mismatched
+            .extend(others.iter().map(|dup| dup.path.clone()));
+
+        // sourceと同じ所有者・モードのduplicateは、置換しないが異なるものには数えない
+        if policy == OwnerPolicy::Refuse {
+            continue;
+        }
+        if !same.is_empty() {
+            plan.groups.push(DuplicateGroup {
+                source: group.source,
+                duplicates: same,
+            });
+        }
+        let mut by_owner: BTreeMap<Ownership, Vec<GitObjectInfo>> = BTreeMap::new();
+        for dup in others {
+            if let Ok(owner) = Ownership::of(&dup.path) {
+                by_owner.entry(owner).or_default().push(dup);
+            }
+        }
+        plan.groups.extend(
+            by_owner
+                .into_values()
+                .filter_map(select_source_and_duplicates),
+        );
+    }
+    plan
+}
+
+/// 共有するinodeを全員が読めて書き込めないモードにする (`normalize`)
+///
+/// Returns:
+///     モードを変更した場合true
+#[cfg(unix)]
+pub fn normalize_mode(path: &Path) -> io::Result<bool> {
+    let owner = Ownership::of(path)?;
+    let mode = owner.normalized_mode();
+    if mode == owner.mode {
+        return Ok(false);
+    }
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))?;
+    Ok(true)
+}
+
+#[cfg(not(unix))]
+pub fn normalize_mode(_path: &Path) -> io::Result<bool> {
+    Ok(false)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::scanner::find_duplicates;
+    use tempfile::TempDir;
+
+    /// 同じハッシュのオブジェクトを `modes` の数だけリポジトリに作り、それぞれのモードにする
+    fn objects_with_modes(temp_dir: &Path, modes: &[u32]) -> Vec<GitObjectInfo> {
+        modes
+            .iter()
+            .enumerate()
+            .map(|(i, mode)| {
+                let dir = temp_dir.join(format!("repo{}/.git/objects/ab", i));
+                fs::create_dir_all(&dir).unwrap();
+                let path = dir.join("cdef0123456789abcdef0123456789abcdef01");
+                fs::write(&path, b"same").unwrap();
+                fs::set_permissions(&path, fs::Permissions::from_mode(*mode)).unwrap();
+                GitObjectInfo::from_path(&path).unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_same_owner_splits_by_mode() {
+        let temp_dir = TempDir::new().unwrap();
+        let objects = objects_with_modes(temp_dir.path(), &[0o444, 0o444, 0o600, 0o600]);
+        let plan = apply_owner_policy(find_duplicates(objects), OwnerPolicy::SameOwner);
+
+        assert_eq!(plan.groups.len(), 2);
+        assert_eq!(plan.mismatched.len(), 2);
+        for group in &plan.groups {
+            let owner = Ownership::of(&group.source.path).unwrap();
+            assert_eq!(group.duplicates.len(), 1);
+            assert_eq!(Ownership::of(&group.duplicates[0].path).unwrap(), owner);
+        }
+    }
+
+    #[test]
+    fn test_refuse_and_normalize() {
+        let temp_dir = TempDir::new().unwrap();
+        let objects = objects_with_modes(temp_dir.path(), &[0o444, 0o444, 0o600]);
+
+        let plan = apply_owner_policy(find_duplicates(objects.clone()), OwnerPolicy::Refuse);
+        assert!(plan.groups.is_empty());
+        assert_eq!(plan.mismatched, vec![objects[2].path.clone()]);
+
+        let plan = apply_owner_policy(find_duplicates(objects), OwnerPolicy::Normalize);
+        assert_eq!(plan.groups.len(), 1);
+        assert_eq!(plan.groups[0].duplicates.len(), 2);
+        assert!(plan.mismatched.is_empty());
+    }
+
+    #[test]
+    fn test_normalize_mode() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("object");
+        fs::write(&path, b"x").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        assert!(normalize_mode(&path).unwrap());
+        assert_eq!(Ownership::of(&path).unwrap().mode, 0o444);
+        assert!(!normalize_mode(&path).unwrap());
+    }
+}
@@ -0,0 +1,457 @@
+//! packファイルの探索と重複判定
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+use crate::hardlink::{is_same_content, ReplaceResult, VerifyMode};
+use crate::object::Hasher;
+use crate::repository::GitRepository;
+use crate::scanner::ObjectFormat;
+
+/// packと一緒に共有する付随ファイルの拡張子
+pub const PACK_COMPANION_EXTENSIONS: [&str; 5] = ["idx", "rev", "bitmap", "mtimes", "keep"];
+
+/// packファイル (`objects/pack/pack-<hash>.pack`) の情報
+#[derive(Debug, Clone)]
+pub struct PackInfo {
+    /// `.pack` ファイルのパス
+    pub path: PathBuf,
+    /// ファイル名のハッシュ (`pack-<hash>.pack` の `<hash>`)
+    pub name_hash: String,
+    /// packファイル末尾のチェックサム (16進)
+    pub checksum: String,
+    /// オブジェクトフォーマット
+    pub format: ObjectFormat,
+    /// `.pack` のファイルの更新時刻
+    pub created: SystemTime,
+    /// `.pack` のファイルサイズ (バイト)
+    pub size: u64,
+    /// `.pack` のinode番号
+    pub inode: u64,
+    /// `.pack` のデバイスID
+    pub device: u64,
+}
+
+impl PackInfo {
+    /// `.pack` ファイルのパスからPackInfoを作成する
+    ///
+    /// Args:
+    ///     path: `.pack` ファイルのパス
+    ///     format: リポジトリのオブジェクトフォーマット
+    ///
+    /// Returns:
+    ///     ファイル名と末尾チェックサムが妥当で `.idx` があればSome(PackInfo)
+    pub fn from_path(path: &Path, format: ObjectFormat) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        let name_hash = file_name.strip_prefix("pack-")?.strip_suffix(".pack")?;
+        if name_hash.len() != format.hex_len() || !name_hash.chars().all(|c| c.is_ascii_hexdigit())
+        {
+            return None;
+        }
+        // .idx の無いpackは転送途中などの可能性があるため対象外
+        if !path.with_extension("idx").is_file() {
+            return None;
+        }
+
+        let metadata = fs::metadata(path).ok()?;
+        let checksum = read_trailer_checksum(path, format).ok()?;
+
+        #[cfg(unix)]
+        let (inode, device) = (metadata.ino(), metadata.dev());
+
+        #[cfg(not(unix))]
+        let (inode, device) = (0, 0);
+
+        Some(PackInfo {
+            path: path.to_path_buf(),
+            name_hash: name_hash.to_ascii_lowercase(),
+            checksum,
+            format,
+            created: metadata.modified().ok()?,
+            size: metadata.len(),
+            inode,
+            device,
+        })
+    }
+
+    /// 存在する付随ファイルのパスを返す
+    pub fn companions(&self) -> Vec<PathBuf> {
+        PACK_COMPANION_EXTENSIONS
+            .iter()
+            .map(|ext| self.path.with_extension(ext))
+            .filter(|p| p.is_file())
+            .collect()
+    }
+}
+
+/// 重複packのグループ
+#[derive(Debug)]
+pub struct PackGroup {
+    /// 基準pack (最古のもの)
+    pub source: PackInfo,
+    /// 置換対象のpack (既にリンク済みは含まない)
+    pub duplicates: Vec<PackInfo>,
+}
+
+impl PackGroup {
+    /// sourceとtargetの間で共有するファイルの組を返す
+    ///
+    /// `.pack` は常に含む。付随ファイルは両方に存在し、内容が一致するものだけを含める
+    /// (片方にしか無い `.keep` や `.bitmap` を増やしたり消したりしない)。
+    pub fn link_pairs(&self, target: &PackInfo) -> io::Result<Vec<(PathBuf, PathBuf)>> {
+        let mut pairs = vec![(self.source.path.clone(), target.path.clone())];
+        for ext in PACK_COMPANION_EXTENSIONS {
+            let source = self.source.path.with_extension(ext);
+            let target = target.path.with_extension(ext);
+            if source.is_file() && target.is_file() && is_same_content(&source, &target)? {
+                pairs.push((source, target));
+            }
+        }
+        Ok(pairs)
+    }
+}
+
+/// リポジトリの `objects/pack` を探索する
+pub fn scan_repository_packs(repo: &GitRepository) -> Vec<PackInfo> {
+    let pack_dir = repo.objects_dir.join("pack");
+    let Ok(entries) = fs::read_dir(&pack_dir) else {
+        return Vec::new();
+    };
+
+    let mut packs: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .map(|e| e.path())
+        .filter(|p| p.extension().is_some_and(|ext| ext == "pack") && p.is_file())
+        .filter_map(|p| PackInfo::from_path(&p, repo.format))
+        .collect();
+    packs.sort_by(|a, b| a.path.cmp(&b.path));
+    packs
+}
+
+/// packを (デバイス, フォーマット, 名前のハッシュ, サイズ, 末尾チェックサム) で
+/// グループ化し、重複グループを返す
+///
+/// Args:
+///     packs: 探索で発見したpackのリスト
+///
+/// Returns:
+///     未リンクのpackを含むグループのみ返す
+pub fn find_duplicate_packs(packs: Vec<PackInfo>) -> Vec<PackGroup> {
+    type PackKey = (u64, ObjectFormat, String, u64, String);
+    let mut groups: HashMap<PackKey, Vec<PackInfo>> = HashMap::new();
+    for pack in packs {
+        let key = (
+            pack.device,
+            pack.format,
+            pack.name_hash.clone(),
+            pack.size,
+            pack.checksum.clone(),
+        );
+        groups.entry(key).or_default().push(pack);
+    }
+
+    let mut result: Vec<_> = groups
+        .into_values()
+        .filter(|v| v.len() >= 2)
+        .filter_map(|mut packs| {
+            packs.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.path.cmp(&b.path)));
+            let source = packs.remove(0);
+            let duplicates: Vec<_> = packs
+                .into_iter()
+                .filter(|p| p.inode != source.inode)
+                .collect();
+            if duplicates.is_empty() {
+                None
+            } else {
+                Some(PackGroup { source, duplicates })
+            }
+        })
+        .collect();
+    result.sort_by(|a, b| a.source.path.cmp(&b.source.path));
+    result
+}
+
+/// 置換前にpackの内容を検証する
+///
+/// 名前のハッシュ・サイズ・末尾チェックサムが同じでも、本体が壊れている
+/// (末尾のチェックサムと合わない) ことはあるため、`--verify` の指定に従って確かめる。
+///
+/// Args:
+///     source: 基準pack
+///     target: 置換対象のpack
+///     mode: 検証方法 (Hashではsourceの末尾チェックサムを内容から再計算する)
+///
+/// Returns:
+///     不一致や破損を検出した場合はその内容を示すErr
+pub fn verify_pack_before_replace(
+    source: &PackInfo,
+    target: &PackInfo,
+    mode: VerifyMode,
+) -> Result<(), ReplaceResult> {
+    if mode == VerifyMode::None {
+        return Ok(());
+    }
+
+    if mode == VerifyMode::Hash {
+        if let Err(e) = verify_trailer_checksum(&source.path, source.format) {
+            return Err(ReplaceResult::ContentMismatch(format!(
+                "source破損: {} ({})",
+                source.path.display(),
+                e
+            )));
+        }
+    }
+
+    match is_same_content(&source.path, &target.path) {
+        Ok(true) => Ok(()),
+        Ok(false) => Err(ReplaceResult::ContentMismatch(format!(
+            "内容不一致: {}",
+            source.path.display()
+        ))),
+        Err(e) => Err(ReplaceResult::Error(e.to_string())),
+    }
+}
+
+/// packと `.idx` のチェックサムを検証する (gitコマンド不要)
+///
+/// `.pack` と `.idx` それぞれの末尾チェックサムを内容から再計算し、`.idx` に
+/// 記録された `.pack` のチェックサムとも照合する。各オブジェクトは展開しない。
+///
+/// Args:
+///     path: `.pack` ファイルのパス
+///     format: リポジトリのオブジェクトフォーマット
+pub fn verify_pack_checksums(path: &Path, format: ObjectFormat) -> io::Result<()> {
+    let checksum = verify_trailer_checksum(path, format)?;
+    let idx = path.with_extension("idx");
+    verify_trailer_checksum(&idx, format)?;
+    // `.idx` の末尾は `.pack` のチェックサム、`.idx` 自身のチェックサムの順
+    if read_checksum_from_end(&idx, format, 2)? != checksum {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("{} does not match the pack", idx.display()),
+        ));
+    }
+    Ok(())
+}
+
+/// ファイル末尾のチェックサムを、それより前の内容から再計算して照合する
+///
+/// Returns:
+///     一致すれば末尾のチェックサム (16進)、一致しなければInvalidDataのErr
+fn verify_trailer_checksum(path: &Path, format: ObjectFormat) -> io::Result<String> {
+    let trailer_len = format.hex_len() / 2;
+    let file = File::open(path)?;
+    let len = file.metadata()?.len();
+    if len < trailer_len as u64 {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "file too short"));
+    }
+
+    let mut reader = BufReader::new(file);
+    let mut hasher = Hasher::new(format);
+    let mut remaining = len - trailer_len as u64;
+    let mut buf = [0u8; 64 * 1024];
+    while remaining > 0 {
+        let n = buf.len().min(remaining as usize);
+        reader.read_exact(&mut buf[..n])?;
+        hasher.update(&buf[..n]);
+        remaining -= n as u64;
+    }
+    let mut trailer = vec![0u8; trailer_len];
+    reader.read_exact(&mut trailer)?;
+
+    let expected: String = trailer.iter().map(|b| format!("{:02x}", b)).collect();
+    let actual = hasher.finalize_hex();
+    if actual != expected {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!(
+                "checksum mismatch: trailer {} / actual {}",
+                expected, actual
+            ),
+        ));
+    }
+    Ok(expected)
+}
+
+/// packファイル末尾のチェックサムを読む
+fn read_trailer_checksum(path: &Path, format: ObjectFormat) -> io::Result<String> {
+    read_checksum_from_end(path, format, 1)
+}
+
+/// ファイル末尾から `nth` 番目のチェックサムを読む (1が末尾)
+fn read_checksum_from_end(path: &Path, format: ObjectFormat, nth: u64) -> io::Result<String> {
+    let len = (format.hex_len() / 2) as u64;
+    let mut file = File::open(path)?;
+    if file.metadata()?.len() < len * nth {
+        return Err(io::Error::new(io::ErrorKind::InvalidData, "file too short"));
+    }
+    file.seek(SeekFrom::End(-((len * nth) as i64)))?;
+    let mut buf = vec![0u8; len as usize];
+    file.read_exact(&mut buf)?;
+    Ok(buf.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardlink::{is_same_inode, replace_set_with_hardlinks};
+    use crate::repository::resolve_repository;
+    use sha1::{Digest, Sha1};
+    use tempfile::TempDir;
+
+    const NAME: &str = "0123456789abcdef0123456789abcdef01234567";
+
+    fn create_pack(repo: &Path, name: &str, content: &[u8], companions: &[&str]) -> PathBuf {
+        let pack_dir = repo.join(".git/objects/pack");
+        fs::create_dir_all(&pack_dir).unwrap();
+        let pack = pack_dir.join(format!("pack-{}.pack", name));
+        fs::write(&pack, content).unwrap();
+        for ext in companions {
+            fs::write(pack.with_extension(ext), format!("{}-{}", ext, name)).unwrap();
+        }
+        pack
+    }
+
+    fn scan(repo: &Path) -> Vec<PackInfo> {
+        scan_repository_packs(&resolve_repository(repo).unwrap())
+    }
+
+    /// 末尾に内容のSHA-1を付けたpackの中身
+    fn with_trailer(body: &[u8]) -> Vec<u8> {
+        let mut content = body.to_vec();
+        content.extend_from_slice(&Sha1::digest(body));
+        content
+    }
+
+    #[test]
+    fn test_pack_info_requires_idx() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        create_pack(&repo, NAME, &[7u8; 64], &[]);
+        assert!(scan(&repo).is_empty());
+
+        fs::write(
+            repo.join(".git/objects/pack")
+                .join(format!("pack-{}.idx", NAME)),
+            b"idx",
+        )
+        .unwrap();
+        let packs = scan(&repo);
+        assert_eq!(packs.len(), 1);
+        assert_eq!(packs[0].name_hash, NAME);
+        assert_eq!(packs[0].checksum, "07".repeat(20));
+    }
+
+    #[test]
+    fn test_pack_info_rejects_invalid_name() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        create_pack(&repo, "short", &[7u8; 64], &["idx"]);
+        assert!(scan(&repo).is_empty());
+    }
+
+    #[test]
+    fn test_find_duplicate_packs() {
+        let temp_dir = TempDir::new().unwrap();
+        let mut packs = Vec::new();
+        for repo in ["repo1", "repo2", "repo3"] {
+            let repo = temp_dir.path().join(repo);
+            create_pack(&repo, NAME, &[7u8; 64], &["idx", "rev"]);
+            packs.extend(scan(&repo));
+        }
+
+        let groups = find_duplicate_packs(packs);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].duplicates.len(), 2);
+    }
+
+    #[test]
+    fn test_find_duplicate_packs_different_content() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo1 = temp_dir.path().join("repo1");
+        let repo2 = temp_dir.path().join("repo2");
+        create_pack(&repo1, NAME, &[7u8; 64], &["idx"]);
+        create_pack(&repo2, NAME, &[8u8; 64], &["idx"]);
+
+        let mut packs = scan(&repo1);
+        packs.extend(scan(&repo2));
+        assert!(find_duplicate_packs(packs).is_empty());
+    }
+
+    #[test]
+    fn test_link_pairs_and_replace_pack_set() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo1 = temp_dir.path().join("repo1");
+        let repo2 = temp_dir.path().join("repo2");
+        let source = create_pack(&repo1, NAME, &[7u8; 64], &["idx", "rev", "bitmap"]);
+        let target = create_pack(&repo2, NAME, &[7u8; 64], &["idx", "rev", "keep"]);
+
+        let mut packs = scan(&repo1);
+        packs.extend(scan(&repo2));
+        let groups = find_duplicate_packs(packs);
+        assert_eq!(groups.len(), 1);
+
+        let pairs = groups[0].link_pairs(&groups[0].duplicates[0]).unwrap();
+        // pack, idx, rev のみ (bitmapとkeepは片方にしか無い)
+        assert_eq!(pairs.len(), 3);
+
+        assert_eq!(replace_set_with_hardlinks(&pairs), ReplaceResult::Replaced);
+        assert!(is_same_inode(&source, &target).unwrap());
+        assert!(
+            is_same_inode(&source.with_extension("idx"), &target.with_extension("idx")).unwrap()
+        );
+        assert!(target.with_extension("keep").exists());
+        assert!(!target.with_extension("bitmap").exists());
+
+        let mut packs = scan(&repo1);
+        packs.extend(scan(&repo2));
+        assert!(find_duplicate_packs(packs).is_empty());
+    }
+
+    #[test]
+    fn test_verify_pack_before_replace() {
+        let temp_dir = TempDir::new().unwrap();
+        let content = with_trailer(b"PACK body");
+        // 本体だけが壊れ、末尾のチェックサムは元のまま
+        let mut corrupted = content.clone();
+        corrupted[4] ^= 0xff;
+        let pack = |repo: &str, content: &[u8]| {
+            let repo = temp_dir.path().join(repo);
+            create_pack(&repo, NAME, content, &["idx"]);
+            scan(&repo).remove(0)
+        };
+        let source = pack("source", &content);
+        let target = pack("target", &corrupted);
+        let broken_source = pack("broken", &corrupted);
+        // 名前・サイズ・末尾チェックサムでは区別できない
+        assert_eq!(source.checksum, target.checksum);
+
+        assert_eq!(
+            verify_pack_before_replace(&source, &target, VerifyMode::None),
+            Ok(())
+        );
+        assert!(matches!(
+            verify_pack_before_replace(&source, &target, VerifyMode::Content),
+            Err(ReplaceResult::ContentMismatch(_))
+        ));
+        // 同じように壊れたpackどうしはバイト比較では一致するが、ハッシュの再計算で検出する
+        assert_eq!(
+            verify_pack_before_replace(&broken_source, &target, VerifyMode::Content),
+            Ok(())
+        );
+        assert!(matches!(
+            verify_pack_before_replace(&broken_source, &target, VerifyMode::Hash),
+            Err(ReplaceResult::ContentMismatch(_))
+        ));
+        assert_eq!(
+            verify_pack_before_replace(&source, &source, VerifyMode::Hash),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,290 @@
+//! 内容アドレスのオブジェクトプール (`--pool`)
+//!
+//! 各リポジトリのlooseオブジェクトを、リポジトリの外に置いた専用ディレクトリ
+//! (`<pool>/<sha1|sha256>/xx/yyyy...`) のファイルにハードリンクする。
+//! 共有元がプールなので、どのリポジトリを削除・再cloneしても共有が崩れない。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+use crate::hardlink::link_count;
+use crate::scanner::{select_source_and_duplicates, GitObjectInfo, ObjectFormat};
+
+/// プールディレクトリ
+#[derive(Debug, Clone)]
+pub struct ObjectPool {
+    root: PathBuf,
+}
+
+/// プールのgc結果
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct PoolGcStats {
+    /// 残したエントリ数
+    pub kept: usize,
+    /// 削除した (dry-runでは削除予定の) エントリ数
+    pub removed: usize,
+    /// 削除したエントリのサイズ合計
+    pub removed_bytes: u64,
+    /// 削除に失敗したエントリ数
+    pub errors: usize,
+}
+
+/// プールを共有元とした1ハッシュ分の計画
+#[derive(Debug)]
+pub struct PoolGroup {
+    /// プール内のエントリ (存在すればSome)
+    pub entry: Option<GitObjectInfo>,
+    /// プールに無い場合にプールへ登録するファイル
+    pub adopt: Option<GitObjectInfo>,
+    /// プールのエントリへのハードリンクに置換するファイル
+    pub duplicates: Vec<GitObjectInfo>,
+}
+
+impl ObjectPool {
+    /// 指定ディレクトリをプールとして使う
+    pub fn new(root: PathBuf) -> Self {
+        ObjectPool { root }
+    }
+
+    /// キャッシュディレクトリ内の既定のプールディレクトリ (`<cache_dir>/pool`)
+    pub fn default_dir(cache_dir: &Path) -> PathBuf {
+        cache_dir.join("pool")
+    }
+
+    /// プールディレクトリ
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    /// オブジェクトに対応するプール内のパス
+    pub fn entry_path(&self, format: ObjectFormat, hash: &str) -> PathBuf {
+        self.root
+            .join(format.as_str())
+            .join(&hash[..2])
+            .join(&hash[2..])
+    }
+
+    /// プール内のエントリを取得する
+    pub fn entry(&self, format: ObjectFormat, hash: &str) -> Option<GitObjectInfo> {
+        GitObjectInfo::from_path_with_format(&self.entry_path(format, hash), format)
+    }
+
+    /// プールのデバイスID (ディレクトリが無ければ作成する)
+    #[cfg(unix)]
+    pub fn device(&self) -> io::Result<u64> {
+        fs::create_dir_all(&self.root)?;
+        Ok(fs::metadata(&self.root)?.dev())
+    }
+
+    #[cfg(not(unix))]
+    pub fn device(&self) -> io::Result<u64> {
+        fs::create_dir_all(&self.root)?;
+        Ok(0)
+    }
+
+    /// 同一ハッシュのファイル群について、プールを共有元とした計画を立てる
+    ///
+    /// プールに既にエントリがあればそれを共有元とし、同じinodeでないファイルを全て
+    /// 置換対象にする。無ければ最も多くリンクされているファイルをプールに登録し、
+    /// 残りを置換対象にする。
+    ///
+    /// Args:
+    ///     files: 同じ (フォーマット, ハッシュ) のファイル (同一デバイス上)
+    pub fn plan(&self, files: Vec<GitObjectInfo>) -> Option<PoolGroup> {
+        let first = files.first()?;
+        if let Some(entry) = self.entry(first.format, &first.hash) {
+            let duplicates = files
+                .into_iter()
+                .filter(|f| (f.device, f.inode) != (entry.device, entry.inode))
+                .collect();
+            return Some(PoolGroup {
+                entry: Some(entry),
+                adopt: None,
+                duplicates,
+            });
+        }
+
+        let fallback = first.clone();
+        let (adopt, duplicates) = match select_source_and_duplicates(files) {
+            Some(group) => (group.source, group.duplicates),
+            // 全て同じinode (またはファイルが1つ) なら、そのまま登録するだけ
+            None => (fallback, Vec::new()),
+        };
+        Some(PoolGroup {
+            entry: None,
+            adopt: Some(adopt),
+            duplicates,
+        })
+    }
+
+    /// ファイルをプールに登録する (プール内のパスにハードリンクを作る)
+    ///
+    /// Returns:
+    ///     登録後のプール内エントリ
+    pub fn adopt(&self, object: &GitObjectInfo) -> io::Result<GitObjectInfo> {
+        let path = self.entry_path(object.format, &object.hash);
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        match fs::hard_link(&object.path, &path) {
+            Ok(()) => {}
+            // 並行する実行が先に登録した場合はそれを使う
+            Err(e) if e.kind() == io::ErrorKind::AlreadyExists => {}
+            Err(e) => return Err(e),
+        }
+        GitObjectInfo::from_path_with_format(&path, object.format)
+            .ok_or_else(|| io::Error::new(io::ErrorKind::NotFound, "pool entry disappeared"))
+    }
+
+    /// どのリポジトリからもリンクされなくなった (リンク数1の) エントリを削除する
+    ///
+    /// Args:
+    ///     dry_run: trueなら削除せず数えるだけ
+    ///     on_removed: 削除した (dry-runでは削除予定の) エントリごとに呼ばれる
+    pub fn gc<F>(&self, dry_run: bool, mut on_removed: F) -> PoolGcStats
+    where
+        F: FnMut(&Path),
+    {
+        let mut stats = PoolGcStats::default();
+        for format in [ObjectFormat::Sha1, ObjectFormat::Sha256] {
+            let format_dir = self.root.join(format.as_str());
+            let Ok(fanouts) = fs::read_dir(&format_dir) else {
+                continue;
+            };
+            let mut fanouts: Vec<_> = fanouts.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+            fanouts.sort();
+
+            for fanout in fanouts {
+                let Ok(entries) = fs::read_dir(&fanout) else {
+                    continue;
+                };
+                let mut entries: Vec<_> =
+                    entries.filter_map(|e| e.ok()).map(|e| e.path()).collect();
+                entries.sort();
+
+                for entry in entries {
+                    if GitObjectInfo::from_path_with_format(&entry, format).is_none() {
+                        continue;
+                    }
+                    if !link_count(&entry).is_ok_and(|n| n <= 1) {
+                        stats.kept += 1;
+                        continue;
+                    }
+                    let size = fs::metadata(&entry).map(|m| m.len()).unwrap_or(0);
+                    if !dry_run && fs::remove_file(&entry).is_err() {
+                        stats.errors += 1;
+                        continue;
+                    }
+                    stats.removed += 1;
+                    stats.removed_bytes += size;
+                    on_removed(&entry);
+                }
+                if !dry_run {
+                    // 空になったfan-outディレクトリは消す (空でなければ失敗するだけ)
+                    let _ = fs::remove_dir(&fanout);
+                }
+            }
+        }
+        stats
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardlink::{is_same_inode, replace_with_hardlink, ReplaceResult};
+    use crate::object::tests::write_loose_object;
+    use crate::scanner::group_by_hash;
+    use tempfile::TempDir;
+
+    fn pool_and_objects(temp_dir: &Path, repos: &[&str]) -> (ObjectPool, Vec<GitObjectInfo>) {
+        let pool = ObjectPool::new(temp_dir.join("pool"));
+        let objects = repos
+            .iter()
+            .map(|repo| {
+                let (path, _) =
+                    write_loose_object(&temp_dir.join(repo), ObjectFormat::Sha1, b"hello\n");
+                GitObjectInfo::from_path(&path).unwrap()
+            })
+            .collect();
+        (pool, objects)
+    }
+
+    #[test]
+    fn test_entry_path_layout() {
+        let pool = ObjectPool::new(PathBuf::from("/pool"));
+        assert_eq!(
+            pool.entry_path(ObjectFormat::Sha1, "abcdef"),
+            PathBuf::from("/pool/sha1/ab/cdef")
+        );
+    }
+
+    #[test]
+    fn test_plan_adopts_then_uses_pool_entry() {
+        let temp_dir = TempDir::new().unwrap();
+        let (pool, objects) = pool_and_objects(temp_dir.path(), &["repo1", "repo2"]);
+
+        let group = pool.plan(objects.clone()).unwrap();
+        assert!(group.entry.is_none());
+        let adopt = group.adopt.unwrap();
+        assert_eq!(group.duplicates.len(), 1);
+
+        let entry = pool.adopt(&adopt).unwrap();
+        assert!(is_same_inode(&entry.path, &adopt.path).unwrap());
+        for dup in &group.duplicates {
+            assert_eq!(
+                replace_with_hardlink(&entry.path, &dup.path),
+                ReplaceResult::Replaced
+            );
+        }
+
+        // 2回目以降はプールのエントリが共有元になり、置換対象は無い
+        let objects: Vec<_> = objects
+            .iter()
+            .map(|o| GitObjectInfo::from_path(&o.path).unwrap())
+            .collect();
+        let group = pool.plan(objects).unwrap();
+        assert!(group.entry.is_some());
+        assert!(group.duplicates.is_empty());
+    }
+
+    #[test]
+    fn test_plan_single_object_is_adopted() {
+        let temp_dir = TempDir::new().unwrap();
+        let (pool, objects) = pool_and_objects(temp_dir.path(), &["repo1"]);
+
+        let groups = group_by_hash(objects);
+        let group = pool.plan(groups.into_iter().next().unwrap()).unwrap();
+        assert!(group.adopt.is_some());
+        assert!(group.duplicates.is_empty());
+    }
+
+    #[test]
+    fn test_gc_removes_unreferenced_entries() {
+        let temp_dir = TempDir::new().unwrap();
+        let (pool, objects) = pool_and_objects(temp_dir.path(), &["repo1"]);
+        let entry = pool.adopt(&objects[0]).unwrap();
+
+        // リポジトリ側からリンクされている間は残す
+        let stats = pool.gc(false, |_| {});
+        assert_eq!(stats.kept, 1);
+        assert_eq!(stats.removed, 0);
+
+        fs::remove_file(&objects[0].path).unwrap();
+        let dry = pool.gc(true, |_| {});
+        assert_eq!(dry.removed, 1);
+        assert!(entry.path.exists());
+
+        let mut removed = Vec::new();
+        let stats = pool.gc(false, |p| removed.push(p.to_path_buf()));
+        assert_eq!(stats.removed, 1);
+        assert_eq!(stats.removed_bytes, entry.size);
+        assert_eq!(removed, vec![entry.path.clone()]);
+        assert!(!entry.path.exists());
+        assert!(!entry.path.parent().unwrap().exists());
+    }
+}
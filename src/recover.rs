@@ -0,0 +1,257 @@
+//! 中断された実行の後始末 (`recover` サブコマンドと起動時の回復処理)
+//!
+//! 置換の途中でプロセスが止まると、objectsディレクトリに退避用リンク
+//! (`*.git-share-obj.bak`) や一時ファイル (`*.git-share-obj.tmp`)、reflinkの
+//! 確認に使った試しのファイル (`.git-share-obj-probe-*`) が残り、
+//! 古い版の置換方式ではtargetが無くなっていることもある。これらを探し、
+//! targetが無ければ復元し、不要なものは削除する。
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::hardlink::{is_same_content, BACKUP_SUFFIX, PROBE_PREFIX, TEMP_SUFFIX};
+use crate::object::verify_loose_object;
+use crate::repository::GitRepository;
+use crate::scanner::ObjectFormat;
+
+/// 残っていたファイルの種類
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LeftoverKind {
+    /// 置換前のtargetを指す退避用リンク
+    Backup,
+    /// rename前のリンク・コピー
+    Temp,
+    /// reflinkの確認に使った試しのファイル
+    Probe,
+}
+
+/// 中断で残ったファイル
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Leftover {
+    /// 残っていたファイル
+    pub path: PathBuf,
+    /// 本来のファイル (接尾辞を除いたパス。試しのファイルでは自身)
+    pub target: PathBuf,
+    pub kind: LeftoverKind,
+}
+
+/// 回復処理の結果
+#[derive(Debug, PartialEq)]
+pub enum RecoverResult {
+    /// targetが無かったので残っていたファイルから復元した
+    Restored,
+    /// targetがあるので残っていたファイルを削除した
+    Removed,
+    /// 自動では判断できないため残した (理由)
+    Kept(String),
+    /// エラー発生
+    Error(String),
+}
+
+impl Leftover {
+    /// パスが退避用リンク・一時ファイル・試しのファイルならLeftoverにする
+    pub fn from_path(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_str()?;
+        if name.starts_with(PROBE_PREFIX) {
+            return Some(Leftover {
+                path: path.to_path_buf(),
+                target: path.to_path_buf(),
+                kind: LeftoverKind::Probe,
+            });
+        }
+        let (target, kind) = if let Some(target) = name.strip_suffix(BACKUP_SUFFIX) {
+            (target, LeftoverKind::Backup)
+        } else {
+            (name.strip_suffix(TEMP_SUFFIX)?, LeftoverKind::Temp)
+        };
+        if target.is_empty() {
+            return None;
+        }
+        Some(Leftover {
+            path: path.to_path_buf(),
+            target: path.with_file_name(target),
+            kind,
+        })
+    }
+
+    /// targetがlooseオブジェクトなら (ハッシュ, フォーマット)
+    fn loose_object_id(&self) -> Option<(String, ObjectFormat)> {
+        let file_name = self.target.file_name()?.to_str()?;
+        let dir_name = self.target.parent()?.file_name()?.to_str()?;
+        if dir_name.len() != 2 {
+            return None;
+        }
+        let format = ObjectFormat::from_file_name_len(file_name.len())?;
+        Some((format!("{}{}", dir_name, file_name), format))
+    }
+}
+
+/// リポジトリのobjectsディレクトリ (fan-out・pack・info) から残ったファイルを探す
+///
+/// Returns:
+///     パス順のLeftover
+pub fn find_leftovers(repo: &GitRepository) -> Vec<Leftover> {
+    let Ok(entries) = fs::read_dir(&repo.objects_dir) else {
+        return Vec::new();
+    };
+    let mut leftovers: Vec<Leftover> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .filter_map(|dir| fs::read_dir(dir.path()).ok())
+        .flatten()
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .filter_map(|e| Leftover::from_path(&e.path()))
+        .collect();
+    leftovers.sort_by(|a, b| a.path.cmp(&b.path));
+    leftovers
+}
+
+/// 残ったファイル1件を後始末する
+///
+/// - 試しのファイル: 本来のファイルは無いので削除する
+/// - targetがある: 一時ファイルは削除する。退避用リンクはtargetと内容が同じなら削除し、
+///   異なれば残す
+/// - targetが無い: 退避用リンクはtargetに戻す。一時ファイルはlooseオブジェクトとして
+///   ハッシュが一致する場合だけtargetにし、それ以外は残す
+///
+/// Args:
+///     leftover: 残ったファイル
+///     dry_run: trueなら何も変更せず、行う予定の結果を返す
+pub fn recover_leftover(leftover: &Leftover, dry_run: bool) -> RecoverResult {
+    if leftover.kind == LeftoverKind::Probe {
+        if dry_run {
+            return RecoverResult::Removed;
+        }
+        return match fs::remove_file(&leftover.path) {
+            Ok(()) => RecoverResult::Removed,
+            Err(e) => RecoverResult::Error(e.to_string()),
+        };
+    }
+    match fs::symlink_metadata(&leftover.target) {
+        Ok(_) => {
+            if leftover.kind == LeftoverKind::Backup {
+                match is_same_content(&leftover.path, &leftover.target) {
+                    Ok(true) => {}
+                    Ok(false) => return RecoverResult::Kept("targetと内容が異なる".to_string()),
+                    Err(e) => return RecoverResult::Error(e.to_string()),
+                }
+            }
+            if dry_run {
+                return RecoverResult::Removed;
+            }
+            match fs::remove_file(&leftover.path) {
+                Ok(()) => RecoverResult::Removed,
+                Err(e) => RecoverResult::Error(e.to_string()),
+            }
+        }
+        Err(e) if e.kind() == io::ErrorKind::NotFound => {
+            if leftover.kind == LeftoverKind::Temp {
+                // 一時ファイルは書き込み途中かもしれないので、検証できるものだけ使う
+                let verified = leftover.loose_object_id().is_some_and(|(hash, format)| {
+                    verify_loose_object(&leftover.path, &hash, format).is_ok()
+                });
+                if !verified {
+                    return RecoverResult::Kept(
+                        "targetが無く、一時ファイルを検証できない".to_string(),
+                    );
+                }
+            }
+            if dry_run {
+                return RecoverResult::Restored;
+            }
+            match fs::rename(&leftover.path, &leftover.target) {
+                Ok(()) => RecoverResult::Restored,
+                Err(e) => RecoverResult::Error(e.to_string()),
+            }
+        }
+        Err(e) => RecoverResult::Error(e.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::object::tests::write_loose_object;
+    use crate::repository::resolve_repository;
+    use tempfile::TempDir;
+
+    fn leftover_path(target: &Path, suffix: &str) -> PathBuf {
+        let name = format!(
+            "{}{}",
+            target.file_name().unwrap().to_string_lossy(),
+            suffix
+        );
+        target.with_file_name(name)
+    }
+
+    #[test]
+    fn test_leftover_from_path() {
+        let leftover =
+            Leftover::from_path(Path::new("/r/objects/ab/cdef.git-share-obj.bak")).unwrap();
+        assert_eq!(leftover.target, PathBuf::from("/r/objects/ab/cdef"));
+        assert_eq!(leftover.kind, LeftoverKind::Backup);
+        let leftover =
+            Leftover::from_path(Path::new("/r/objects/pack/p.idx.git-share-obj.tmp")).unwrap();
+        assert_eq!(leftover.kind, LeftoverKind::Temp);
+        assert!(Leftover::from_path(Path::new("/r/objects/ab/cdef")).is_none());
+        assert!(Leftover::from_path(Path::new("/r/objects/ab/.git-share-obj.bak")).is_none());
+        let leftover =
+            Leftover::from_path(Path::new("/r/objects/ab/.git-share-obj-probe-42.clone")).unwrap();
+        assert_eq!(leftover.kind, LeftoverKind::Probe);
+    }
+
+    #[test]
+    fn test_recover_restores_missing_and_removes_redundant() {
+        let temp_dir = TempDir::new().unwrap();
+        let objects_dir = temp_dir.path().join("repo/.git/objects");
+        let (missing, _) = write_loose_object(&objects_dir, ObjectFormat::Sha1, b"missing\n");
+        let (present, _) = write_loose_object(&objects_dir, ObjectFormat::Sha1, b"present\n");
+        let (partial, _) = write_loose_object(&objects_dir, ObjectFormat::Sha1, b"partial\n");
+        let (copied, copied_hash) =
+            write_loose_object(&objects_dir, ObjectFormat::Sha1, b"copied\n");
+        let repo = resolve_repository(&temp_dir.path().join("repo")).unwrap();
+
+        // 旧方式で退避リネームした直後に止まった (targetが無い)
+        fs::rename(&missing, leftover_path(&missing, BACKUP_SUFFIX)).unwrap();
+        // 退避用リンクの削除前に止まった
+        fs::hard_link(&present, leftover_path(&present, BACKUP_SUFFIX)).unwrap();
+        // rename前の一時ファイル (targetあり)
+        fs::write(leftover_path(&present, TEMP_SUFFIX), b"garbage").unwrap();
+        // 書き込み途中の一時ファイル (targetなし)
+        fs::remove_file(&partial).unwrap();
+        fs::write(leftover_path(&partial, TEMP_SUFFIX), b"truncated").unwrap();
+        // 書き込みを終えた一時ファイル (targetなし)
+        fs::rename(&copied, leftover_path(&copied, TEMP_SUFFIX)).unwrap();
+        // reflinkの確認の途中で止まった
+        let probe = present.with_file_name(format!("{}42", PROBE_PREFIX));
+        fs::write(&probe, b"probe").unwrap();
+
+        let leftovers = find_leftovers(&repo);
+        assert_eq!(leftovers.len(), 6);
+
+        // dry-runでは何も変えない
+        for leftover in &leftovers {
+            recover_leftover(leftover, true);
+            assert!(leftover.path.exists());
+        }
+
+        for leftover in &leftovers {
+            let result = recover_leftover(leftover, false);
+            if leftover.target == missing || leftover.target == copied {
+                assert_eq!(result, RecoverResult::Restored);
+            } else if leftover.target == present || leftover.path == probe {
+                assert_eq!(result, RecoverResult::Removed);
+            } else {
+                assert!(matches!(result, RecoverResult::Kept(_)), "{:?}", result);
+            }
+        }
+        assert!(missing.exists());
+        assert!(verify_loose_object(&copied, &copied_hash, ObjectFormat::Sha1).is_ok());
+        assert!(present.exists());
+        assert!(!partial.exists());
+        assert!(!probe.exists());
+        assert_eq!(find_leftovers(&repo).len(), 1);
+    }
+}
@@ -0,0 +1,252 @@
+//! 機械可読な実行レポート (`--format json` / `--format ndjson`)
+//!
+//! スキーマは `docs/report-format-ja.md` を参照。互換性の無い変更を行う場合は
+//! `SCHEMA_VERSION` を上げる。
+
+use std::io::{self, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use clap::ValueEnum;
+use serde_json::{json, Value};
+
+use crate::fsck::FsckResult;
+use crate::hardlink::{ReplaceResult, UnshareResult};
+
+/// レポートのスキーマバージョン
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// 人間向けの出力を標準出力に出すか (JSON出力時は標準エラーに回す)
+static TEXT_ON_STDOUT: AtomicBool = AtomicBool::new(true);
+
+/// 人間向けの出力先が標準出力か
+pub fn text_on_stdout() -> bool {
+    TEXT_ON_STDOUT.load(Ordering::Relaxed)
+}
+
+/// 人間向けの出力 (JSON出力時は標準出力を汚さないよう標準エラーに出す)
+#[macro_export]
+macro_rules! say {
+    ($($arg:tt)*) => {
+        if $crate::report::text_on_stdout() {
+            println!($($arg)*);
+        } else {
+            eprintln!($($arg)*);
+        }
+    };
+}
+
+/// 出力形式
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum OutputFormat {
+    /// 人間向けのテキスト (ローカライズあり)
+    #[default]
+    Text,
+    /// 実行終了時に1つのJSONドキュメントを出力
+    Json,
+    /// イベントごとに1行のJSONを逐次出力 (NDJSON)
+    Ndjson,
+}
+
+/// イベントを形式に応じて出力・蓄積する
+#[derive(Debug)]
+pub struct Reporter {
+    format: OutputFormat,
+    events: Vec<Value>,
+}
+
+impl Reporter {
+    /// レポーターを作る (JSON系の形式では人間向けの出力を標準エラーに切り替える)
+    pub fn new(format: OutputFormat) -> Self {
+        TEXT_ON_STDOUT.store(format == OutputFormat::Text, Ordering::Relaxed);
+        let mut reporter = Reporter {
+            format,
+            events: Vec::new(),
+        };
+        reporter.emit(json!({
+            "type": "start",
+            "schema_version": SCHEMA_VERSION,
+            "tool_version": env!("CARGO_PKG_VERSION"),
+        }));
+        reporter
+    }
+
+    /// イベントを1件出力する
+    pub fn emit(&mut self, event: Value) {
+        match self.format {
+            OutputFormat::Text => {}
+            OutputFormat::Json => self.events.push(event),
+            OutputFormat::Ndjson => {
+                let mut out = io::stdout().lock();
+                let _ = writeln!(out, "{}", event);
+                let _ = out.flush();
+            }
+        }
+    }
+
+    /// 終了イベントを出力し、JSON形式ならドキュメント全体を書き出す
+    pub fn finish(mut self, exit_code: i32) {
+        self.emit(json!({ "type": "finish", "exit_code": exit_code }));
+        if self.format == OutputFormat::Json {
+            let document = json!({
+                "schema_version": SCHEMA_VERSION,
+                "events": self.events,
+            });
+            println!("{}", document);
+        }
+    }
+
+    /// 蓄積したイベント (JSON形式のみ)
+    pub fn events(&self) -> &[Value] {
+        &self.events
+    }
+}
+
+/// パスをJSON文字列にする
+pub fn path_value(path: &Path) -> Value {
+    Value::String(path.to_string_lossy().into_owned())
+}
+
+/// ロック取得結果のイベント
+///
+/// Args:
+///     waited_ms: ロックを待った時間 (ミリ秒、`--lock-timeout`)
+pub fn lock_event(repo: &Path, waited_ms: u64, error: Option<String>) -> Value {
+    json!({
+        "type": "lock",
+        "repo": path_value(repo),
+        "ok": error.is_none(),
+        "waited_ms": waited_ms,
+        "error": error,
+    })
+}
+
+/// fsck結果のイベント
+///
+/// Args:
+///     phase: "pre" / "post" / "only"
+///     result: fsckの結果
+pub fn fsck_event(phase: &str, result: &FsckResult) -> Value {
+    json!({
+        "type": "fsck",
+        "phase": phase,
+        "repo": path_value(&result.repo),
+        "ok": result.success,
+        "code": result.code,
+        "stderr": result.stderr,
+    })
+}
+
+/// 置換結果の種別名とエラー内容
+pub fn replace_result_parts(result: &ReplaceResult) -> (&'static str, Option<&str>) {
+    match result {
+        ReplaceResult::Replaced => ("replaced", None),
+        ReplaceResult::AlreadyLinked => ("already_linked", None),
+        ReplaceResult::CrossFilesystem => ("cross_filesystem", None),
+        ReplaceResult::RolledBack(e) => ("rolled_back", Some(e)),
+        ReplaceResult::RollbackFailed(e) => ("rollback_failed", Some(e)),
+        ReplaceResult::ContentMismatch(e) => ("content_mismatch", Some(e)),
+        ReplaceResult::ReflinkUnsupported(e) => ("reflink_unsupported", Some(e)),
+        ReplaceResult::TooManyLinks(e) => ("too_many_links", Some(e)),
+        ReplaceResult::Error(e) => ("error", Some(e)),
+    }
+}
+
+/// 置換1件のイベント
+///
+/// Args:
+///     kind: "loose" / "pack"
+///     source: リンク元
+///     target: 置換対象
+///     result: 置換結果
+pub fn replace_event(kind: &str, source: &Path, target: &Path, result: &ReplaceResult) -> Value {
+    let (name, detail) = replace_result_parts(result);
+    json!({
+        "type": "replace",
+        "kind": kind,
+        "source": path_value(source),
+        "target": path_value(target),
+        "result": name,
+        "detail": detail,
+    })
+}
+
+/// ハードリンク解除1件のイベント (restore / unshare)
+pub fn unshare_event(command: &str, target: &Path, result: &UnshareResult) -> Value {
+    let (name, detail) = match result {
+        UnshareResult::Unshared => ("unshared", None),
+        UnshareResult::NotShared => ("not_shared", None),
+        UnshareResult::RolledBack(e) => ("rolled_back", Some(e)),
+        UnshareResult::RollbackFailed(e) => ("rollback_failed", Some(e)),
+        UnshareResult::Error(e) => ("error", Some(e)),
+    };
+    json!({
+        "type": command,
+        "target": path_value(target),
+        "result": name,
+        "detail": detail,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    #[test]
+    fn test_reporter_json_collects_events() {
+        let mut reporter = Reporter::new(OutputFormat::Json);
+        reporter.emit(lock_event(Path::new("/repo"), 0, None));
+        reporter.emit(lock_event(
+            Path::new("/busy"),
+            1000,
+            Some("busy".to_string()),
+        ));
+
+        let events = reporter.events();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0]["type"], "start");
+        assert_eq!(events[0]["schema_version"], SCHEMA_VERSION);
+        assert_eq!(events[1]["ok"], true);
+        assert_eq!(events[2]["ok"], false);
+        assert_eq!(events[2]["error"], "busy");
+        assert_eq!(events[2]["waited_ms"], 1000);
+        TEXT_ON_STDOUT.store(true, Ordering::Relaxed);
+    }
+
+    #[test]
+    fn test_replace_event() {
+        let event = replace_event(
+            "loose",
+            Path::new("/a/objects/ab/cd"),
+            Path::new("/b/objects/ab/cd"),
+            &ReplaceResult::RolledBack("link failed".to_string()),
+        );
+        assert_eq!(event["result"], "rolled_back");
+        assert_eq!(event["detail"], "link failed");
+        assert_eq!(event["target"], "/b/objects/ab/cd");
+
+        let event = replace_event(
+            "pack",
+            Path::new("/a"),
+            Path::new("/b"),
+            &ReplaceResult::Replaced,
+        );
+        assert_eq!(event["result"], "replaced");
+        assert!(event["detail"].is_null());
+    }
+
+    #[test]
+    fn test_fsck_event() {
+        let result = FsckResult {
+            repo: PathBuf::from("/repo"),
+            success: false,
+            code: Some(1),
+            stderr: "broken".to_string(),
+        };
+        let event = fsck_event("post", &result);
+        assert_eq!(event["phase"], "post");
+        assert_eq!(event["ok"], false);
+        assert_eq!(event["code"], 1);
+    }
+}
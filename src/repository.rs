@@ -0,0 +1,476 @@
+//! Gitリポジトリの探索とオブジェクトディレクトリの解決
+
+use std::collections::HashSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+use crate::scanner::{read_object_format, ObjectFormat, PROGRESS_INTERVAL};
+
+/// 発見したGitリポジトリ
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitRepository {
+    /// gitコマンドを実行するパス (作業ツリーのルート、またはgitディレクトリそのもの)
+    pub path: PathBuf,
+    /// gitディレクトリ (`.git`、bareリポジトリ、`.git/modules/*`、worktree管理ディレクトリ)
+    pub git_dir: PathBuf,
+    /// 実体のオブジェクトディレクトリ (worktreeでは共通ディレクトリの `objects`)
+    pub objects_dir: PathBuf,
+    /// オブジェクトフォーマット
+    pub format: ObjectFormat,
+}
+
+impl GitRepository {
+    /// gitディレクトリからリポジトリ情報を組み立てる
+    ///
+    /// `commondir` がある場合 (linked worktree) はその先のオブジェクトディレクトリを使う。
+    ///
+    /// Args:
+    ///     path: gitコマンドを実行するパス
+    ///     git_dir: gitディレクトリ
+    ///
+    /// Returns:
+    ///     オブジェクトディレクトリが存在すればSome(GitRepository)
+    pub fn from_git_dir(path: &Path, git_dir: &Path) -> Option<Self> {
+        let common_dir = match fs::read_to_string(git_dir.join("commondir")) {
+            Ok(content) => resolve_relative(git_dir, content.trim()),
+            Err(_) => git_dir.to_path_buf(),
+        };
+        let objects_dir = common_dir.join("objects");
+        if !objects_dir.is_dir() {
+            return None;
+        }
+
+        Some(GitRepository {
+            path: path.to_path_buf(),
+            git_dir: git_dir.to_path_buf(),
+            format: read_object_format(&common_dir),
+            objects_dir,
+        })
+    }
+
+    /// 重複排除用のキー (正規化したオブジェクトディレクトリ)
+    pub fn store_key(&self) -> PathBuf {
+        fs::canonicalize(&self.objects_dir).unwrap_or_else(|_| self.objects_dir.clone())
+    }
+}
+
+/// パスをGitリポジトリとして解決する
+///
+/// 次のいずれかに対応する。
+/// - `.git` ディレクトリを持つ作業ツリー
+/// - `gitdir:` を記した `.git` ファイルを持つ作業ツリー (linked worktree、submodule)
+/// - gitディレクトリそのもの (bareリポジトリ、`.git/modules/*`)
+///
+/// Args:
+///     path: 解決対象のパス
+///
+/// Returns:
+///     Gitリポジトリであれば Some(GitRepository)
+pub fn resolve_repository(path: &Path) -> Option<GitRepository> {
+    let dot_git = path.join(".git");
+    if dot_git.is_dir() {
+        return GitRepository::from_git_dir(path, &dot_git);
+    }
+    if dot_git.is_file() {
+        let git_dir = read_gitdir_file(&dot_git)?;
+        return GitRepository::from_git_dir(path, &git_dir);
+    }
+    if is_git_dir(path) {
+        return GitRepository::from_git_dir(path, path);
+    }
+    None
+}
+
+/// 指定ディレクトリ以下のGitリポジトリを列挙する
+///
+/// 同じオブジェクトディレクトリを共有するリポジトリ (worktreeなど) は1つにまとめる。
+pub fn discover_repositories(base_path: &Path) -> Vec<GitRepository> {
+    discover_repositories_with_progress(base_path, |_| {})
+}
+
+/// 指定ディレクトリ以下のGitリポジトリを列挙する（進捗通知付き）
+pub fn discover_repositories_with_progress<F>(
+    base_path: &Path,
+    on_progress: F,
+) -> Vec<GitRepository>
+where
+    F: Fn(&Path) + Sync,
+{
+    discover_repositories_parallel(base_path, 1, on_progress)
+}
+
+/// 指定ディレクトリ以下のGitリポジトリを複数スレッドで列挙する（進捗通知付き）
+///
+/// 未探索ディレクトリは共有のスタックに積み、`jobs` 個のスレッドが取り出して
+/// 読む (深さ優先なので保持するパスは幅×深さ程度に収まる)。
+/// 結果は `dedup_repositories` でパス順に並べるため、スレッド数によらず同じになる。
+///
+/// Args:
+///     base_path: 探索開始ディレクトリ
+///     jobs: スレッド数 (0は1とみなす)
+///     on_progress: 一定件数ごとに呼ばれるコールバック
+///
+/// Returns:
+///     発見したリポジトリ (パス順)
+pub fn discover_repositories_parallel<F>(
+    base_path: &Path,
+    jobs: usize,
+    on_progress: F,
+) -> Vec<GitRepository>
+where
+    F: Fn(&Path) + Sync,
+{
+    let mut repos = Vec::new();
+    let mut roots = Vec::new();
+    // 探索開始パス自体もリポジトリかどうか判定する
+    match classify(
+        base_path,
+        fs::symlink_metadata(base_path).ok().map(|m| m.file_type()),
+    ) {
+        Entry::Repository(repo) => repos.push(repo),
+        Entry::Directory => roots.push(base_path.to_path_buf()),
+        Entry::Other => {}
+    }
+
+    let walk = WalkState {
+        queue: Mutex::new((roots, 0)),
+        ready: Condvar::new(),
+        scanned: AtomicUsize::new(0),
+        repos: Mutex::new(repos),
+    };
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1) {
+            scope.spawn(|| walk.run(&on_progress));
+        }
+    });
+
+    dedup_repositories(walk.repos.into_inner().unwrap_or_default())
+}
+
+/// 並列探索の共有状態
+struct WalkState {
+    /// (未探索ディレクトリ, 処理中のスレッド数)
+    queue: Mutex<(Vec<PathBuf>, usize)>,
+    ready: Condvar,
+    scanned: AtomicUsize,
+    repos: Mutex<Vec<GitRepository>>,
+}
+
+impl WalkState {
+    fn run<F>(&self, on_progress: &F)
+    where
+        F: Fn(&Path),
+    {
+        while let Some(dir) = self.next_dir() {
+            let mut subdirs = Vec::new();
+            let mut found = Vec::new();
+            if let Ok(entries) = fs::read_dir(&dir) {
+                for entry in entries.filter_map(|e| e.ok()) {
+                    let path = entry.path();
+                    let scanned = self.scanned.fetch_add(1, Ordering::Relaxed) + 1;
+                    if scanned.is_multiple_of(PROGRESS_INTERVAL) {
+                        on_progress(&path);
+                    }
+                    match classify(&path, entry.file_type().ok()) {
+                        Entry::Repository(repo) => found.push(repo),
+                        Entry::Directory => subdirs.push(path),
+                        Entry::Other => {}
+                    }
+                }
+            }
+
+            if !found.is_empty() {
+                if let Ok(mut repos) = self.repos.lock() {
+                    repos.extend(found);
+                }
+            }
+            self.finish_dir(subdirs);
+        }
+    }
+
+    /// 次に読むディレクトリを取り出す (全スレッドが待機状態になったらNone)
+    fn next_dir(&self) -> Option<PathBuf> {
+        let mut state = self.queue.lock().ok()?;
+        loop {
+            if let Some(dir) = state.0.pop() {
+                state.1 += 1;
+                return Some(dir);
+            }
+            if state.1 == 0 {
+                self.ready.notify_all();
+                return None;
+            }
+            state = self.ready.wait(state).ok()?;
+        }
+    }
+
+    fn finish_dir(&self, subdirs: Vec<PathBuf>) {
+        if let Ok(mut state) = self.queue.lock() {
+            state.0.extend(subdirs);
+            state.1 -= 1;
+        }
+        self.ready.notify_all();
+    }
+}
+
+/// 探索中のエントリの分類
+enum Entry {
+    /// リポジトリを発見した (その中には降りない)
+    Repository(GitRepository),
+    /// 降りて探索するディレクトリ
+    Directory,
+    /// それ以外 (通常ファイル、シンボリックリンクなど)
+    Other,
+}
+
+fn classify(path: &Path, file_type: Option<fs::FileType>) -> Entry {
+    let Some(file_type) = file_type else {
+        return Entry::Other;
+    };
+    let name = path.file_name();
+
+    if file_type.is_dir() {
+        if name.is_some_and(|n| n == "objects") {
+            // objectsを持つgitディレクトリ (.git、bare、.git/modules/*)
+            if let Some(git_dir) = path.parent().filter(|d| is_git_dir(d)) {
+                let repo_path = if git_dir.file_name().is_some_and(|n| n == ".git") {
+                    git_dir.parent().unwrap_or(git_dir)
+                } else {
+                    git_dir
+                };
+                // オブジェクトディレクトリの中にリポジトリは無いので降りない
+                return match GitRepository::from_git_dir(repo_path, git_dir) {
+                    Some(repo) => Entry::Repository(repo),
+                    None => Entry::Other,
+                };
+            }
+        }
+        return Entry::Directory;
+    }
+
+    if file_type.is_file() && name.is_some_and(|n| n == ".git") {
+        // gitdir: を記した .git ファイル (linked worktree、submodule)
+        let repo = path.parent().and_then(|repo_path| {
+            read_gitdir_file(path)
+                .and_then(|git_dir| GitRepository::from_git_dir(repo_path, &git_dir))
+        });
+        if let Some(repo) = repo {
+            return Entry::Repository(repo);
+        }
+    }
+    Entry::Other
+}
+
+/// オブジェクトディレクトリを共有するリポジトリを1つにまとめる
+///
+/// 代表には自前のオブジェクトディレクトリを持つもの (linked worktreeでないもの)、
+/// 次に作業ツリーを持つものを優先し、結果はパス順に並べる。
+pub fn dedup_repositories(mut repos: Vec<GitRepository>) -> Vec<GitRepository> {
+    repos.sort_by_cached_key(|repo| {
+        (
+            repo.git_dir.join("commondir").exists(),
+            repo.path == repo.git_dir,
+            repo.path.clone(),
+        )
+    });
+
+    let mut seen = HashSet::new();
+    let mut unique: Vec<_> = repos
+        .into_iter()
+        .filter(|repo| seen.insert(repo.store_key()))
+        .collect();
+    unique.sort_by(|a, b| a.path.cmp(&b.path));
+    unique
+}
+
+/// gitディレクトリらしいか (`.git` という名前か、HEADとobjectsを持つか) を判定する
+fn is_git_dir(dir: &Path) -> bool {
+    if !dir.join("objects").is_dir() {
+        return false;
+    }
+    dir.file_name().is_some_and(|n| n == ".git") || dir.join("HEAD").is_file()
+}
+
+/// `.git` ファイルの `gitdir:` 行を読み、gitディレクトリのパスを返す
+fn read_gitdir_file(dot_git: &Path) -> Option<PathBuf> {
+    let content = fs::read_to_string(dot_git).ok()?;
+    let target = content
+        .lines()
+        .find_map(|line| line.strip_prefix("gitdir:"))?
+        .trim();
+    if target.is_empty() {
+        return None;
+    }
+    Some(resolve_relative(dot_git.parent()?, target))
+}
+
+fn resolve_relative(base: &Path, target: &str) -> PathBuf {
+    let target = Path::new(target);
+    if target.is_absolute() {
+        target.to_path_buf()
+    } else {
+        base.join(target)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process::Command;
+    use tempfile::TempDir;
+
+    fn git(args: &[&str], dir: &Path) {
+        let status = Command::new("git")
+            .args(args)
+            .current_dir(dir)
+            .env("GIT_AUTHOR_NAME", "test")
+            .env("GIT_AUTHOR_EMAIL", "test@example.com")
+            .env("GIT_COMMITTER_NAME", "test")
+            .env("GIT_COMMITTER_EMAIL", "test@example.com")
+            .status()
+            .unwrap();
+        assert!(status.success(), "git {:?} failed", args);
+    }
+
+    #[test]
+    fn test_discover_plain_repository() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        git(&["init", "-q"], &repo);
+
+        let repos = discover_repositories(temp_dir.path());
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].path, repo);
+        assert_eq!(repos[0].objects_dir, repo.join(".git/objects"));
+    }
+
+    #[test]
+    fn test_discover_bare_repository() {
+        let temp_dir = TempDir::new().unwrap();
+        git(&["init", "-q", "--bare", "foo.git"], temp_dir.path());
+
+        let repos = discover_repositories(temp_dir.path());
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].path, temp_dir.path().join("foo.git"));
+        assert_eq!(
+            repos[0].objects_dir,
+            temp_dir.path().join("foo.git/objects")
+        );
+    }
+
+    #[test]
+    fn test_discover_linked_worktree_shares_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("repo");
+        fs::create_dir_all(&repo).unwrap();
+        git(&["init", "-q"], &repo);
+        git(&["commit", "-q", "--allow-empty", "-m", "init"], &repo);
+        git(&["worktree", "add", "-q", "../wt"], &repo);
+
+        let repos = discover_repositories(temp_dir.path());
+        // worktreeは本体とオブジェクトディレクトリを共有するので1つにまとまる
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].path, repo);
+
+        let wt = resolve_repository(&temp_dir.path().join("wt")).unwrap();
+        assert_eq!(wt.store_key(), repos[0].store_key());
+    }
+
+    #[test]
+    fn test_discover_gitdir_file_outside_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let store = temp_dir.path().join("store/real.git");
+        fs::create_dir_all(store.join("objects")).unwrap();
+        fs::write(store.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let work = temp_dir.path().join("root/work");
+        fs::create_dir_all(&work).unwrap();
+        fs::write(work.join(".git"), "gitdir: ../../store/real.git\n").unwrap();
+
+        let repos = discover_repositories(&temp_dir.path().join("root"));
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].path, work);
+        assert_eq!(
+            repos[0].store_key(),
+            fs::canonicalize(store.join("objects")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_discover_submodule_store() {
+        let temp_dir = TempDir::new().unwrap();
+        let repo = temp_dir.path().join("super");
+        let modules = repo.join(".git/modules/sub");
+        fs::create_dir_all(repo.join(".git/objects")).unwrap();
+        fs::write(repo.join(".git/HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::create_dir_all(modules.join("objects")).unwrap();
+        fs::write(modules.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+
+        let sub = repo.join("sub");
+        fs::create_dir_all(&sub).unwrap();
+        fs::write(sub.join(".git"), "gitdir: ../.git/modules/sub\n").unwrap();
+
+        let repos = discover_repositories(temp_dir.path());
+        // 親リポジトリとsubmoduleのストア (作業ツリー経由と直接発見の重複は1つに)
+        assert_eq!(repos.len(), 2);
+        assert!(repos.iter().any(|r| r.path == repo));
+        let sub_repo = repos.iter().find(|r| r.path != repo).unwrap();
+        assert_eq!(sub_repo.path, sub);
+        assert_eq!(
+            sub_repo.store_key(),
+            fs::canonicalize(modules.join("objects")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_resolve_repository_non_repo() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(resolve_repository(temp_dir.path()).is_none());
+    }
+
+    #[test]
+    fn test_discover_reads_object_format() {
+        let temp_dir = TempDir::new().unwrap();
+        let git_dir = temp_dir.path().join("repo/.git");
+        fs::create_dir_all(git_dir.join("objects")).unwrap();
+        fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        fs::write(
+            git_dir.join("config"),
+            "[extensions]\n\tobjectformat = sha256\n",
+        )
+        .unwrap();
+
+        let repos = discover_repositories(temp_dir.path());
+        assert_eq!(repos.len(), 1);
+        assert_eq!(repos[0].format, ObjectFormat::Sha256);
+    }
+
+    #[test]
+    fn test_discover_parallel_matches_sequential() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..20 {
+            let git_dir = temp_dir
+                .path()
+                .join(format!("group{}/repo{}/.git", i % 4, i));
+            fs::create_dir_all(git_dir.join("objects/ab")).unwrap();
+            fs::write(git_dir.join("HEAD"), "ref: refs/heads/main\n").unwrap();
+        }
+        fs::create_dir_all(temp_dir.path().join("bare.git/objects")).unwrap();
+        fs::write(
+            temp_dir.path().join("bare.git/HEAD"),
+            "ref: refs/heads/main\n",
+        )
+        .unwrap();
+
+        let sequential = discover_repositories(temp_dir.path());
+        assert_eq!(sequential.len(), 21);
+        for jobs in [2, 4, 8] {
+            let parallel = discover_repositories_parallel(temp_dir.path(), jobs, |_| {});
+            assert_eq!(parallel, sequential);
+        }
+    }
+}
@@ -0,0 +1,144 @@
+//! 実際に解放されたディスク容量の集計
+//!
+//! 置換したファイルのinodeは、最後のリンクが消えたときに初めて解放される。
+//! 置換前のリンク数を記録し、同じinodeへの置換をすべて数え終えた時点で
+//! そのinodeのブロック使用量 (`st_blocks`) を解放量に加える。
+
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+#[cfg(unix)]
+use std::os::unix::fs::MetadataExt;
+
+/// `st_blocks` の単位 (バイト)
+const BLOCK_SIZE: u64 = 512;
+
+/// 置換前のファイルのinodeとディスク使用量
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InodeUsage {
+    pub device: u64,
+    pub inode: u64,
+    /// 置換前のリンク数
+    pub nlink: u64,
+    /// ディスク上の使用量 (バイト、ブロック単位)
+    pub bytes: u64,
+}
+
+impl InodeUsage {
+    /// ファイルのinodeとディスク使用量を取得する (置換の直前に呼ぶ)
+    #[cfg(unix)]
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(InodeUsage {
+            device: metadata.dev(),
+            inode: metadata.ino(),
+            nlink: metadata.nlink(),
+            bytes: metadata.blocks() * BLOCK_SIZE,
+        })
+    }
+
+    #[cfg(not(unix))]
+    pub fn of(path: &Path) -> io::Result<Self> {
+        let metadata = fs::metadata(path)?;
+        Ok(InodeUsage {
+            device: 0,
+            inode: 0,
+            nlink: 1,
+            bytes: metadata.len(),
+        })
+    }
+}
+
+/// 置換によって解放された容量
+#[derive(Debug, Default)]
+pub struct FreedSpace {
+    /// まだリンクが残っているinodeの (残りリンク数, 使用量)
+    pending: HashMap<(u64, u64), (u64, u64)>,
+    freed: u64,
+}
+
+impl FreedSpace {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// 置換に成功したファイルの置換前の状態を記録する
+    ///
+    /// Args:
+    ///     usage: 置換直前に取得したtargetのinode情報
+    pub fn record_unlinked(&mut self, usage: InodeUsage) {
+        let key = (usage.device, usage.inode);
+        let entry = self
+            .pending
+            .entry(key)
+            .or_insert((usage.nlink, usage.bytes));
+        entry.0 = entry.0.saturating_sub(1);
+        if entry.0 == 0 {
+            self.freed += entry.1;
+            self.pending.remove(&key);
+        }
+    }
+
+    /// 解放された容量 (バイト)
+    pub fn freed(&self) -> u64 {
+        self.freed
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hardlink::{replace_with_hardlink, ReplaceResult};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_freed_only_when_last_link_removed() {
+        let temp_dir = TempDir::new().unwrap();
+        let source = temp_dir.path().join("source");
+        let target1 = temp_dir.path().join("target1");
+        let target2 = temp_dir.path().join("target2");
+        fs::write(&source, vec![1u8; 10000]).unwrap();
+        fs::write(&target1, vec![1u8; 10000]).unwrap();
+        fs::hard_link(&target1, &target2).unwrap();
+
+        let mut freed = FreedSpace::new();
+        let usage = InodeUsage::of(&target1).unwrap();
+        assert_eq!(usage.nlink, 2);
+        assert!(usage.bytes >= 10000);
+        assert_eq!(
+            replace_with_hardlink(&source, &target1),
+            ReplaceResult::Replaced
+        );
+        freed.record_unlinked(usage);
+        // target2がまだ同じinodeを参照している
+        assert_eq!(freed.freed(), 0);
+
+        let usage2 = InodeUsage::of(&target2).unwrap();
+        assert_eq!(
+            replace_with_hardlink(&source, &target2),
+            ReplaceResult::Replaced
+        );
+        freed.record_unlinked(usage2);
+        assert_eq!(freed.freed(), usage.bytes);
+    }
+
+    #[test]
+    fn test_freed_single_link() {
+        let mut freed = FreedSpace::new();
+        freed.record_unlinked(InodeUsage {
+            device: 1,
+            inode: 2,
+            nlink: 1,
+            bytes: 4096,
+        });
+        freed.record_unlinked(InodeUsage {
+            device: 1,
+            inode: 3,
+            nlink: 1,
+            bytes: 8192,
+        });
+        assert_eq!(freed.freed(), 4096 + 8192);
+    }
+}
@@ -1,16 +1,104 @@
 //! Gitオブジェクトファイルの探索
 
-use std::collections::HashMap;
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashMap};
 use std::fs;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use std::thread;
 use std::time::SystemTime;
-use walkdir::WalkDir;
 
 #[cfg(unix)]
 use std::os::unix::fs::MetadataExt;
 
-const PROGRESS_INTERVAL: usize = 1000;
+use crate::cache::{to_timestamp, ScanCache};
+use crate::hardlink::link_count;
+use crate::repository::{discover_repositories_with_progress, GitRepository};
+
+pub(crate) const PROGRESS_INTERVAL: usize = 1000;
+
+/// リポジトリのオブジェクトフォーマット (`extensions.objectformat`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum ObjectFormat {
+    /// SHA-1 (40桁の16進ハッシュ、既定)
+    #[default]
+    Sha1,
+    /// SHA-256 (64桁の16進ハッシュ)
+    Sha256,
+}
+
+impl ObjectFormat {
+    /// ハッシュの16進桁数を返す
+    pub fn hex_len(self) -> usize {
+        match self {
+            ObjectFormat::Sha1 => 40,
+            ObjectFormat::Sha256 => 64,
+        }
+    }
+
+    /// 設定値の名前を返す
+    pub fn as_str(self) -> &'static str {
+        match self {
+            ObjectFormat::Sha1 => "sha1",
+            ObjectFormat::Sha256 => "sha256",
+        }
+    }
+
+    /// `extensions.objectformat` の値から変換する
+    pub fn from_config_value(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "sha1" => Some(ObjectFormat::Sha1),
+            "sha256" => Some(ObjectFormat::Sha256),
+            _ => None,
+        }
+    }
+
+    /// fan-outディレクトリ以下のファイル名の長さからフォーマットを推定する
+    pub(crate) fn from_file_name_len(len: usize) -> Option<Self> {
+        [ObjectFormat::Sha1, ObjectFormat::Sha256]
+            .into_iter()
+            .find(|f| f.hex_len() - 2 == len)
+    }
+}
+
+/// gitディレクトリの `config` からオブジェクトフォーマットを読み取る
+///
+/// `extensions.objectformat` が無い、または読めない場合はSHA-1とみなす。
+///
+/// Args:
+///     git_dir: `.git` ディレクトリ (またはbareリポジトリ) のパス
+///
+/// Returns:
+///     オブジェクトフォーマット
+pub fn read_object_format(git_dir: &Path) -> ObjectFormat {
+    let Ok(config) = fs::read_to_string(git_dir.join("config")) else {
+        return ObjectFormat::default();
+    };
+
+    let mut in_extensions = false;
+    for line in config.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') {
+            let section = line.trim_start_matches('[').trim_end_matches(']').trim();
+            in_extensions = section.eq_ignore_ascii_case("extensions");
+            continue;
+        }
+        if !in_extensions {
+            continue;
+        }
+        if let Some((key, value)) = line.split_once('=') {
+            if key.trim().eq_ignore_ascii_case("objectformat") {
+                if let Some(format) = ObjectFormat::from_config_value(value) {
+                    return format;
+                }
+            }
+        }
+    }
+    ObjectFormat::default()
+}
 
 /// Gitオブジェクトファイルの情報
 #[derive(Debug, Clone)]
@@ -19,6 +107,8 @@ pub struct GitObjectInfo {
     pub path: PathBuf,
     /// ハッシュ値 (ディレクトリ名 + ファイル名)
     pub hash: String,
+    /// オブジェクトフォーマット (SHA-1 / SHA-256)
+    pub format: ObjectFormat,
     /// ファイルの作成時刻
     pub created: SystemTime,
     /// ファイルサイズ (バイト)
@@ -32,18 +122,35 @@ pub struct GitObjectInfo {
 impl GitObjectInfo {
     /// パスからGitObjectInfoを作成する
     ///
+    /// フォーマットはファイル名の長さ (SHA-1: 38文字、SHA-256: 62文字) から推定する。
+    ///
     /// Args:
     ///     path: オブジェクトファイルのパス
     ///
     /// Returns:
     ///     成功時はSome(GitObjectInfo)、失敗時はNone
     pub fn from_path(path: &Path) -> Option<Self> {
+        let file_name = path.file_name()?.to_str()?;
+        let format = ObjectFormat::from_file_name_len(file_name.len())?;
+        Self::from_path_with_format(path, format)
+    }
+
+    /// 指定フォーマットのオブジェクトとしてパスからGitObjectInfoを作成する
+    ///
+    /// Args:
+    ///     path: オブジェクトファイルのパス
+    ///     format: リポジトリのオブジェクトフォーマット
+    ///
+    /// Returns:
+    ///     ファイル名がフォーマットに合致すればSome(GitObjectInfo)、それ以外はNone
+    pub fn from_path_with_format(path: &Path, format: ObjectFormat) -> Option<Self> {
         let file_name = path.file_name()?.to_str()?;
         let parent = path.parent()?;
         let dir_name = parent.file_name()?.to_str()?;
 
-        // ハッシュは2文字のディレクトリ名 + 38文字のファイル名 = 40文字
-        if dir_name.len() != 2 || file_name.len() != 38 {
+        // ハッシュは2文字のディレクトリ名 + 残りのファイル名
+        // (SHA-1: 2 + 38 = 40文字、SHA-256: 2 + 62 = 64文字)
+        if dir_name.len() != 2 || file_name.len() != format.hex_len() - 2 {
             return None;
         }
 
@@ -68,6 +175,7 @@ impl GitObjectInfo {
         Some(GitObjectInfo {
             path: path.to_path_buf(),
             hash,
+            format,
             created,
             size,
             inode,
@@ -76,7 +184,7 @@ impl GitObjectInfo {
     }
 }
 
-/// 指定ディレクトリ以下の全ての.git/objectsを探索する
+/// 指定ディレクトリ以下の全てのGitリポジトリのオブジェクトを探索する
 ///
 /// Args:
 ///     base_path: 探索開始ディレクトリ
@@ -87,68 +195,41 @@ pub fn scan_git_objects(base_path: &Path) -> Vec<GitObjectInfo> {
     scan_git_objects_with_progress(base_path, |_| {})
 }
 
-/// 指定ディレクトリ以下の全ての.git/objectsを探索する（進捗通知付き）
-pub fn scan_git_objects_with_progress<F>(base_path: &Path, mut on_progress: F) -> Vec<GitObjectInfo>
+/// 指定ディレクトリ以下の全てのGitリポジトリのオブジェクトを探索する（進捗通知付き）
+///
+/// bareリポジトリやworktreeも含め、実体のオブジェクトディレクトリごとに1回だけ探索する。
+pub fn scan_git_objects_with_progress<F>(base_path: &Path, on_progress: F) -> Vec<GitObjectInfo>
 where
-    F: FnMut(&Path),
+    F: Fn(&Path) + Sync,
 {
-    let mut objects = Vec::new();
-    let mut scanned_entries = 0usize;
-
-    // base_path以下の全ての.gitディレクトリを探索
-    for entry in WalkDir::new(base_path)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        scanned_entries += 1;
-        let path = entry.path();
-        if scanned_entries.is_multiple_of(PROGRESS_INTERVAL) {
-            on_progress(path);
-        }
-
-        // .git/objectsディレクトリを発見したら、その中を探索
-        if path.ends_with(".git/objects") && path.is_dir() {
-            objects.extend(scan_objects_dir(path));
-        }
-    }
+    discover_repositories_with_progress(base_path, on_progress)
+        .iter()
+        .flat_map(scan_repository_objects)
+        .collect()
+}
 
-    objects
+/// 単一リポジトリのオブジェクトディレクトリを探索する
+pub fn scan_repository_objects(repo: &GitRepository) -> Vec<GitObjectInfo> {
+    scan_objects_dir(&repo.objects_dir, repo.format)
 }
 
-/// 指定ディレクトリ以下のGitリポジトリルートを列挙する
+/// 指定ディレクトリ以下のGitリポジトリのパスを列挙する
 ///
-/// `.git/objects` が存在するディレクトリをGitリポジトリとして扱い、
-/// リポジトリルート（`.git` の親ディレクトリ）を重複なく返す。
+/// 作業ツリーを持つリポジトリはそのルート、bareリポジトリはgitディレクトリを
+/// 重複なく返す。
 pub fn find_git_repositories(base_path: &Path) -> Vec<PathBuf> {
     find_git_repositories_with_progress(base_path, |_| {})
 }
 
-/// 指定ディレクトリ以下のGitリポジトリルートを列挙する（進捗通知付き）
-pub fn find_git_repositories_with_progress<F>(base_path: &Path, mut on_progress: F) -> Vec<PathBuf>
+/// 指定ディレクトリ以下のGitリポジトリのパスを列挙する（進捗通知付き）
+pub fn find_git_repositories_with_progress<F>(base_path: &Path, on_progress: F) -> Vec<PathBuf>
 where
-    F: FnMut(&Path),
+    F: Fn(&Path) + Sync,
 {
-    let mut repos = HashSet::new();
-    let mut scanned_entries = 0usize;
-
-    for entry in WalkDir::new(base_path).into_iter().filter_map(|e| e.ok()) {
-        scanned_entries += 1;
-        let path = entry.path();
-        if scanned_entries.is_multiple_of(PROGRESS_INTERVAL) {
-            on_progress(path);
-        }
-        if path.ends_with(".git/objects") && path.is_dir() {
-            if let Some(git_dir) = path.parent() {
-                if let Some(repo_root) = git_dir.parent() {
-                    repos.insert(repo_root.to_path_buf());
-                }
-            }
-        }
-    }
-
-    let mut repo_list: Vec<_> = repos.into_iter().collect();
-    repo_list.sort();
-    repo_list
+    discover_repositories_with_progress(base_path, on_progress)
+        .into_iter()
+        .map(|repo| repo.path)
+        .collect()
 }
 
 /// 重複ファイルのグループ
@@ -182,6 +263,8 @@ pub fn group_by_device(objects: Vec<GitObjectInfo>) -> HashMap<u64, Vec<GitObjec
 ///
 /// 既存のハードリンクグループがある場合は、そのグループを優先してsourceとする。
 /// これにより、後から古いファイルが追加されても既存のハードリンクを壊さない。
+/// ハッシュはオブジェクトフォーマットと組で比較するため、SHA-1とSHA-256の
+/// オブジェクトが同じグループになることはない。
 ///
 /// Args:
 ///     objects: 探索で発見したオブジェクト情報のリスト
@@ -189,26 +272,35 @@ pub fn group_by_device(objects: Vec<GitObjectInfo>) -> HashMap<u64, Vec<GitObjec
 /// Returns:
 ///     2つ以上のファイルが存在し、かつ未リンクファイルがあるグループのみ返す
 pub fn find_duplicates(objects: Vec<GitObjectInfo>) -> Vec<DuplicateGroup> {
-    // ハッシュ値でグループ化
-    let mut groups: HashMap<String, Vec<GitObjectInfo>> = HashMap::new();
-    for obj in objects {
-        groups.entry(obj.hash.clone()).or_default().push(obj);
-    }
-
-    // 2つ以上のファイルがあるグループを処理
-    groups
-        .into_values()
+    // 2つ以上のファイルがあるグループを処理 (並列探索でも結果が揃うようハッシュ順に並べる)
+    group_by_hash(objects)
+        .into_iter()
         .filter(|v| v.len() >= 2)
         .filter_map(select_source_and_duplicates)
         .collect()
 }
 
+/// オブジェクトファイルを (フォーマット, ハッシュ値) でグループ化する
+///
+/// Returns:
+///     ハッシュ順に並べたグループ (1ファイルだけのグループも含む)
+pub fn group_by_hash(objects: Vec<GitObjectInfo>) -> Vec<Vec<GitObjectInfo>> {
+    let mut groups: BTreeMap<(ObjectFormat, String), Vec<GitObjectInfo>> = BTreeMap::new();
+    for obj in objects {
+        groups
+            .entry((obj.format, obj.hash.clone()))
+            .or_default()
+            .push(obj);
+    }
+    groups.into_values().collect()
+}
+
 /// グループ内からsourceと未リンクのduplicatesを選定する
 ///
 /// 1. 同一inode (同一デバイス上) のファイルをサブグループ化
 /// 2. 最大のサブグループ (最も多くリンクされている) のファイルをsource候補
 /// 3. source候補の中から1つを選び、他のサブグループのファイルをduplicatesに
-fn select_source_and_duplicates(files: Vec<GitObjectInfo>) -> Option<DuplicateGroup> {
+pub fn select_source_and_duplicates(files: Vec<GitObjectInfo>) -> Option<DuplicateGroup> {
     // (device, inode) でサブグループ化
     let mut inode_groups: HashMap<(u64, u64), Vec<GitObjectInfo>> = HashMap::new();
     for file in files {
@@ -218,25 +310,27 @@ fn select_source_and_duplicates(files: Vec<GitObjectInfo>) -> Option<DuplicateGr
             .push(file);
     }
 
-    // 最大のサブグループを見つける (同数なら最初に見つかったもの)
-    let (source_key, _) = inode_groups
-        .iter()
-        .max_by_key(|(_, group)| group.len())?;
+    // 各サブグループを (更新時刻, パス) 順に並べておく
+    for group in inode_groups.values_mut() {
+        group.sort_by(|a, b| a.created.cmp(&b.created).then_with(|| a.path.cmp(&b.path)));
+    }
 
-    let source_key = *source_key;
+    // 最大のサブグループを見つける (同数なら最古のファイルを含むもの)
+    let source_key = inode_groups
+        .iter()
+        .max_by(|(_, a), (_, b)| {
+            a.len()
+                .cmp(&b.len())
+                .then_with(|| (b[0].created, &b[0].path).cmp(&(a[0].created, &a[0].path)))
+        })
+        .map(|(key, _)| *key)?;
 
     // sourceグループから1つを選ぶ (最古のもの)
-    let mut source_candidates: Vec<_> = inode_groups
-        .remove(&source_key)
-        .unwrap_or_default();
-    source_candidates.sort_by_key(|f| f.created);
-    let source = source_candidates.into_iter().next()?;
+    let source = inode_groups.remove(&source_key)?.into_iter().next()?;
 
     // 他のサブグループのファイルをduplicatesとして収集
-    let duplicates: Vec<_> = inode_groups
-        .into_values()
-        .flatten()
-        .collect();
+    let mut duplicates: Vec<_> = inode_groups.into_values().flatten().collect();
+    duplicates.sort_by(|a, b| a.path.cmp(&b.path));
 
     // 置換対象がなければNone
     if duplicates.is_empty() {
@@ -246,42 +340,200 @@ fn select_source_and_duplicates(files: Vec<GitObjectInfo>) -> Option<DuplicateGr
     Some(DuplicateGroup { source, duplicates })
 }
 
+/// ハードリンク数の上限を超えないよう重複グループを分割する
+///
+/// sourceの現在のリンク数に置換する数を足して上限に達したら、次のduplicateを
+/// 置換せずに新しいsourceとし、残りをそちらにリンクする。これにより、何千もの
+/// リポジトリにある空のtreeなどでも、上限ごとに1つのinodeで共有できる。
+///
+/// Args:
+///     group: 重複グループ
+///     max_links: 1つのinodeに作れるリンク数の上限
+///
+/// Returns:
+///     分割後のグループ (duplicatesが空のグループは含まない)
+pub fn split_by_link_limit(group: DuplicateGroup, max_links: u64) -> Vec<DuplicateGroup> {
+    let nlink = |object: &GitObjectInfo| link_count(&object.path).unwrap_or(1);
+    let mut groups = Vec::new();
+    let mut source = group.source;
+    let mut links = nlink(&source);
+    let mut duplicates = Vec::new();
+    for dup in group.duplicates {
+        if links >= max_links {
+            groups.push(DuplicateGroup {
+                source,
+                duplicates: std::mem::take(&mut duplicates),
+            });
+            links = nlink(&dup);
+            source = dup;
+            continue;
+        }
+        links += 1;
+        duplicates.push(dup);
+    }
+    groups.push(DuplicateGroup { source, duplicates });
+    groups.retain(|g| !g.duplicates.is_empty());
+    groups
+}
+
 /// .git/objectsディレクトリ内のオブジェクトファイルを探索する
 ///
+/// フォーマットに合わない長さのファイル名は無視する。
+///
 /// Args:
 ///     objects_dir: .git/objectsディレクトリのパス
+///     format: リポジトリのオブジェクトフォーマット
 ///
 /// Returns:
 ///     発見したGitオブジェクト情報のベクタ
-fn scan_objects_dir(objects_dir: &Path) -> Vec<GitObjectInfo> {
-    let mut objects = Vec::new();
+fn scan_objects_dir(objects_dir: &Path, format: ObjectFormat) -> Vec<GitObjectInfo> {
+    scan_objects_dir_cached(objects_dir, format, &mut ScanCache::disabled())
+}
 
-    for entry in WalkDir::new(objects_dir)
-        .min_depth(2)
-        .max_depth(2)
-        .into_iter()
-        .filter_map(|e| e.ok())
-    {
-        let path = entry.path();
-
-        // pack, infoディレクトリは除外
-        if let Some(parent) = path.parent() {
-            if let Some(parent_name) = parent.file_name() {
-                let name = parent_name.to_string_lossy();
-                if name == "pack" || name == "info" {
-                    continue;
+/// キャッシュを使って.git/objectsディレクトリ内のオブジェクトファイルを探索する
+///
+/// fan-outディレクトリ (`xx`) のmtimeがキャッシュと一致すれば中身を読まずに
+/// キャッシュの内容を使い、一致しなければ読み直してキャッシュを更新する。
+/// pack, infoなど2桁の16進でないディレクトリは対象外。
+///
+/// Args:
+///     objects_dir: .git/objectsディレクトリのパス
+///     format: リポジトリのオブジェクトフォーマット
+///     cache: 探索キャッシュ
+///
+/// Returns:
+///     発見したGitオブジェクト情報のベクタ (パス順)
+pub fn scan_objects_dir_cached(
+    objects_dir: &Path,
+    format: ObjectFormat,
+    cache: &mut ScanCache,
+) -> Vec<GitObjectInfo> {
+    let cache = Mutex::new(cache);
+    list_fanout_dirs(objects_dir)
+        .iter()
+        .flat_map(|fanout| scan_fanout_dir_with(fanout, format, &cache))
+        .collect()
+}
+
+/// キャッシュを使って単一リポジトリのオブジェクトディレクトリを探索する
+pub fn scan_repository_objects_cached(
+    repo: &GitRepository,
+    cache: &mut ScanCache,
+) -> Vec<GitObjectInfo> {
+    scan_objects_dir_cached(&repo.objects_dir, repo.format, cache)
+}
+
+/// 複数リポジトリのオブジェクトディレクトリを複数スレッドで探索する
+///
+/// fan-outディレクトリ単位で作業を分け、`jobs` 個のスレッドが順に取り出して処理する。
+/// 結果はリポジトリ順・fan-out順に連結するため、スレッド数によらず同じ並びになる。
+///
+/// Args:
+///     repos: 探索するリポジトリ
+///     jobs: スレッド数 (0は1とみなす)
+///     cache: 探索キャッシュ
+///
+/// Returns:
+///     発見したオブジェクトのリスト
+pub fn scan_repositories_parallel(
+    repos: &[GitRepository],
+    jobs: usize,
+    cache: &mut ScanCache,
+) -> Vec<GitObjectInfo> {
+    let work: Vec<(PathBuf, ObjectFormat)> = repos
+        .iter()
+        .flat_map(|repo| {
+            list_fanout_dirs(&repo.objects_dir)
+                .into_iter()
+                .map(|d| (d, repo.format))
+        })
+        .collect();
+    let results: Vec<Mutex<Vec<GitObjectInfo>>> =
+        work.iter().map(|_| Mutex::new(Vec::new())).collect();
+    let next = AtomicUsize::new(0);
+    let cache = Mutex::new(cache);
+
+    thread::scope(|scope| {
+        for _ in 0..jobs.max(1).min(work.len().max(1)) {
+            scope.spawn(|| loop {
+                let i = next.fetch_add(1, Ordering::Relaxed);
+                let Some((fanout, format)) = work.get(i) else {
+                    break;
+                };
+                let found = scan_fanout_dir_with(fanout, *format, &cache);
+                if let Ok(mut slot) = results[i].lock() {
+                    *slot = found;
                 }
-            }
+            });
         }
+    });
 
-        if path.is_file() {
-            if let Some(info) = GitObjectInfo::from_path(path) {
-                objects.push(info);
-            }
-        }
+    results
+        .into_iter()
+        .flat_map(|slot| slot.into_inner().unwrap_or_default())
+        .collect()
+}
+
+/// キャッシュを共有しながらfan-outディレクトリを1つ探索する
+fn scan_fanout_dir_with(
+    fanout: &Path,
+    format: ObjectFormat,
+    cache: &Mutex<&mut ScanCache>,
+) -> Vec<GitObjectInfo> {
+    let Some(modified) = fs::metadata(fanout).ok().and_then(|m| m.modified().ok()) else {
+        return Vec::new();
+    };
+    let modified = to_timestamp(modified);
+    if let Some(cached) = cache
+        .lock()
+        .ok()
+        .and_then(|c| c.lookup(fanout, format, modified))
+    {
+        return cached;
+    }
+
+    // 読み込みと stat はロックの外で行う
+    let found = scan_fanout_dir(fanout, format);
+    if let Ok(mut cache) = cache.lock() {
+        cache.store(fanout, format, modified, &found);
     }
+    found
+}
 
-    objects
+/// オブジェクトディレクトリ直下のfan-outディレクトリ (2桁の16進) をパス順に列挙する
+fn list_fanout_dirs(objects_dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(objects_dir) else {
+        return Vec::new();
+    };
+    let mut dirs: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| {
+            let name = e.file_name();
+            let name = name.to_string_lossy();
+            name.len() == 2 && name.chars().all(|c| c.is_ascii_hexdigit())
+        })
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_dir()))
+        .map(|e| e.path())
+        .collect();
+    dirs.sort();
+    dirs
+}
+
+/// fan-outディレクトリ内のオブジェクトファイルをパス順に探索する
+fn scan_fanout_dir(fanout: &Path, format: ObjectFormat) -> Vec<GitObjectInfo> {
+    let Ok(entries) = fs::read_dir(fanout) else {
+        return Vec::new();
+    };
+    let mut paths: Vec<_> = entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .map(|e| e.path())
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .filter_map(|path| GitObjectInfo::from_path_with_format(path, format))
+        .collect()
 }
 
 #[cfg(test)]
@@ -357,6 +609,100 @@ mod tests {
         assert!(info.is_none());
     }
 
+    #[test]
+    fn test_git_object_info_from_path_sha256() {
+        let temp_dir = TempDir::new().unwrap();
+        let obj_dir = temp_dir.path().join("ab");
+        fs::create_dir_all(&obj_dir).unwrap();
+        let name = "cd".repeat(31);
+        let obj_path = obj_dir.join(&name);
+        File::create(&obj_path).unwrap();
+
+        let info = GitObjectInfo::from_path(&obj_path).unwrap();
+        assert_eq!(info.format, ObjectFormat::Sha256);
+        assert_eq!(info.hash, format!("ab{}", name));
+
+        // SHA-1として解釈するとファイル名の長さが合わない
+        assert!(GitObjectInfo::from_path_with_format(&obj_path, ObjectFormat::Sha1).is_none());
+    }
+
+    #[test]
+    fn test_read_object_format() {
+        let temp_dir = TempDir::new().unwrap();
+        let git_dir = temp_dir.path();
+
+        // configが無ければSHA-1
+        assert_eq!(read_object_format(git_dir), ObjectFormat::Sha1);
+
+        fs::write(
+            git_dir.join("config"),
+            "[core]\n\trepositoryformatversion = 1\n[extensions]\n\tobjectFormat = sha256\n",
+        )
+        .unwrap();
+        assert_eq!(read_object_format(git_dir), ObjectFormat::Sha256);
+
+        // 別セクションの同名キーは無視する
+        fs::write(git_dir.join("config"), "[core]\n\tobjectformat = sha256\n").unwrap();
+        assert_eq!(read_object_format(git_dir), ObjectFormat::Sha1);
+    }
+
+    #[test]
+    fn test_scan_git_objects_sha256_repo() {
+        let temp_dir = TempDir::new().unwrap();
+        let git_dir = temp_dir.path().join("repo/.git");
+        fs::create_dir_all(git_dir.join("objects/ab")).unwrap();
+        fs::write(
+            git_dir.join("config"),
+            "[extensions]\n\tobjectformat = sha256\n",
+        )
+        .unwrap();
+
+        // SHA-256リポジトリ内の38文字ファイル名は無視される
+        File::create(git_dir.join("objects/ab").join("cd".repeat(31))).unwrap();
+        File::create(git_dir.join("objects/ab/cdef1234567890abcdef1234567890abcdef12")).unwrap();
+
+        let objects = scan_git_objects(temp_dir.path());
+
+        assert_eq!(objects.len(), 1);
+        assert_eq!(objects[0].format, ObjectFormat::Sha256);
+        assert_eq!(objects[0].hash.len(), 64);
+    }
+
+    #[test]
+    fn test_scan_git_objects_sha1_repo_ignores_sha256_names() {
+        let temp_dir = TempDir::new().unwrap();
+        let obj_dir = temp_dir.path().join("repo/.git/objects/ab");
+        fs::create_dir_all(&obj_dir).unwrap();
+        File::create(obj_dir.join("cd".repeat(31))).unwrap();
+
+        let objects = scan_git_objects(temp_dir.path());
+        assert!(objects.is_empty());
+    }
+
+    #[test]
+    fn test_find_duplicates_does_not_mix_formats() {
+        let temp_dir = TempDir::new().unwrap();
+        let sha1_name = "cdef1234567890abcdef1234567890abcdef12";
+        let sha256_name = format!("{}{}", sha1_name, "0".repeat(24));
+
+        let sha1_dir = temp_dir.path().join("sha1/.git/objects/ab");
+        fs::create_dir_all(&sha1_dir).unwrap();
+        File::create(sha1_dir.join(sha1_name)).unwrap();
+
+        let sha256_git = temp_dir.path().join("sha256/.git");
+        fs::create_dir_all(sha256_git.join("objects/ab")).unwrap();
+        fs::write(
+            sha256_git.join("config"),
+            "[extensions]\n\tobjectformat = sha256\n",
+        )
+        .unwrap();
+        File::create(sha256_git.join("objects/ab").join(&sha256_name)).unwrap();
+
+        let objects = scan_git_objects(temp_dir.path());
+        assert_eq!(objects.len(), 2);
+        assert!(find_duplicates(objects).is_empty());
+    }
+
     #[test]
     fn test_scan_git_objects() {
         let temp_dir = TempDir::new().unwrap();
@@ -696,4 +1042,125 @@ mod tests {
         assert_eq!(objects.len(), 1);
         assert!(calls.load(Ordering::Relaxed) >= 1);
     }
+
+    #[test]
+    fn test_scan_objects_dir_cached_reuses_unchanged_fanout() {
+        let temp_dir = TempDir::new().unwrap();
+        let git_objects = create_test_git_structure(temp_dir.path());
+        let fanout = git_objects.join("ab");
+        let obj = fanout.join("cdef1234567890abcdef1234567890abcdef12");
+
+        // 探索直後の更新とみなされないよう、fan-outのmtimeを過去にする
+        let old = SystemTime::now() - std::time::Duration::from_secs(60);
+        File::options()
+            .write(true)
+            .open(&obj)
+            .unwrap()
+            .set_modified(old)
+            .unwrap();
+        File::open(&fanout).unwrap().set_modified(old).unwrap();
+
+        let cache_file = temp_dir.path().join("scan.cache");
+        let mut cache = ScanCache::open(Some(cache_file.clone()));
+        let first = scan_objects_dir_cached(&git_objects, ObjectFormat::Sha1, &mut cache);
+        assert_eq!(first.len(), 1);
+        cache.save().unwrap();
+
+        // キャッシュ済みのfan-outは読み直さないので、追加ファイルは見えない
+        File::create(fanout.join("ef12345678901234567890123456789012abcd")).unwrap();
+        File::open(&fanout).unwrap().set_modified(old).unwrap();
+        let mut cache = ScanCache::open(Some(cache_file.clone()));
+        let cached = scan_objects_dir_cached(&git_objects, ObjectFormat::Sha1, &mut cache);
+        assert_eq!(cached.len(), 1);
+        assert_eq!(cached[0].inode, first[0].inode);
+
+        // mtimeが変われば読み直す
+        File::open(&fanout)
+            .unwrap()
+            .set_modified(old + std::time::Duration::from_secs(1))
+            .unwrap();
+        let rescanned = scan_objects_dir_cached(&git_objects, ObjectFormat::Sha1, &mut cache);
+        assert_eq!(rescanned.len(), 2);
+    }
+
+    #[test]
+    fn test_scan_repositories_parallel_is_deterministic() {
+        let temp_dir = TempDir::new().unwrap();
+        for repo in ["repo1", "repo2", "repo3"] {
+            for dir in ["ab", "cd", "ef"] {
+                let obj_dir = temp_dir.path().join(repo).join(".git/objects").join(dir);
+                fs::create_dir_all(&obj_dir).unwrap();
+                File::create(obj_dir.join("cdef1234567890abcdef1234567890abcdef12")).unwrap();
+            }
+        }
+        let repos = discover_repositories_with_progress(temp_dir.path(), |_| {});
+
+        let sequential: Vec<_> = repos.iter().flat_map(scan_repository_objects).collect();
+        assert_eq!(sequential.len(), 9);
+        for jobs in [1, 3, 8] {
+            let parallel = scan_repositories_parallel(&repos, jobs, &mut ScanCache::disabled());
+            let paths: Vec<_> = parallel.iter().map(|o| &o.path).collect();
+            assert_eq!(
+                paths,
+                sequential.iter().map(|o| &o.path).collect::<Vec<_>>()
+            );
+
+            let groups = find_duplicates(parallel);
+            assert_eq!(groups.len(), 3);
+            assert!(groups
+                .windows(2)
+                .all(|w| w[0].source.hash < w[1].source.hash));
+            for group in &groups {
+                let mut sorted: Vec<_> = group.duplicates.iter().map(|d| &d.path).collect();
+                sorted.sort();
+                assert_eq!(
+                    group.duplicates.iter().map(|d| &d.path).collect::<Vec<_>>(),
+                    sorted
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn test_split_by_link_limit() {
+        let temp_dir = TempDir::new().unwrap();
+        let paths: Vec<_> = (0..7)
+            .map(|i| {
+                let obj_dir = temp_dir
+                    .path()
+                    .join(format!("repo{}", i))
+                    .join(".git/objects/ab");
+                fs::create_dir_all(&obj_dir).unwrap();
+                let path = obj_dir.join("cdef1234567890abcdef1234567890abcdef12");
+                File::create(&path).unwrap();
+                path
+            })
+            .collect();
+        let load = || {
+            paths
+                .iter()
+                .map(|p| GitObjectInfo::from_path(p).unwrap())
+                .collect::<Vec<_>>()
+        };
+
+        let group = select_source_and_duplicates(load()).unwrap();
+        let source = group.source.path.clone();
+        // sourceは既に別の場所からもリンクされている (リンク数2)
+        fs::hard_link(&source, temp_dir.path().join("extra")).unwrap();
+        let groups = split_by_link_limit(group, 3);
+
+        // 2+1=3 で上限に達し、次のduplicateを新しいsourceとして2件ずつリンクする
+        let sizes: Vec<_> = groups.iter().map(|g| g.duplicates.len()).collect();
+        assert_eq!(sizes, vec![1, 2, 1]);
+        assert_eq!(groups[0].source.path, source);
+        for group in &groups {
+            assert!(!group.duplicates.iter().any(|d| d.path == group.source.path));
+        }
+        let total: usize = groups.iter().map(|g| g.duplicates.len() + 1).sum();
+        assert_eq!(total, 7);
+
+        let groups = split_by_link_limit(select_source_and_duplicates(load()).unwrap(), u64::MAX);
+        assert_eq!(groups.len(), 1);
+        assert_eq!(groups[0].duplicates.len(), 6);
+    }
 }
@@ -0,0 +1,139 @@
+//! SIGINT/SIGTERMの処理
+//!
+//! 最初のシグナルではフラグを立てるだけにし、処理中の置換を終えてから
+//! 新しい置換を始めずに途中までの集計を表示して終了する。
+//! 2回目のシグナルでは既定の動作 (即時終了) に戻す。
+
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// シグナルで中断したときの終了コード (128 + SIGINT)
+pub const EXIT_INTERRUPTED: i32 = 130;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(test)]
+thread_local! {
+    /// シグナルの代わりに中断を判定する関数 (テストで差し込む)
+    static INTERRUPT_CHECK: std::cell::RefCell<Option<Box<dyn Fn() -> bool>>> = const { std::cell::RefCell::new(None) };
+}
+
+#[cfg(unix)]
+extern "C" fn handle_signal(signum: libc::c_int) {
+    if INTERRUPTED.swap(true, Ordering::SeqCst) {
+        // signal/raiseはasync-signal-safe
+        unsafe {
+            libc::signal(signum, libc::SIG_DFL);
+            libc::raise(signum);
+        }
+    }
+}
+
+/// SIGINT/SIGTERMのハンドラを設定する
+#[cfg(unix)]
+pub fn install() -> io::Result<()> {
+    for signum in [libc::SIGINT, libc::SIGTERM] {
+        let rc = unsafe {
+            let mut action: libc::sigaction = std::mem::zeroed();
+            action.sa_sigaction = handle_signal as *const () as libc::sighandler_t;
+            action.sa_flags = libc::SA_RESTART;
+            libc::sigemptyset(&mut action.sa_mask);
+            libc::sigaction(signum, &action, std::ptr::null_mut())
+        };
+        if rc != 0 {
+            return Err(io::Error::last_os_error());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+pub fn install() -> io::Result<()> {
+    Ok(())
+}
+
+/// SIGINT/SIGTERMを受け取ったか
+pub fn interrupted() -> bool {
+    #[cfg(test)]
+    if INTERRUPT_CHECK.with(|check| check.borrow().as_ref().is_some_and(|check| check())) {
+        return true;
+    }
+    INTERRUPTED.load(Ordering::SeqCst)
+}
+
+/// 現在のスレッドの中断の判定関数を差し込む (テスト用)
+///
+/// プロセス全体のフラグを立てずに、並行して動く他のテストに影響を与えず
+/// 任意の時点で中断させるために使う。返り値をdropすると元に戻る。
+#[cfg(test)]
+pub fn inject_interrupt_check(check: impl Fn() -> bool + 'static) -> InterruptCheckGuard {
+    INTERRUPT_CHECK.with(|slot| *slot.borrow_mut() = Some(Box::new(check)));
+    InterruptCheckGuard
+}
+
+/// `inject_interrupt_check` で差し込んだ判定関数を、drop時に取り除く
+#[cfg(test)]
+pub struct InterruptCheckGuard;
+
+#[cfg(test)]
+impl Drop for InterruptCheckGuard {
+    fn drop(&mut self) {
+        INTERRUPT_CHECK.with(|slot| *slot.borrow_mut() = None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 子プロセスとして実行されていることを示す環境変数
+    const CHILD_ENV: &str = "GIT_SHARE_OBJ_SIGNAL_TEST_CHILD";
+
+    #[test]
+    #[cfg(unix)]
+    fn test_first_signal_only_sets_flag() {
+        // フラグはプロセス全体に残るので、このテストだけを子プロセスで実行する
+        if std::env::var_os(CHILD_ENV).is_none() {
+            let output = std::process::Command::new(std::env::current_exe().unwrap())
+                .args([
+                    "--exact",
+                    "signal::tests::test_first_signal_only_sets_flag",
+                    "--test-threads=1",
+                ])
+                .env(CHILD_ENV, "1")
+                .output()
+                .unwrap();
+            assert!(
+                output.status.success(),
+                "{}",
+                String::from_utf8_lossy(&output.stdout)
+            );
+            return;
+        }
+        assert!(!interrupted());
+        install().unwrap();
+        assert_eq!(unsafe { libc::raise(libc::SIGTERM) }, 0);
+        // プロセスは終了せず、フラグだけが立つ
+        assert!(interrupted());
+    }
+
+    #[test]
+    fn test_injected_check() {
+        let count = std::rc::Rc::new(std::cell::Cell::new(0));
+        let guard = {
+            let count = count.clone();
+            inject_interrupt_check(move || {
+                count.set(count.get() + 1);
+                count.get() > 2
+            })
+        };
+        assert!(!interrupted());
+        assert!(!interrupted());
+        assert!(interrupted());
+        drop(guard);
+        assert!(!interrupted());
+        // 他のスレッドには影響しない
+        let _guard = inject_interrupt_check(|| true);
+        assert!(!std::thread::spawn(interrupted).join().unwrap());
+    }
+}